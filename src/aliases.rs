@@ -0,0 +1,118 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::config::strip_comments;
+use crate::nodes::parse_node_id;
+
+/// A node's entry in the aliases file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AliasEntry {
+    name: String,
+    irc: Option<String>, // The IRC nick of the node's owner, for mentions
+}
+
+/// What a node is called, from the aliases file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    pub name: String,
+    pub irc: Option<String>,
+}
+
+/// Names for mesh nodes from the aliases file, which win over the names the
+/// nodes announce, and the IRC nicks they're linked to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aliases {
+    aliases: HashMap<u32, Alias>,
+}
+
+impl Aliases {
+    /// Read the aliases file, a JSON object from node IDs like "!a1b2c3d4" to
+    /// {"name": ..., "irc": ...}. No file means no aliases.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Can't read aliases file {}: {}", path.display(), e))?;
+        let entries: BTreeMap<String, AliasEntry> = serde_json::from_str(&strip_comments(&text))
+            .map_err(|e| anyhow::anyhow!("Could not parse aliases file {}: {}", path.display(), e))?;
+        let mut aliases = HashMap::new();
+        for (node, entry) in entries {
+            let num = parse_node_id(&node).ok_or_else(|| {
+                anyhow::anyhow!("Invalid node ID \"{}\" in aliases file {}, expected e.g. \"!a1b2c3d4\"", node, path.display())
+            })?;
+            if entry.name.trim().is_empty() {
+                return Err(anyhow::anyhow!("Node {} in aliases file {} has an empty name", node, path.display()));
+            }
+            if entry.irc.as_deref().is_some_and(|irc| irc.is_empty() || irc.contains(char::is_whitespace)) {
+                return Err(anyhow::anyhow!("Node {} in aliases file {} has an invalid IRC nick", node, path.display()));
+            }
+            aliases.insert(num, Alias { name: entry.name, irc: entry.irc });
+        }
+        Ok(Self { aliases })
+    }
+
+    pub fn get(&self, node: u32) -> Option<&Alias> {
+        self.aliases.get(&node)
+    }
+
+    /// Look up a node by its alias or linked IRC nick
+    pub fn resolve(&self, name: &str) -> Option<u32> {
+        self.aliases.iter()
+            .find(|(_, alias)| alias.name.eq_ignore_ascii_case(name)
+                || alias.irc.as_deref().is_some_and(|irc| irc.eq_ignore_ascii_case(name)))
+            .map(|(num, _)| *num)
+    }
+
+    /// Turn @mentions of aliased nodes in mesh text into their IRC nicks, so
+    /// IRC clients highlight them
+    pub fn mentions_to_irc(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for alias in self.aliases.values() {
+            if let Some(irc) = &alias.irc {
+                text = replace_mention(&text, &alias.name, irc);
+            }
+        }
+        text
+    }
+
+    /// Turn @mentions of linked IRC nicks in IRC text into the nodes' aliases
+    pub fn mentions_to_mesh(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for alias in self.aliases.values() {
+            if let Some(irc) = &alias.irc {
+                text = replace_mention(&text, irc, &alias.name);
+            }
+        }
+        text
+    }
+}
+
+/// Replace "@from" with "@to", ignoring case, where it isn't part of a longer
+/// word
+fn replace_mention(text: &str, from: &str, to: &str) -> String {
+    let mention = format!("@{}", from.to_lowercase());
+    let lowered = text.to_lowercase();
+    // Lowercasing can change byte lengths, which would throw the offsets off
+    if lowered.len() != text.len() {
+        return text.to_string();
+    }
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = 0;
+    for (start, _) in lowered.match_indices(&mention) {
+        let end = start + mention.len();
+        let ends_word = lowered[end..].chars().next().is_none_or(|c| !c.is_alphanumeric() && c != '_');
+        if start < rest || !ends_word {
+            continue;
+        }
+        replaced.push_str(&text[rest..start]);
+        replaced.push('@');
+        replaced.push_str(to);
+        rest = end;
+    }
+    replaced.push_str(&text[rest..]);
+    replaced
+}
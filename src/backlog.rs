@@ -0,0 +1,171 @@
+use anyhow::Result;
+use log::{debug, error, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::BufferConfig;
+use crate::disk_queue::DiskQueue;
+use crate::irc_handler::{IrcMessage, MeshMessage};
+
+// Defaults for how much is held for a leg that is down
+const DEFAULT_MAX_MESSAGES: usize = 50;
+const DEFAULT_MAX_AGE_SECS: u64 = 600;
+
+/// A message that can wait in a backlog for the leg it's going to
+pub trait Delayable: Serialize + DeserializeOwned {
+    /// Whether it still makes sense to deliver late
+    fn replayable(&self) -> bool {
+        true
+    }
+
+    /// Put the delayed marker in front of the text
+    fn mark_delayed(&mut self, marker: &str);
+}
+
+impl Delayable for MeshMessage {
+    fn mark_delayed(&mut self, marker: &str) {
+        if !self.content.starts_with(marker) {
+            self.content = format!("{} {}", marker, self.content);
+        }
+    }
+}
+
+impl Delayable for IrcMessage {
+    // A bridge command answered late would be confusing, or worse for !admin
+    fn replayable(&self) -> bool {
+        self.command.is_none()
+    }
+
+    fn mark_delayed(&mut self, marker: &str) {
+        if !self.content.starts_with(marker) {
+            self.content = format!("{} {}", marker, self.content);
+        }
+    }
+}
+
+/// Messages held while the leg they're for is down, oldest first. When full
+/// the oldest is dropped, and messages older than the maximum age aren't
+/// replayed. With a path they're kept on disk, so what a restart or crash
+/// interrupted is replayed when the bridge is back.
+#[derive(Debug)]
+pub struct Backlog<T> {
+    messages: VecDeque<(u64, T)>,  // With the time they were held since, in seconds after the epoch
+    disk: Option<DiskQueue>,
+    path: Option<PathBuf>,
+    max_messages: usize,
+    max_age_secs: u64,
+    marker: Option<String>,
+}
+
+impl<T: Delayable> Backlog<T> {
+    /// A backlog for `leg`, which names its messages in a shared file
+    pub fn new(config: &BufferConfig, leg: &str) -> Result<Self> {
+        let disk = config.path.as_deref().map(|path| DiskQueue::open(path, leg)).transpose()?;
+        let mut backlog = Self {
+            messages: VecDeque::new(),
+            disk,
+            path: config.path.clone(),
+            max_messages: 0,
+            max_age_secs: 0,
+            marker: None,
+        };
+        backlog.configure(config);
+        Ok(backlog)
+    }
+
+    /// Take on reloaded limits, keeping what is held as far as they allow
+    pub fn configure(&mut self, config: &BufferConfig) {
+        if config.path != self.path {
+            warn!("buffer.path applies once the bridge is restarted");
+        }
+        self.max_messages = config.max_messages.unwrap_or(DEFAULT_MAX_MESSAGES);
+        self.max_age_secs = config.max_age_secs.unwrap_or(DEFAULT_MAX_AGE_SECS);
+        self.marker = config.delayed_marker.clone();
+        while self.messages.len() > self.max_messages {
+            self.messages.pop_front();
+        }
+        self.trim_disk();
+    }
+
+    /// Hold a message until the leg is back
+    pub fn push(&mut self, message: T) {
+        if !message.replayable() || self.max_messages == 0 {
+            debug!("Not holding a message for a leg that is down");
+            return;
+        }
+        let now = now_secs();
+        if let Some(disk) = &self.disk {
+            let stored = serde_json::to_string(&message).map_err(anyhow::Error::from)
+                .and_then(|json| disk.push(now, &json));
+            match stored {
+                Ok(()) => {
+                    self.trim_disk();
+                    return;
+                }
+                // Kept in memory instead, which is better than losing it
+                Err(e) => error!("Failed to store a held message on disk: {}", e),
+            }
+        }
+        if self.messages.len() >= self.max_messages {
+            warn!("Backlog is full ({} messages), dropping the oldest", self.max_messages);
+            self.messages.pop_front();
+        }
+        self.messages.push_back((now, message));
+    }
+
+    /// The held messages still young enough to deliver, oldest first and
+    /// marked as delayed
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut held = Vec::new();
+        if let Some(disk) = &self.disk {
+            match disk.take() {
+                Ok(stored) => {
+                    for (held_since, json) in stored {
+                        match serde_json::from_str(&json) {
+                            Ok(message) => held.push((held_since, message)),
+                            Err(e) => warn!("Dropping an unreadable held message: {}", e),
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to read held messages from disk: {}", e),
+            }
+        }
+        held.extend(self.messages.drain(..));
+
+        let now = now_secs();
+        let mut expired = 0;
+        let mut messages = Vec::new();
+        for (held_since, mut message) in held {
+            if now.saturating_sub(held_since) > self.max_age_secs {
+                expired += 1;
+                continue;
+            }
+            if let Some(marker) = &self.marker {
+                message.mark_delayed(marker);
+            }
+            messages.push(message);
+        }
+        if expired > 0 {
+            warn!("Dropped {} held messages older than {}s", expired, self.max_age_secs);
+        }
+        messages
+    }
+
+    fn trim_disk(&self) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        match disk.trim(self.max_messages) {
+            Ok(0) => {}
+            Ok(dropped) => warn!("Backlog is full ({} messages), dropped the oldest {}", self.max_messages, dropped),
+            Err(e) => error!("Failed to trim held messages on disk: {}", e),
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use crate::config::ReconnectConfig;
+
+// Defaults for the waits between reconnection attempts
+const DEFAULT_INITIAL_DELAY_SECS: u64 = 1;
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+const DEFAULT_MAX_DELAY_SECS: u64 = 60;
+
+/// The waits between attempts to reconnect to IRC, a radio or an MQTT
+/// broker: the initial delay, growing by the multiplier up to the maximum,
+/// for at most max_attempts attempts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial_delay: Duration,
+    multiplier: f64,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+    delay: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(config: &ReconnectConfig) -> Self {
+        let initial_delay = Duration::from_secs(config.initial_delay_secs.unwrap_or(DEFAULT_INITIAL_DELAY_SECS));
+        Self {
+            initial_delay,
+            multiplier: config.multiplier.unwrap_or(DEFAULT_MULTIPLIER),
+            max_delay: Duration::from_secs(config.max_delay_secs.unwrap_or(DEFAULT_MAX_DELAY_SECS)),
+            max_attempts: config.max_attempts,
+            delay: initial_delay,
+            attempts: 0,
+        }
+    }
+
+    /// How long to wait before the next attempt, or None once every attempt
+    /// has been made
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max_attempts| self.attempts >= max_attempts) {
+            return None;
+        }
+        self.attempts += 1;
+        let delay = self.delay.min(self.max_delay);
+        self.delay = self.delay.mul_f64(self.multiplier).min(self.max_delay);
+        Some(delay)
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Start again from the initial delay, after connecting
+    pub fn reset(&mut self) {
+        self.delay = self.initial_delay;
+        self.attempts = 0;
+    }
+}
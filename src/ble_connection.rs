@@ -0,0 +1,235 @@
+use anyhow::Result;
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, ScanFilter,
+    WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures_util::StreamExt;
+use log::{debug, error, info};
+use meshtastic::api::StreamHandle;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use uuid::Uuid;
+
+use crate::config::BleConfig;
+
+// Meshtastic BLE GATT service and characteristic UUIDs
+const MESHTASTIC_SERVICE: Uuid = Uuid::from_u128(0x6ba1b218_15a8_461f_9fa8_5dcae273eafd);
+const TORADIO: Uuid = Uuid::from_u128(0xf75c76d2_129e_4dad_a1dd_7866124401e7);
+const FROMRADIO: Uuid = Uuid::from_u128(0x2c55e69e_4993_11ed_b878_0242ac120002);
+const FROMNUM: Uuid = Uuid::from_u128(0xed9da18c_a800_4f66_a670_aa7547e34453);
+
+const DEFAULT_SCAN_TIMEOUT_SECS: u64 = 10;
+
+// The stream API speaks the serial framing: 0x94 0xc3 followed by a big-endian length
+const FRAME_MAGIC: [u8; 2] = [0x94, 0xc3];
+
+struct Characteristics {
+    to_radio: Characteristic,
+    from_radio: Characteristic,
+    from_num: Characteristic,
+}
+
+/// Connect to a Meshtastic node over BLE and expose it as a stream that
+/// `StreamApi::connect` understands. A background task translates between
+/// the framed stream and the GATT characteristics.
+pub async fn build_ble_stream(config: &BleConfig) -> Result<StreamHandle<DuplexStream>> {
+    let scan_timeout = Duration::from_secs(config.scan_timeout_secs.unwrap_or(DEFAULT_SCAN_TIMEOUT_SECS));
+    let (radio, adapter) = find_radio(&config.device, scan_timeout).await?;
+
+    info!("Connecting to Meshtastic BLE device {}", config.device);
+    radio.connect().await
+        .map_err(|e| ble_error(&config.device, "Failed to connect", e))?;
+    radio.discover_services().await
+        .map_err(|e| ble_error(&config.device, "Failed to discover services", e))?;
+
+    let characteristics = find_characteristics(&radio)?;
+
+    // Subscribing to FROMNUM is the first operation that needs an encrypted
+    // link, so this is where an unpaired device is rejected
+    radio.subscribe(&characteristics.from_num).await
+        .map_err(|e| ble_error(&config.device, "Failed to subscribe to notifications", e))?;
+
+    info!("Connected to Meshtastic BLE device {}", config.device);
+
+    let (client, server) = tokio::io::duplex(4096);
+    let device = config.device.clone();
+    tokio::spawn(async move {
+        if let Err(e) = pump(radio, adapter, characteristics, server).await {
+            error!("BLE connection to {} lost: {}", device, e);
+        }
+    });
+
+    Ok(StreamHandle::from_stream(client))
+}
+
+async fn find_radio(device: &str, scan_timeout: Duration) -> Result<(Peripheral, Adapter)> {
+    let address = BDAddr::from_str(device).ok();
+    let manager = Manager::new().await
+        .map_err(|e| anyhow::anyhow!("Failed to initialize Bluetooth: {}", e))?;
+    let adapters = manager.adapters().await
+        .map_err(|e| anyhow::anyhow!("Failed to list Bluetooth adapters: {}", e))?;
+
+    if adapters.is_empty() {
+        return Err(anyhow::anyhow!("No Bluetooth adapters found"));
+    }
+
+    for adapter in adapters {
+        info!("Scanning for Meshtastic BLE device {} ({}s)", device, scan_timeout.as_secs());
+        if let Err(e) = adapter.start_scan(ScanFilter { services: vec![MESHTASTIC_SERVICE] }).await {
+            error!("Failed to scan for BLE devices: {}", e);
+            continue;
+        }
+        tokio::time::sleep(scan_timeout).await;
+        let _ = adapter.stop_scan().await;
+
+        for peripheral in adapter.peripherals().await.unwrap_or_default() {
+            if let Ok(Some(properties)) = peripheral.properties().await {
+                let matches = match address {
+                    Some(address) => properties.address == address,
+                    None => properties.local_name.as_deref() == Some(device),
+                };
+                if matches {
+                    debug!("Found BLE device {} at {}", device, properties.address);
+                    return Ok((peripheral, adapter));
+                }
+            }
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "Meshtastic BLE device {} not found. Please check:\n\
+        - Bluetooth is enabled on the device\n\
+        - The device name or MAC address is correct\n\
+        - No phone or other client is currently connected to it",
+        device
+    ))
+}
+
+fn find_characteristics(radio: &Peripheral) -> Result<Characteristics> {
+    let characteristics = radio.characteristics();
+    let find = |uuid: Uuid| {
+        characteristics.iter()
+            .find(|c| c.uuid == uuid)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Device is missing Meshtastic characteristic {}", uuid))
+    };
+
+    Ok(Characteristics {
+        to_radio: find(TORADIO)?,
+        from_radio: find(FROMRADIO)?,
+        from_num: find(FROMNUM)?,
+    })
+}
+
+/// Turn a btleplug error into a user-facing one, explaining how to pair when
+/// the device rejects us for lack of authentication. Pairing itself is left to
+/// the operating system since btleplug has no API for entering a PIN.
+fn ble_error(device: &str, context: &str, e: btleplug::Error) -> anyhow::Error {
+    let message = e.to_string().to_lowercase();
+    if message.contains("auth") || message.contains("not permitted") || message.contains("encrypt") {
+        anyhow::anyhow!(
+            "{} to {}: {}\n\
+            The device requires pairing. Pair it once using your system's Bluetooth tools\n\
+            (e.g. `bluetoothctl pair <MAC>` on Linux) with the PIN shown on the device screen\n\
+            (123456 if the device has no screen and uses a fixed PIN), then restart the bridge.",
+            context, device, e
+        )
+    } else {
+        anyhow::anyhow!("{} to {}: {}", context, device, e)
+    }
+}
+
+async fn pump(
+    radio: Peripheral,
+    adapter: Adapter,
+    characteristics: Characteristics,
+    mut server: DuplexStream,
+) -> Result<()> {
+    let mut notifications = radio.notifications().await?;
+    let mut adapter_events = adapter.events().await?;
+    let radio_id = radio.id();
+    let mut buf = [0u8; 1024];
+    let mut pending = Vec::new();
+
+    // Drain whatever the device queued before we subscribed
+    drain_from_radio(&radio, &characteristics.from_radio, &mut server).await?;
+
+    loop {
+        tokio::select! {
+            notification = notifications.next() => {
+                match notification {
+                    Some(notification) if notification.uuid == FROMNUM => {
+                        drain_from_radio(&radio, &characteristics.from_radio, &mut server).await?;
+                    }
+                    Some(_) => {}
+                    None => return Err(anyhow::anyhow!("notification stream ended")),
+                }
+            }
+            event = adapter_events.next() => {
+                if let Some(CentralEvent::DeviceDisconnected(id)) = event {
+                    if id == radio_id {
+                        return Err(anyhow::anyhow!("device disconnected"));
+                    }
+                }
+            }
+            read = server.read(&mut buf) => {
+                let len = read?;
+                if len == 0 {
+                    debug!("BLE stream closed, disconnecting");
+                    let _ = radio.disconnect().await;
+                    return Ok(());
+                }
+                pending.extend_from_slice(&buf[..len]);
+                while let Some(packet) = take_frame(&mut pending) {
+                    radio.write(&characteristics.to_radio, &packet, WriteType::WithResponse).await?;
+                }
+            }
+        }
+    }
+}
+
+async fn drain_from_radio(
+    radio: &Peripheral,
+    from_radio: &Characteristic,
+    server: &mut DuplexStream,
+) -> Result<()> {
+    loop {
+        let data = radio.read(from_radio).await?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let len = (data.len() as u16).to_be_bytes();
+        server.write_all(&[FRAME_MAGIC[0], FRAME_MAGIC[1], len[0], len[1]]).await?;
+        server.write_all(&data).await?;
+    }
+}
+
+/// Pop one complete framed packet off the front of `pending`, returning its
+/// payload without the header
+fn take_frame(pending: &mut Vec<u8>) -> Option<Vec<u8>> {
+    // Resynchronise on the magic bytes if we ever get out of step
+    match pending.windows(2).position(|w| w == FRAME_MAGIC) {
+        Some(start) => {
+            pending.drain(..start);
+        }
+        None => {
+            // Keep a trailing first magic byte, its partner may be in the next read
+            let keep = usize::from(pending.last() == Some(&FRAME_MAGIC[0]));
+            pending.drain(..pending.len() - keep);
+            return None;
+        }
+    }
+
+    if pending.len() < 4 {
+        return None;
+    }
+    let len = u16::from_be_bytes([pending[2], pending[3]]) as usize;
+    if pending.len() < 4 + len {
+        return None;
+    }
+
+    let frame: Vec<u8> = pending.drain(..4 + len).collect();
+    Some(frame[4..].to_vec())
+}
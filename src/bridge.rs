@@ -1,98 +1,428 @@
 use anyhow::Result;
-use log::{error, info};
-use tokio::sync::mpsc;
+use futures_util::future::join_all;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
 
-use crate::config::Config;
-use crate::irc_handler::{IrcHandler, IrcMessage};
-use crate::meshtastic_handler::MeshtasticHandler;
-use crate::mqtt_handler::MqttHandler;
+use crate::config::{Config, MeshtasticConfig};
+use crate::dedup::{PacketDedup, DEDUP_WINDOW};
+use crate::filter::Filter;
+use crate::links::{Heard, Links};
+use crate::logging;
+use crate::endpoint::{IrcLeg, MeshLeg, MeshState};
+use crate::irc_handler::{IrcEndpoint, IrcMessage, MeshCommand, MeshMessage, IRC_LEG};
+use crate::meshtastic_handler::{radio_leg, RadioEndpoint, DEFAULT_HISTORY_WINDOW_MINUTES};
+use crate::metrics::Metrics;
+use crate::node_db::NodeDb;
+use crate::mqtt_handler::{mqtt_leg, FeedEntry, MqttEndpoint, Uplink};
+use crate::presence::PresenceTracker;
+use crate::supervisor::{supervise, Handoff};
+
+// Time the radios get to disconnect cleanly on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Nodes not heard from for this long are announced as offline
+const DEFAULT_OFFLINE_AFTER_MINUTES: u64 = 60;
+
+/// Reads the config again, for reloads on SIGHUP
+pub type ConfigLoader = Box<dyn Fn() -> Result<Config> + Send>;
 
 pub struct Bridge {
     config: Config,
+    reload: ConfigLoader,
+    metrics: Metrics,
 }
 
 impl Bridge {
-    pub fn new(config: Config) -> Self {
-        Self { config }
+    pub fn new(config: Config, reload: ConfigLoader) -> Self {
+        Self {
+            config,
+            reload,
+            metrics: Metrics::new(),
+        }
     }
 
     pub async fn run(self) -> Result<()> {
         info!("Starting bridge...");
 
-        // Create message channels
-        let (irc_to_mesh_tx, irc_to_mesh_rx) = mpsc::channel::<IrcMessage>(100);
-        let (mesh_to_irc_tx, mesh_to_irc_rx) = mpsc::channel::<String>(100);
+        // Create message channels. What each endpoint hears goes to the
+        // router, which passes it on to the endpoints linked to it
+        let links = Links::new(&self.config)?;
+        let (heard_tx, heard_rx) = mpsc::channel::<(String, Heard)>(100);
+        let (mesh_to_irc_tx, mut mesh_to_irc_rx) = mpsc::channel::<MeshMessage>(100);
+        let mut targets = HashMap::new();
+        targets.insert(IRC_LEG.to_string(), Target::Irc(mesh_to_irc_tx));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        // Every handler watches the config and applies what a reload changed
+        let (config_tx, config_rx) = watch::channel(self.config.clone());
+        tokio::spawn(reload_on_hangup(self.reload, config_tx));
+        
+        // With a feed topic, traffic both ways is also copied to MQTT
+        let main_irc_channel = self.config.irc.channel.clone();
+        let feed = self.config.meshtastic.all_brokers().iter()
+            .any(|mqtt| mqtt.enabled.unwrap_or(true) && mqtt.feed_topic.is_some());
+        let (feed_tx, mut feed_rx) = if feed {
+            let (feed_tx, feed_rx) = mpsc::channel::<FeedEntry>(100);
+            (Some(feed_tx), Some(feed_rx))
+        } else {
+            (None, None)
+        };
 
         // Start both handlers in parallel
         let irc_config = self.config.irc.clone();
         let meshtastic_config = self.config.meshtastic.clone();
+        let irc_metrics = self.metrics.clone();
+        let irc_reconnect = meshtastic_config.reconnect.clone();
+        let irc_reload = config_rx.clone();
+        let irc_to_mesh_tx = heard_by::<IrcMessage>(IRC_LEG, &heard_tx);
 
-        // Spawn IRC handler initialization
+        // Spawn IRC handler initialization, restarted if it fails
         let irc_handle = tokio::spawn(async move {
-            info!("Initializing IRC connection...");
-            match IrcHandler::new(&irc_config).await {
-                Ok(handler) => {
-                    info!("IRC handler initialized successfully");
-                    info!("Starting IRC message handler loop");
-                    if let Err(e) = handler.run(mesh_to_irc_rx, irc_to_mesh_tx).await {
-                        error!("IRC handler error: {}", e);
-                    }
+            if !irc_config.enabled.unwrap_or(true) {
+                info!("IRC is disabled, messages from the mesh are dropped");
+                while let Some(message) = mesh_to_irc_rx.recv().await {
+                    debug!("Not relaying to IRC: {}", message.content);
                 }
+                // Stays up while the mesh side does
+                std::future::pending::<()>().await;
+            }
+            let from_mesh = match Handoff::buffered(mesh_to_irc_rx, &irc_config.buffer, IRC_LEG) {
+                Ok(from_mesh) => from_mesh,
                 Err(e) => {
-                    error!("Failed to initialize IRC handler: {}", e);
+                    error!("Failed to set up the IRC backlog: {}", e);
+                    return;
                 }
-            }
+            };
+            let mut irc: Box<IrcLeg> = Box::new(IrcEndpoint::new(irc_reload, irc_metrics.clone()));
+            supervise(irc.as_mut(), &irc_reconnect, &from_mesh, &irc_to_mesh_tx, &irc_metrics).await;
         });
 
-        // Spawn Meshtastic handler initialization (either serial or MQTT)
-        let mesh_handle = if let Some(mqtt_config) = &meshtastic_config.mqtt {
-            let mqtt_config = mqtt_config.clone();
-            let channel = meshtastic_config.channel;
-            tokio::spawn(async move {
-                info!("Initializing MQTT connection...");
-                match MqttHandler::new(&mqtt_config, channel).await {
-                    Ok(handler) => {
-                        info!("MQTT handler initialized successfully");
-                        info!("Starting MQTT message handler loop");
-                        if let Err(e) = handler.run(irc_to_mesh_rx, mesh_to_irc_tx).await {
-                            error!("MQTT handler error: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to initialize MQTT handler: {}", e);
-                    }
-                }
-            })
+        // Spawn Meshtastic handler initialization: one handler per radio, plus
+        // one for MQTT if configured
+        let devices = meshtastic_config.all_devices();
+        // Messages replayed by a Store & Forward router may be older than the usual window
+        let history_window = Duration::from_secs(
+            60 * u64::from(meshtastic_config.store_forward.window_minutes.unwrap_or(DEFAULT_HISTORY_WINDOW_MINUTES))
+        );
+        let offline_after = Duration::from_secs(
+            60 * meshtastic_config.presence.offline_after_minutes.unwrap_or(DEFAULT_OFFLINE_AFTER_MINUTES)
+        );
+        // Shared by all handlers, so a packet heard over RF and via MQTT is relayed once
+        let state = MeshState {
+            dedup: Arc::new(Mutex::new(PacketDedup::new(DEDUP_WINDOW.max(history_window)))),
+            presence: Arc::new(Mutex::new(PresenceTracker::new(offline_after))),
+            node_db: Arc::new(Mutex::new(NodeDb::new())),
+            metrics: self.metrics.clone(),
+        };
+        
+        // In gateway mode the radios pass what they hear on to MQTT
+        let uplink = meshtastic_config.all_brokers().iter()
+            .any(|mqtt| mqtt.enabled.unwrap_or(true) && mqtt.uplink.unwrap_or(false));
+        let (uplink_tx, mut uplink_rx) = if uplink {
+            let (uplink_tx, uplink_rx) = mpsc::channel::<Uplink>(100);
+            (Some(uplink_tx), Some(uplink_rx))
         } else {
-            tokio::spawn(async move {
-                info!("Initializing Meshtastic serial connection...");
-                match MeshtasticHandler::new(&meshtastic_config).await {
-                    Ok(handler) => {
-                        info!("Meshtastic handler initialized successfully");
-                        info!("Starting Meshtastic message handler loop");
-                        if let Err(e) = handler.run(irc_to_mesh_rx, mesh_to_irc_tx).await {
-                            error!("Meshtastic handler error: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to initialize Meshtastic handler: {}", e);
-                    }
-                }
-            })
+            (None, None)
         };
+        
+        let mut mesh_handles = Vec::new();
+        // Closed once every radio handler has exited, which on shutdown means
+        // they have disconnected from their devices
+        let (radios_tx, mut radios_done) = mpsc::channel::<()>(1);
+        for (device_index, device) in devices.into_iter().enumerate() {
+            // Skipped rather than removed, so indexes still match the config
+            if !device.enabled.unwrap_or(true) {
+                info!("Radio {} is disabled", device.display_name());
+                continue;
+            }
+            let leg = radio_leg(&device);
+            let (device_tx, device_rx) = mpsc::channel::<IrcMessage>(100);
+            targets.insert(leg.clone(), Target::Mesh(device_tx));
+            let from_irc = Handoff::buffered(device_rx, &meshtastic_config.buffer, &leg)?;
+            let radio = RadioEndpoint::new(&device, device_index, state.clone(), uplink_tx.clone(), shutdown_rx.clone(), config_rx.clone());
+            let heard = heard_by(&leg, &heard_tx);
+            mesh_handles.push(spawn_mesh_leg(Box::new(radio), from_irc, &meshtastic_config, heard, &self.metrics, Some(radios_tx.clone())));
+        }
+        drop(radios_tx);
+        
+        // One handler per broker; the shared dedup relays a packet heard
+        // through several of them once
+        for (broker_index, mqtt_config) in meshtastic_config.all_brokers().into_iter().enumerate() {
+            if !mqtt_config.enabled.unwrap_or(true) {
+                info!("MQTT broker {} is disabled", mqtt_config.broker_address);
+                continue;
+            }
+            let leg = mqtt_leg(mqtt_config);
+            let (mqtt_tx, mqtt_rx) = mpsc::channel::<IrcMessage>(100);
+            targets.insert(leg.clone(), Target::Mesh(mqtt_tx));
+            let from_irc = Handoff::buffered(mqtt_rx, &meshtastic_config.buffer, &leg)?;
+            // The feed and uplink go to the first broker that has them
+            let feed = if mqtt_config.feed_topic.is_some() { feed_rx.take().map(Handoff::new) } else { None };
+            let uplink = if mqtt_config.uplink.unwrap_or(false) { uplink_rx.take().map(Handoff::new) } else { None };
+            let broker = MqttEndpoint::new(mqtt_config, broker_index, state.clone(), feed, uplink, config_rx.clone());
+            let heard = heard_by(&leg, &heard_tx);
+            mesh_handles.push(spawn_mesh_leg(Box::new(broker), from_irc, &meshtastic_config, heard, &self.metrics, None));
+        }
+        
+        tokio::spawn(route(links, heard_rx, targets, feed_tx, main_irc_channel, self.metrics.clone(), state.node_db.clone()));
+        
+        // The mesh side is only considered down once every handler has exited
+        let mesh_handle = tokio::spawn(async move {
+            join_all(mesh_handles).await;
+        });
 
         info!("Bridge is running! Waiting for both connections to establish...");
 
-        // Wait for tasks to complete
+        // Failed handlers are restarted, so this only ends once IRC, or every
+        // radio and broker, has been given up on
+        let mut mesh_handle = mesh_handle;
         tokio::select! {
             _ = irc_handle => {
                 error!("IRC handler terminated");
             }
-            _ = mesh_handle => {
-                error!("Meshtastic handler terminated");
+            _ = &mut mesh_handle => {
+                error!("Every Meshtastic handler terminated");
+            }
+            _ = shutdown_signal() => {
+                info!("Shutting down bridge...");
+                // The radio handlers disconnect from their devices; MQTT just stops
+                let _ = shutdown_tx.send(true);
+                if tokio::time::timeout(SHUTDOWN_TIMEOUT, radios_done.recv()).await.is_err() {
+                    error!("Timed out disconnecting from the Meshtastic radios");
+                }
+                return Ok(());
             }
         }
 
         Err(anyhow::anyhow!("Bridge terminated unexpectedly"))
     }
+}
+
+/// Where the router delivers to: IRC, or a radio or MQTT broker
+enum Target {
+    Irc(mpsc::Sender<MeshMessage>),
+    Mesh(mpsc::Sender<IrcMessage>),
+}
+
+/// A sender for what `leg` hears, which hands it to the router tagged with
+/// the leg
+fn heard_by<T: Into<Heard> + Send + 'static>(leg: &str, heard: &mpsc::Sender<(String, Heard)>) -> mpsc::Sender<T> {
+    let (heard_tx, mut heard_rx) = mpsc::channel::<T>(100);
+    let (leg, heard) = (leg.to_string(), heard.clone());
+    tokio::spawn(async move {
+        while let Some(message) = heard_rx.recv().await {
+            if heard.send((leg.clone(), message.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+    heard_tx
+}
+
+/// Pass what each endpoint hears on to the endpoints linked to it, copying
+/// traffic to and from IRC to the feed if there is one
+async fn route(
+    links: Links,
+    mut heard: mpsc::Receiver<(String, Heard)>,
+    targets: HashMap<String, Target>,
+    feed_tx: Option<mpsc::Sender<FeedEntry>>,
+    main_irc_channel: String,
+    metrics: Metrics,
+    node_db: Arc<Mutex<NodeDb>>,
+) {
+    while let Some((from, message)) = heard.recv().await {
+        if let (Some(feed_tx), Heard::Irc(message)) = (&feed_tx, &message) {
+            if message.command.is_none() {
+                // A slow broker shouldn't hold up the bridge
+                let _ = feed_tx.try_send(FeedEntry::to_mesh(message, &main_irc_channel));
+            }
+        }
+        // Disabled endpoints aren't targets
+        let mut linked: Vec<_> = links.targets(&from, &message).into_iter()
+            .filter(|(to, _)| targets.contains_key(*to))
+            .collect();
+        if let Heard::Irc(message) = &message {
+            if message.command.is_some() || message.destination.is_some() {
+                let node = message.destination.as_deref().or_else(|| message.command.as_ref().and_then(MeshCommand::node));
+                linked = one_target(linked, node, &node_db);
+            }
+        }
+        for (to, filter) in linked {
+            let Some(target) = targets.get(to) else {
+                continue;
+            };
+            let sent = match (target, &message) {
+                (Target::Mesh(to_mesh), Heard::Irc(message)) => {
+                    let mut message = message.clone();
+                    // Bridge commands aren't filtered
+                    if message.command.is_none() {
+                        let Some(content) = pass(filter, &message.content, &metrics) else {
+                            continue;
+                        };
+                        message.content = content;
+                    }
+                    to_mesh.send(message).await.is_ok()
+                }
+                (Target::Mesh(to_mesh), Heard::Mesh(message)) => {
+                    // The bridge's notices and replies are only for IRC
+                    let Some(chat) = &message.chat else {
+                        continue;
+                    };
+                    let Some(content) = pass(filter, &chat.text, &metrics) else {
+                        continue;
+                    };
+                    // Sent on like a message from IRC, by whoever said it
+                    to_mesh.send(IrcMessage {
+                        sender: chat.sender.clone(),
+                        content,
+                        channel: message.channel.clone(),
+                        destination: None,
+                        mesh_channel: None,
+                        command: None,
+                    }).await.is_ok()
+                }
+                (Target::Irc(to_irc), Heard::Mesh(message)) => {
+                    let Some(content) = pass(filter, &message.content, &metrics) else {
+                        continue;
+                    };
+                    let message = MeshMessage { content, ..message.clone() };
+                    if let Some(feed_tx) = &feed_tx {
+                        let _ = feed_tx.try_send(FeedEntry::to_irc(&message, &main_irc_channel));
+                    }
+                    to_irc.send(message).await.is_ok()
+                }
+                // Links never join IRC to itself
+                (Target::Irc(_), Heard::Irc(_)) => continue,
+            };
+            if !sent {
+                error!("Failed to queue message for {}", to);
+            }
+        }
+    }
+}
+
+/// Where a direct message or bridge command goes, so it is sent or answered
+/// once rather than by every radio and broker: the endpoint its node was last
+/// heard on, or else the first one linked
+fn one_target<'a>(linked: Vec<(&'a str, &'a Filter)>, node: Option<&str>, node_db: &Mutex<NodeDb>) -> Vec<(&'a str, &'a Filter)> {
+    let heard_via = node.and_then(|node| {
+        let node_db = node_db.lock().unwrap();
+        node_db.resolve(node).and_then(|num| node_db.heard_via(num)).map(str::to_string)
+    });
+    let index = heard_via.and_then(|via| linked.iter().position(|(to, _)| *to == via)).unwrap_or(0);
+    linked.into_iter().nth(index).into_iter().collect()
+}
+
+/// The text as a link's filter lets it through, or None if it is dropped
+fn pass(filter: &Filter, text: &str, metrics: &Metrics) -> Option<String> {
+    let passed = filter.apply(text).map(|text| text.into_owned());
+    if passed.is_none() {
+        debug!("Link filter dropped: {}", text);
+        metrics.record_filtered();
+    }
+    passed
+}
+
+/// Run a radio or MQTT broker, restarted if it fails. A radio holds `done`
+/// until it has disconnected from its device.
+fn spawn_mesh_leg(
+    mut endpoint: Box<MeshLeg>,
+    from_irc: Handoff<IrcMessage>,
+    meshtastic_config: &MeshtasticConfig,
+    heard: mpsc::Sender<MeshMessage>,
+    metrics: &Metrics,
+    done: Option<mpsc::Sender<()>>,
+) -> JoinHandle<()> {
+    let reconnect = meshtastic_config.reconnect.clone();
+    let metrics = metrics.clone();
+    tokio::spawn(async move {
+        let _done = done;
+        supervise(endpoint.as_mut(), &reconnect, &from_irc, &heard, &metrics).await;
+    })
+}
+
+/// Read the config again on every SIGHUP and hand it to the handlers. A
+/// config that fails to load leaves the running one in place.
+#[cfg(unix)]
+async fn reload_on_hangup(reload: ConfigLoader, config_tx: watch::Sender<Config>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, config reloads are off: {}", e);
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the config");
+        let config = match reload() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Keeping the running config: {}", e);
+                continue;
+            }
+        };
+        let current = config_tx.borrow().clone();
+        // The aliases file is re-read even when the config is the same
+        if config == current && config.meshtastic.aliases_file.is_none() {
+            info!("Config unchanged");
+            continue;
+        }
+        if config.meshtastic.all_devices().len() != current.meshtastic.all_devices().len()
+            || config.meshtastic.all_brokers().len() != current.meshtastic.all_brokers().len() {
+            warn!("Radios and MQTT brokers are only added or removed when the bridge is restarted");
+        }
+        if enabled_legs(&config) != enabled_legs(&current) {
+            warn!("IRC, radios and MQTT brokers are only enabled or disabled when the bridge is restarted");
+        }
+        if config.links != current.links {
+            warn!("Links between endpoints only change when the bridge is restarted");
+        }
+        if config.logging != current.logging {
+            if let Err(e) = logging::apply(&config.logging) {
+                error!("Keeping the running logging settings: {}", e);
+            }
+        }
+        config_tx.send_replace(config);
+    }
+}
+
+/// Whether IRC, each radio and each broker is enabled, in config order
+#[cfg(unix)]
+fn enabled_legs(config: &Config) -> Vec<bool> {
+    let radios = config.meshtastic.all_devices().into_iter().map(|device| device.enabled.unwrap_or(true));
+    let brokers = config.meshtastic.all_brokers().into_iter().map(|mqtt| mqtt.enabled.unwrap_or(true));
+    std::iter::once(config.irc.enabled.unwrap_or(true)).chain(radios).chain(brokers).collect()
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_reload: ConfigLoader, config_tx: watch::Sender<Config>) {
+    // Kept open so the handlers don't see the config as gone
+    let _config_tx = config_tx;
+    std::future::pending::<()>().await;
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM (e.g. from systemd or `docker stop`)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use meshtastic::protobufs::config::{self, lo_ra_config::{ModemPreset, RegionCode}};
+use meshtastic::protobufs::{channel, from_radio, Channel, FromRadio};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::ChannelRef;
+
+// Meshtastic devices have a fixed number of channel slots
+const MAX_CHANNELS: u32 = 8;
+
+/// The device's channel table, collected from the Channel and LoRa config
+/// packets the radio sends while it is being configured
+#[derive(Debug, Default)]
+pub struct ChannelTable {
+    channels: BTreeMap<u32, Channel>,
+    disabled: BTreeSet<u32>,
+    modem_preset: Option<ModemPreset>,
+    region: Option<RegionCode>,
+}
+
+impl ChannelTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, from_radio: &FromRadio) {
+        match &from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::Channel(channel)) => self.add_channel(channel),
+            Some(from_radio::PayloadVariant::Config(config)) => {
+                if let Some(config::PayloadVariant::Lora(lora)) = &config.payload_variant {
+                    if lora.use_preset {
+                        self.modem_preset = Some(lora.modem_preset());
+                    }
+                    if lora.region() != RegionCode::Unset {
+                        self.region = Some(lora.region());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_channel(&mut self, channel: &Channel) {
+        let index = channel.index as u32;
+        if channel.role() == channel::Role::Disabled {
+            self.channels.remove(&index);
+            self.disabled.insert(index);
+            return;
+        }
+        self.disabled.remove(&index);
+        self.channels.insert(index, channel.clone());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// The modem preset, if the device uses one rather than custom LoRa settings
+    pub fn modem_preset(&self) -> Option<ModemPreset> {
+        self.modem_preset
+    }
+
+    /// The LoRa region the device is set to, if any
+    pub fn region(&self) -> Option<RegionCode> {
+        self.region
+    }
+
+    /// The name a channel is shown under in the Meshtastic apps. A primary
+    /// channel without a name is named after the modem preset, e.g. "LongFast".
+    pub fn name(&self, index: u32) -> Option<String> {
+        let name = self.channels.get(&index)?
+            .settings.as_ref()
+            .map(|settings| settings.name.as_str())
+            .unwrap_or_default();
+        if name.is_empty() {
+            let preset = self.modem_preset.unwrap_or(ModemPreset::LongFast);
+            Some(format!("{:?}", preset))
+        } else {
+            Some(name.to_string())
+        }
+    }
+
+    /// The channel as the device reported it
+    pub fn channel(&self, index: u32) -> Option<&Channel> {
+        self.channels.get(&index)
+    }
+
+    pub fn resolve(&self, channel: &ChannelRef) -> Result<u32> {
+        match channel {
+            ChannelRef::Index(index) if self.channels.contains_key(index) => Ok(*index),
+            ChannelRef::Index(index) if *index >= MAX_CHANNELS => Err(anyhow::anyhow!(
+                "Channel {} is out of range, devices have channels 0 to {}. Available channels: {}",
+                index, MAX_CHANNELS - 1, self.describe()
+            )),
+            ChannelRef::Index(index) => Err(anyhow::anyhow!(
+                "Channel {} is {} on the device, so nothing would be relayed. Available channels: {}",
+                index,
+                if self.disabled.contains(index) { "disabled" } else { "not configured" },
+                self.describe()
+            )),
+            ChannelRef::Name(wanted) => self.channels.keys()
+                .copied()
+                .find(|index| self.name(*index).is_some_and(|name| name.eq_ignore_ascii_case(wanted)))
+                .ok_or_else(|| anyhow::anyhow!(
+                    "Channel \"{}\" not found on the device. Available channels: {}",
+                    wanted, self.describe()
+                )),
+        }
+    }
+
+    /// e.g. "0: LongFast, 1: admin"
+    pub fn describe(&self) -> String {
+        if self.channels.is_empty() {
+            return "none".to_string();
+        }
+        self.channels.keys()
+            .filter_map(|index| self.name(*index).map(|name| format!("{}: {}", index, name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use tokio::net::lookup_host;
+
+use crate::config::Config;
+use crate::meshtastic_handler::DEFAULT_TCP_PORT;
+use crate::serial_detector;
+
+/// Tallies the outcome of each check as it is printed
+#[derive(Default)]
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn ok(&self, what: &str, detail: &str) {
+        println!("  ok    {}: {}", what, detail);
+    }
+
+    fn skip(&self, what: &str, detail: &str) {
+        println!("  skip  {}: {}", what, detail);
+    }
+
+    fn fail(&mut self, what: &str, detail: &str) {
+        println!("  FAIL  {}: {}", what, detail);
+        self.failed += 1;
+    }
+
+    /// Look up host:port in DNS, as the bridge will when it connects
+    async fn resolve(&mut self, what: &str, host: &str, port: u16) {
+        match lookup_host((host, port)).await {
+            Ok(addresses) => {
+                let addresses: Vec<String> = addresses.map(|address| address.ip().to_string()).collect();
+                self.ok(what, &format!("{}:{} resolves to {}", host, port, addresses.join(", ")));
+            }
+            Err(e) => self.fail(what, &format!("can't resolve {}: {}", host, e)),
+        }
+    }
+}
+
+/// Check a loaded config without bridging anything: validate it, find the
+/// radios' serial ports and resolve the servers' host names. Fails if any
+/// check does.
+pub async fn run(config: &Config) -> Result<()> {
+    let mut report = Report::default();
+
+    println!("Configuration:");
+    let problems = config.validate();
+    if problems.is_empty() {
+        report.ok("settings", "valid");
+    }
+    for problem in &problems {
+        report.fail("settings", problem);
+    }
+
+    println!("Radios:");
+    let devices = config.meshtastic.all_devices();
+    if devices.is_empty() && config.meshtastic.all_brokers().is_empty() {
+        match serial_detector::detect_meshtastic_port().await {
+            Ok(port) => report.ok("auto-detect", &format!("found a radio on {}", port.display())),
+            Err(e) => report.fail("auto-detect", &e.to_string()),
+        }
+    } else if devices.is_empty() {
+        report.skip("radios", "none configured, MQTT only");
+    }
+    for device in &devices {
+        let name = format!("radio {}", device.display_name());
+        if !device.enabled.unwrap_or(true) {
+            report.skip(&name, "disabled");
+        } else if let Some(tcp_address) = &device.tcp_address {
+            let (host, port) = match tcp_address.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse().unwrap_or(DEFAULT_TCP_PORT)),
+                None => (tcp_address.as_str(), DEFAULT_TCP_PORT),
+            };
+            report.resolve(&name, host, port).await;
+        } else if device.ble.is_some() {
+            report.skip(&name, "BLE radios are only found by scanning when the bridge starts");
+        } else if let Some(serial_port) = &device.serial_port {
+            if serial_port.exists() {
+                report.ok(&name, "serial port exists");
+            } else {
+                report.fail(&name, "serial port does not exist");
+            }
+        }
+    }
+
+    println!("Servers:");
+    // Through a proxy, host names are resolved by the proxy instead
+    if let Some(proxy) = &config.proxy {
+        match proxy.address.rsplit_once(':').and_then(|(host, port)| Some((host, port.parse().ok()?))) {
+            Some((host, port)) => report.resolve("proxy", host, port).await,
+            None => report.fail("proxy", &format!("address \"{}\" is not host:port", proxy.address)),
+        }
+        report.skip("IRC and MQTT servers", "resolved by the proxy");
+    } else {
+        if config.irc.enabled.unwrap_or(true) {
+            report.resolve("IRC server", &config.irc.server, config.irc.port).await;
+        } else {
+            report.skip("IRC server", "disabled");
+        }
+        for mqtt in config.meshtastic.all_brokers() {
+            if mqtt.enabled.unwrap_or(true) {
+                report.resolve("MQTT broker", &mqtt.broker_address, mqtt.port).await;
+            } else {
+                report.skip("MQTT broker", &format!("{} is disabled", mqtt.broker_address));
+            }
+        }
+    }
+
+    if report.failed > 0 {
+        return Err(anyhow::anyhow!("{} check(s) failed", report.failed));
+    }
+    println!("All checks passed");
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Largest text payload that reliably fits in a single LoRa packet
+pub const MAX_TEXT_BYTES: usize = 200;
+
+/// Pause between the parts of a split message so they arrive in order
+pub const CHUNK_DELAY: Duration = Duration::from_secs(2);
+
+/// Split `content` so that every part, once `prefix` and a part number like
+/// "(1/3) " are added, fits in `max_bytes`. Parts are broken at whitespace
+/// where possible. A message that already fits is returned as one part
+/// without a number.
+pub fn split_text(prefix: &str, content: &str, max_bytes: usize) -> Vec<String> {
+    if prefix.len() + content.len() <= max_bytes {
+        return vec![format!("{}{}", prefix, content)];
+    }
+
+    // The space taken by the part numbers depends on how many parts there
+    // are, so retry with wider numbers until they fit
+    let mut digits = 1;
+    loop {
+        let widest = 10usize.pow(digits) - 1;
+        let marker_len = format!("({}/{}) ", widest, widest).len();
+        let budget = max_bytes.saturating_sub(prefix.len() + marker_len).max(1);
+        let parts = split_words(content, budget);
+        if parts.len() <= widest {
+            let total = parts.len();
+            return parts.into_iter()
+                .enumerate()
+                .map(|(i, part)| format!("{}({}/{}) {}", prefix, i + 1, total, part))
+                .collect();
+        }
+        digits += 1;
+    }
+}
+
+/// Break text into pieces of at most `budget` bytes, preferring whitespace
+/// and never splitting a UTF-8 character
+fn split_words(text: &str, budget: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim();
+    while rest.len() > budget {
+        let mut end = budget;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            // A single character wider than the budget
+            end = rest.chars().next().map(char::len_utf8).unwrap_or(rest.len());
+        }
+        let cut = if rest[end..].starts_with(char::is_whitespace) {
+            end
+        } else {
+            rest[..end].rfind(char::is_whitespace)
+                .filter(|&space| space > 0)
+                .unwrap_or(end)
+        };
+        parts.push(rest[..cut].trim_end());
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
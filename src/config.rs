@@ -1,13 +1,101 @@
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use log::info;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::aliases::Aliases;
+use crate::filter::Filters;
+use crate::links::Links;
+use crate::nodes::parse_node_id;
+use crate::schedule::QuietHours;
+use crate::sealed;
+use crate::template::Templates;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub irc: IrcConfig,
     pub meshtastic: MeshtasticConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>, // SOCKS5 proxy for the IRC and MQTT connections
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<LinkConfig>, // Where messages go between endpoints, IRC and every radio and broker both ways if empty
+}
+
+/// Messages passed from one endpoint to another: "irc", "radio <name>" or
+/// "mqtt <address>:<port>"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkConfig {
+    pub from: String,
+    pub to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub irc_channel: Option<String>, // Only messages said in or posted to this IRC channel, any by default
+    #[serde(default)]
+    pub direction: LinkDirection,
+    #[serde(default)]
+    pub filters: FilterRules, // For messages either way
+}
+
+/// Whether a link also carries messages back from `to` to `from`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkDirection {
+    #[default]
+    Both,
+    OneWay,
+}
+
+/// How much is logged and where to. RUST_LOG, when set, takes precedence over
+/// the levels given here.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>, // Defaults to "info"
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modules: BTreeMap<String, String>, // Level per module, e.g. {"meshtastic_irc::mqtt_handler": "debug"}
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>, // Appended to instead of logging to stderr
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+/// How each log line is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json, // One object per line, for log collectors
+}
+
+impl LoggingConfig {
+    /// The log levels as (module, level) pairs, None for the default
+    pub fn levels(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+        std::iter::once((None, self.level.as_deref().unwrap_or("info")))
+            .chain(self.modules.iter().map(|(module, level)| (Some(module.as_str()), level.as_str())))
+    }
+}
+
+/// A SOCKS5 proxy, e.g. Tor at "127.0.0.1:9050"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub address: String,  // host:port
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, deserialize_with = "optional_secret", skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct IrcConfig {
     pub server: String,
     pub port: u16,
@@ -15,27 +103,1395 @@ pub struct IrcConfig {
     pub nickname: String,
     pub username: Option<String>,
     pub realname: Option<String>,
+    #[serde(default, deserialize_with = "optional_secret")]
     pub password: Option<String>,
     pub use_tls: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admins: Vec<String>, // Hostmasks allowed to use !admin, e.g. "alice!*@example.org"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>, // Defaults to true; when false the mesh side runs without IRC
+    #[serde(default)]
+    pub buffer: BufferConfig, // Messages from the mesh held while IRC is down
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MeshtasticConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>, // The radio set by serial_port, tcp_address or ble below, defaults to true
     #[serde(skip_serializing_if = "Option::is_none")]
     pub serial_port: Option<PathBuf>,
+    #[serde(default)]
+    pub serial: SerialConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ble: Option<BleConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mqtt: Option<MqttConfig>,
-    pub channel: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mqtt_brokers: Vec<MqttConfig>, // Further brokers, e.g. the public one read-only beside a private one
+    pub channel: ChannelRef,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<ChannelMapping>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<ChannelRoute>, // Like channels, but each may carry messages one way only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hop_limit: Option<u32>, // For packets sent from IRC, device default if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_destination: Option<String>, // Node ID like "!a1b2c3d4" IRC channel messages go to, broadcast if unset
+    #[serde(default)]
+    pub positions: PositionConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub detection: DetectionConfig,
+    #[serde(default)]
+    pub presence: PresenceConfig,
+    #[serde(default)]
+    pub store_forward: StoreForwardConfig,
+    #[serde(default)]
+    pub topology: TopologyConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_export_path: Option<String>, // Written by !export nodes, defaults to "nodes.json"
+    #[serde(default)]
+    pub sender_format: SenderFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases_file: Option<PathBuf>, // Names and IRC nicks for nodes, re-read on reload
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub buffer: BufferConfig, // Messages from IRC held while a radio or MQTT broker is down
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub report_delivery_errors: bool, // Post every NAK to IRC, not just final failures
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_nodes: Vec<String>, // Node IDs like "!a1b2c3d4" never relayed to IRC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_nodes: Option<Vec<String>>, // Only these nodes are bridged, all nodes if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay_ports: Option<Vec<String>>, // e.g. ["text", "waypoint"], defaults to text only
+    #[serde(default)]
+    pub skip_via_mqtt: bool, // Ignore packets the radio got from an MQTT downlink rather than over LoRa
+    #[serde(default)]
+    pub range_test: RangeTestMode,
+    #[serde(default)]
+    pub unknown_ports: UnknownPortsConfig,
+    #[serde(default)]
+    pub show_signal: bool, // Append SNR, RSSI and hop count to messages from the mesh
+    #[serde(default)]
+    pub direct_messages: DirectMessageConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<OwnerConfig>, // Names set on the radio at startup, left alone if unset
+    #[serde(default)]
+    pub time_sync: TimeSyncConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channel_keys: Vec<ChannelKeyConfig>, // For packets the radio or MQTT broker delivers encrypted
+    #[serde(default)]
+    pub templates: TemplateConfig,
+    #[serde(default)]
+    pub filters: FilterConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+}
+
+/// Limits on traffic sent to the mesh from IRC. Excess messages are queued.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub messages_per_minute: Option<u32>, // Unlimited by default
+    pub region: Option<String>, // e.g. "EU_868", defaults to the device's LoRa region
+    pub duty_cycle_percent: Option<f32>, // Airtime per hour, defaults to the region's legal limit
+    pub queue_warning: Option<usize>, // Queued messages before IRC is warned, defaults to 5
+    pub max_queue: Option<usize>, // Queued packets before the oldest chatter is dropped, defaults to 50
+}
+
+/// Retransmission of messages sent from IRC that are not acknowledged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_retries: Option<u32>, // Defaults to 2
+    pub ack_timeout_secs: Option<u64>, // Before the first retry, doubled each time; defaults to 30
+}
+
+/// Reconnecting to a radio after it is unplugged, reboots or drops the
+/// connection. Read from meshtastic.reconnect, but the backoff settings apply
+/// to IRC and MQTT as well, and to restarting any of them after it fails.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectConfig {
+    #[serde(default)]
+    pub redetect_serial_port: bool, // Re-run serial port auto-detection before each attempt
+    pub initial_delay_secs: Option<u64>, // Wait before the first attempt, defaults to 1
+    pub multiplier: Option<f64>, // Growth of the wait after each failed attempt, defaults to 2
+    pub max_delay_secs: Option<u64>, // Longest wait between attempts, defaults to 60
+    pub max_attempts: Option<u32>, // Attempts before giving up on the connection, unlimited by default
+    pub stall_timeout_secs: Option<u64>, // Silence before a radio is reconnected, defaults to 300, 0 to disable
+    pub max_restarts: Option<u32>, // Restarts of a failed IRC, radio or MQTT handler within the window, defaults to 5
+    pub restart_window_secs: Option<u64>, // Defaults to 600
+}
+
+/// Messages held while the leg they're going to is down, replayed in order
+/// once it is back
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BufferConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_messages: Option<usize>, // The oldest is dropped beyond this, defaults to 50, 0 to hold nothing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<u64>, // Older messages aren't replayed, defaults to 600
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delayed_marker: Option<String>, // Put before replayed messages, e.g. "[delayed]"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>, // SQLite file messages are held in to survive restarts, in memory if unset
+}
+
+/// How mesh senders are named on IRC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SenderFormat {
+    #[default]
+    #[serde(rename = "short")]
+    Short,
+    #[serde(rename = "long")]
+    Long,
+    #[serde(rename = "short (long)")]
+    ShortLong,
+    #[serde(rename = "!nodeid")]
+    NodeId,
+}
+
+/// What to do with range test packets ("seq 1", "seq 2", ...)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeTestMode {
+    #[default]
+    Suppress,
+    Summarize, // One line per node once its test goes quiet
+    Relay,
+}
+
+/// Dumping of packets on ports the bridge doesn't understand, for
+/// developers of custom Meshtastic apps. Off by default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnknownPortsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: DumpFormat,
+    #[serde(default)]
+    pub sink: DumpSink,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+    #[default]
+    Hex,
+    Json,
+}
+
+/// Where packet dumps go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpSink {
+    #[default]
+    Irc,
+    Log,
+}
+
+/// Serial line settings. Some boards (certain NRF52 variants and USB adapters)
+/// reset into the bootloader when DTR/RTS are asserted and need them disabled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialConfig {
+    pub baud_rate: Option<u32>, // Defaults to 115200
+    pub dtr: Option<bool>, // Defaults to true
+    pub rts: Option<bool>, // Defaults to true
+}
+
+/// Announcements of significant telemetry changes to IRC, off by default
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    #[serde(default)]
+    pub announce: bool,
+    pub battery_threshold: Option<u32>, // Percent, defaults to 20
+    pub battery_hysteresis: Option<u32>, // Percent above the threshold before alerting again, defaults to 5
+    pub voltage_drop: Option<f32>, // Volts between two reports, defaults to 0.3
+}
+
+/// Relaying of node positions to IRC, off by default
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PositionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub min_interval_secs: Option<u64>, // Per node, defaults to 900
+}
+
+/// Relaying of detection sensor alerts (motion, doors, ...) to IRC, off by default
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DetectionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<String>, // Node IDs like "!a1b2c3d4" to relay, all nodes if empty
+}
+
+/// Announcing nodes that go quiet and come back, off by default
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresenceConfig {
+    #[serde(default)]
+    pub announce: bool,
+    pub offline_after_minutes: Option<u64>, // Defaults to 60
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub muted: Vec<String>, // Node IDs like "!a1b2c3d4" never announced
+}
+
+/// Replaying missed messages from a Store & Forward router on the mesh
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreForwardConfig {
+    #[serde(default)]
+    pub history_on_startup: bool,
+    pub router: Option<String>, // Node ID like "!a1b2c3d4", learned from heartbeats if unset
+    pub window_minutes: Option<u32>, // History to request, defaults to 60
+}
+
+/// The mesh topology learned from NeighborInfo packets
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopologyConfig {
+    pub export_path: Option<String>, // JSON file rewritten on every NeighborInfo packet
+}
+
+/// Direct messages from the mesh to the bridge's own radio
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirectMessageConfig {
+    pub auto_reply: Option<String>, // Sent back to the sender, at most once an hour per node
+    pub forward_to: Option<String>, // IRC nick sent DMs privately, the main IRC channel by default
+}
+
+/// The key of a channel whose packets the bridge decrypts itself
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelKeyConfig {
+    pub name: String, // Channel name, e.g. "LongFast" for the default channel
+    #[serde(deserialize_with = "secret")]
+    pub psk: String, // Base64 as shown by the Meshtastic apps, e.g. "AQ==" for the default key
+    pub irc_channel: Option<String>, // Defaults to the main IRC channel (serial/TCP/BLE only)
+}
+
+/// Layout of bridged text messages. Placeholders are {sender}, {channel}
+/// (the mesh channel's name), {snr}, {time} and {text}. The timezone and
+/// time format also apply to Store & Forward history replays, and the
+/// timezone to quiet hours.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mesh_to_irc: Option<String>, // Defaults to "[mesh-{sender}]: {text}"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub irc_to_mesh: Option<String>, // Must end with {text}, defaults to "[IRC-{sender}] {text}"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>, // "UTC", "local" or an offset like "+02:00", defaults to "UTC"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<String>, // strftime-style, defaults to "%H:%M"
+}
+
+/// Rules a text message must pass to be bridged, for each direction. Bridge
+/// commands from IRC aren't filtered.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    #[serde(default)]
+    pub irc_to_mesh: FilterRules,
+    #[serde(default)]
+    pub mesh_to_irc: FilterRules,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>, // Regexes; when any are given, only messages matching one pass
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>, // Regexes; messages matching any are dropped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>, // In characters, unlimited by default
+    #[serde(default)]
+    pub too_long: TooLongAction,
+}
+
+/// What happens to a message over max_length
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TooLongAction {
+    #[default]
+    Truncate, // Cut short, ending in "…"
+    Drop,
+}
+
+/// Times when messages from IRC aren't sent to the mesh, e.g. to keep the
+/// night's airtime free for emergency traffic. The mesh is still relayed to IRC.
+/// Times are in templates.timezone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quiet_hours: Vec<QuietHoursConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHoursConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>, // e.g. ["sat", "sun"], every day if empty
+    pub from: String, // "HH:MM"
+    pub to: String, // "HH:MM", the next day if not after from
+}
+
+/// Setting the radio's clock from the host, for nodes without GPS. Off by
+/// default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeSyncConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub interval_minutes: Option<u64>, // Defaults to 60
+}
+
+/// The owner names the bridge gives its radio, so mesh users can tell the
+/// gateway apart from people
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OwnerConfig {
+    pub short_name: String, // Up to 4 characters, e.g. "IRC"
+    pub long_name: String, // e.g. "IRC Bridge #meshtastic"
+}
+
+/// An additional mesh channel to bridge, optionally relayed to its own IRC
+/// channel instead of the main one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelMapping {
+    pub channel: ChannelRef,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub irc_channel: Option<String>,
+}
+
+/// A mesh channel routed to an IRC channel (the main one if unset), in one
+/// or both directions. A mesh channel may be routed to several IRC channels
+/// and the other way round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelRoute {
+    pub channel: ChannelRef,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub irc_channel: Option<String>,
+    #[serde(default)]
+    pub direction: RouteDirection,
+}
+
+/// Which way a route carries messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteDirection {
+    #[default]
+    Both,
+    MeshToIrc,
+    IrcToMesh,
+}
+
+impl RouteDirection {
+    pub fn to_irc(self) -> bool {
+        self != RouteDirection::IrcToMesh
+    }
+
+    pub fn to_mesh(self) -> bool {
+        self != RouteDirection::MeshToIrc
+    }
+}
+
+/// A single radio connection. The top-level serial_port/tcp_address/ble
+/// fields describe one implicit device; `devices` lists additional ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>, // Defaults to true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial_port: Option<PathBuf>,
+    #[serde(default)]
+    pub serial: SerialConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ble: Option<BleConfig>,
+    pub channel: ChannelRef,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<ChannelMapping>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<ChannelRoute>,
+}
+
+impl DeviceConfig {
+    /// Every route of this device, starting with `channel` which always goes
+    /// both ways with the main IRC channel. Entries in `channels` go both ways.
+    pub fn channel_routes(&self) -> Vec<ChannelRoute> {
+        let mut routes = vec![ChannelRoute {
+            channel: self.channel.clone(),
+            irc_channel: None,
+            direction: RouteDirection::Both,
+        }];
+        routes.extend(self.channels.iter().map(|mapping| ChannelRoute {
+            channel: mapping.channel.clone(),
+            irc_channel: mapping.irc_channel.clone(),
+            direction: RouteDirection::Both,
+        }));
+        routes.extend(self.routes.iter().cloned());
+        routes
+    }
+
+    pub fn display_name(&self) -> String {
+        if let Some(name) = &self.name {
+            name.clone()
+        } else if let Some(tcp_address) = &self.tcp_address {
+            tcp_address.clone()
+        } else if let Some(ble) = &self.ble {
+            ble.device.clone()
+        } else if let Some(serial_port) = &self.serial_port {
+            serial_port.display().to_string()
+        } else {
+            "auto-detect".to_string()
+        }
+    }
+}
+
+/// A password or key given inline, as "env:NAME" to read the environment
+/// variable NAME, or as {"file": "/run/secrets/name"} to read a file such as
+/// a Docker secret or systemd credential
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SecretSource {
+    Inline(String),
+    File { file: PathBuf },
+}
+
+impl SecretSource {
+    fn resolve(self) -> Result<String, String> {
+        match self {
+            SecretSource::Inline(value) => match value.strip_prefix("env:") {
+                Some(name) => std::env::var(name)
+                    .map_err(|_| format!("environment variable {} named by \"{}\" is not set", name, value)),
+                None => Ok(value),
+            },
+            // Files written by editors and `echo` end in a newline that
+            // isn't part of the secret
+            SecretSource::File { file } => std::fs::read_to_string(&file)
+                .map(|secret| secret.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|e| format!("can't read secret file {}: {}", file.display(), e)),
+        }
+    }
+}
+
+fn secret<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    SecretSource::deserialize(deserializer)?.resolve().map_err(serde::de::Error::custom)
+}
+
+fn optional_secret<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Option::<SecretSource>::deserialize(deserializer)?
+        .map(SecretSource::resolve)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// The commented example config, which `config init` starts from
+const EXAMPLE_CONFIG: &str = include_str!("../config.example.jsonc");
+
+/// Blank out `//` and `/* */` comments outside strings, keeping line breaks
+/// so parse errors point at the right line
+pub fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(c);
+            }
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        stripped.push(c);
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Key naming the files a config file is layered on top of
+const INCLUDE_KEY: &str = "include";
+
+/// A config file as read, with its includes removed
+struct ConfigLayer {
+    text: Option<String>,  // Without comments, None if it had an include key, which the text still has
+    value: Value,
+}
+
+/// Read config files and deep-merge them, later files overriding earlier
+/// ones. A file's `include`s are read before it, relative to its directory.
+/// Objects are merged key by key; anything else, lists included, replaces
+/// what it overrides. A profile goes beneath them all, on top of the
+/// defaults, so files only need what they change.
+pub fn load_files(paths: &[PathBuf], profile: Option<Profile>) -> anyhow::Result<Config> {
+    let mut layers = Vec::new();
+    for path in paths {
+        read_layers(path, &mut Vec::new(), &mut layers)?;
+    }
+    let mut merged = match profile {
+        Some(profile) => {
+            let mut defaults = serde_json::to_value(Config::default())?;
+            merge(&mut defaults, profile.settings());
+            defaults
+        }
+        // Parsing the text rather than the merged value keeps line numbers
+        // in errors when there is nothing to merge
+        None => match layers.as_slice() {
+            [] => return Ok(Config::default()),
+            [ConfigLayer { text: Some(text), value }] => return serde_json::from_str(text)
+                .map_err(|e| anyhow::anyhow!("Could not parse config file {}: {}", paths[0].display(), explain(&e, value))),
+            _ => Value::Object(Default::default()),
+        },
+    };
+    for layer in layers {
+        merge(&mut merged, layer.value);
+    }
+    let mut sources: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+    if let Some(profile) = profile {
+        sources.insert(0, format!("profile {}", profile));
+    }
+    serde_json::from_value(merged.clone())
+        .map_err(|e| anyhow::anyhow!("Could not parse config from {}: {}", sources.join(", "), explain(&e, &merged)))
+}
+
+/// A parse error in words, naming an unknown setting by its path with the
+/// known one it's closest to, e.g. "unknown setting irc.chanel (did you mean
+/// irc.channel?)"
+fn explain(error: &serde_json::Error, value: &Value) -> String {
+    let message = error.to_string();
+    let Some(rest) = message.strip_prefix("unknown field `") else {
+        return message;
+    };
+    // serde lists the known fields as "expected one of `a`, `b`, ..."
+    let mut quoted = rest.split('`').step_by(2);
+    let name = quoted.next().unwrap_or_default();
+    let known: Vec<&str> = quoted.take_while(|field| !field.contains(" at line ")).collect();
+    let path = find_key(value, name, &known).unwrap_or_else(|| name.to_string());
+    let mut explained = format!("unknown setting {}", path);
+    if error.line() > 0 {
+        explained.push_str(&format!(" at line {} column {}", error.line(), error.column()));
+    }
+    let closest = known.iter()
+        .map(|field| (strsim::jaro_winkler(name, field), field))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+    if let Some((_, field)) = closest {
+        let parent = &path[..path.len() - name.len()];
+        explained.push_str(&format!(" (did you mean {}{}?)", parent, field));
+    }
+    explained
+}
+
+/// The path of the first object holding `name` among keys otherwise in
+/// `known`, e.g. "meshtastic.mqtt_brokers[1].pasword"
+fn find_key(value: &Value, name: &str, known: &[&str]) -> Option<String> {
+    match value {
+        Value::Object(object) => {
+            if object.contains_key(name) && object.keys().all(|key| key == name || known.contains(&key.as_str())) {
+                return Some(name.to_string());
+            }
+            object.iter().find_map(|(key, value)| {
+                let path = find_key(value, name, known)?;
+                Some(if path.starts_with('[') { format!("{}{}", key, path) } else { format!("{}.{}", key, path) })
+            })
+        }
+        Value::Array(items) => items.iter().enumerate()
+            .find_map(|(index, item)| find_key(item, name, known).map(|path| format!("[{}].{}", index, path))),
+        _ => None,
+    }
+}
+
+/// Read `path` and, first, everything it includes. `including` holds the
+/// files on the way here, to catch a file that includes itself.
+fn read_layers(path: &Path, including: &mut Vec<PathBuf>, layers: &mut Vec<ConfigLayer>) -> anyhow::Result<()> {
+    let canonical = path.canonicalize()
+        .map_err(|e| anyhow::anyhow!("Can't read config file {}: {}", path.display(), e))?;
+    if including.contains(&canonical) {
+        return Err(anyhow::anyhow!("Config file {} includes itself", path.display()));
+    }
+    info!("Loading config from: {}", path.display());
+    let mut text = std::fs::read_to_string(path)?;
+    if sealed::is_sealed(&text) {
+        text = sealed::open_config(&text)
+            .map_err(|e| anyhow::anyhow!("Can't decrypt config file {}: {}", path.display(), e))?;
+    }
+    let text = strip_comments(&text);
+    let mut value: Value = serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Could not parse config file {}: {}", path.display(), e))?;
+    let include = value.as_object_mut().and_then(|object| object.remove(INCLUDE_KEY));
+    let text = include.is_none().then_some(text);
+    let includes = match include {
+        None => Vec::new(),
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Array(items)) => items.into_iter()
+            .map(|item| match item {
+                Value::String(include) => Ok(include),
+                _ => Err(anyhow::anyhow!("include in {} must list file names", path.display())),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        Some(_) => return Err(anyhow::anyhow!("include in {} must be a file name or a list of them", path.display())),
+    };
+    
+    including.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for include in includes {
+        read_layers(&dir.join(include), including, layers)?;
+    }
+    including.pop();
+    layers.push(ConfigLayer { text, value });
+    Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Deep-merge `overlay` into `base`
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// A starter config with every setting at a sensible default, using
+/// `serial_port` if given and auto-detection otherwise
+pub fn starter_config(serial_port: Option<&std::path::Path>, format: ConfigFormat) -> anyhow::Result<String> {
+    let serial_port = match serial_port {
+        Some(path) => serde_json::to_string(&path.display().to_string())?,
+        None => "null".to_string(),
+    };
+    // The example's opening comments are about copying it
+    let body = &EXAMPLE_CONFIG[EXAMPLE_CONFIG.find('{').unwrap_or(0)..];
+    let mut text = format!(
+        "// Meshtastic-IRC Bridge configuration, written by `meshtastic-irc config init`\n\
+         // Comments are allowed; see the README for what each feature does\n\n{}",
+        body.replace("\"serial_port\": \"/dev/ttyUSB0\"", &format!("\"serial_port\": {}", serial_port))
+    );
+    let config: Config = serde_json::from_str(&strip_comments(&text))
+        .map_err(|e| anyhow::anyhow!("The example config doesn't parse: {}", e))?;
+    if format == ConfigFormat::Json {
+        text = serde_json::to_string_pretty(&config)? + "\n";
+    }
+    Ok(text)
+}
+
+/// The most hops the Meshtastic firmware allows
+pub const MAX_HOP_LIMIT: u32 = 7;
+
+/// Prefix of the environment variables that override config settings, e.g.
+/// MESHIRC_IRC_PASSWORD
+const ENV_PREFIX: &str = "MESHIRC_";
+
+/// An override from the environment; empty variables count as unset
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(format!("{}{}", ENV_PREFIX, name)).ok().filter(|value| !value.is_empty())
+}
+
+fn env_parse<T: FromStr>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: fmt::Display,
+{
+    env_var(name)
+        .map(|value| value.parse::<T>()
+            .map_err(|e| anyhow::anyhow!("Invalid {}{} \"{}\": {}", ENV_PREFIX, name, value, e)))
+        .transpose()
+}
+
+impl MeshtasticConfig {
+    /// All radios to connect to: the top-level device (if one is configured)
+    /// followed by the entries in `devices`
+    pub fn all_devices(&self) -> Vec<DeviceConfig> {
+        let mut devices = Vec::new();
+        if self.serial_port.is_some() || self.tcp_address.is_some() || self.ble.is_some() {
+            devices.push(DeviceConfig {
+                name: None,
+                enabled: self.enabled,
+                serial_port: self.serial_port.clone(),
+                serial: self.serial.clone(),
+                tcp_address: self.tcp_address.clone(),
+                ble: self.ble.clone(),
+                channel: self.channel.clone(),
+                channels: self.channels.clone(),
+                routes: self.routes.clone(),
+            });
+        }
+        devices.extend(self.devices.iter().cloned());
+        devices
+    }
+
+    /// All MQTT brokers to connect to: `mqtt` (if configured) followed by
+    /// the entries in `mqtt_brokers`
+    pub fn all_brokers(&self) -> Vec<&MqttConfig> {
+        self.mqtt.iter().chain(self.mqtt_brokers.iter()).collect()
+    }
+
+    /// IRC channels that mesh channels are routed to, besides the main one
+    pub fn irc_channels(&self) -> Vec<String> {
+        let mut irc_channels: Vec<String> = Vec::new();
+        let mappings = self.channels.iter()
+            .chain(self.devices.iter().flat_map(|device| device.channels.iter()));
+        let channel_routes = self.routes.iter()
+            .chain(self.devices.iter().flat_map(|device| device.routes.iter()));
+        let routes = self.all_brokers().into_iter().flat_map(|mqtt| mqtt.routes.iter());
+        let mapped = mappings.filter_map(|mapping| mapping.irc_channel.as_ref())
+            .chain(channel_routes.filter_map(|route| route.irc_channel.as_ref()))
+            .chain(routes.map(|route| &route.irc_channel));
+        for irc_channel in mapped {
+            if !irc_channels.contains(irc_channel) {
+                irc_channels.push(irc_channel.clone());
+            }
+        }
+        irc_channels
+    }
+}
+
+/// A mesh channel, given either by index (0-7) or by name (e.g. "LongFast")
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChannelRef {
+    Index(u32),
+    Name(String),
+}
+
+impl fmt::Display for ChannelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelRef::Index(index) => write!(f, "{}", index),
+            ChannelRef::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl FromStr for ChannelRef {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse::<u32>() {
+            Ok(index) => ChannelRef::Index(index),
+            Err(_) => ChannelRef::Name(s.to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BleConfig {
+    pub device: String, // BLE name (e.g. "Meshtastic_2ef4") or MAC address
+    pub scan_timeout_secs: Option<u64>,
+}
+
+/// Traffic on topics matching `topic` goes to its own IRC channel, and
+/// messages said there are published under that topic's root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopicRoute {
+    pub topic: String, // Topic filter, e.g. "msh/US/WA/#"
+    pub irc_channel: String,
+}
+
+/// What to do with retained messages the broker replays on subscribing,
+/// usually the last envelope on each topic
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetainedPolicy {
+    #[default]
+    Relay,
+    Skip,
+    Mark, // Relay with an "[old]" prefix
+}
+
+/// A known broker whose settings fill in those left unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttPreset {
+    Public, // mqtt.meshtastic.org, run by the Meshtastic project
+}
+
+impl FromStr for MqttPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(MqttPreset::Public),
+            _ => Err(format!("unknown MQTT preset \"{}\", expected \"public\"", s)),
+        }
+    }
+}
+
+/// Built-in settings for a typical deployment, which the config files,
+/// environment and command line override
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    LiberaLongfast, // A radio on LongFast bridged to #meshtastic on Libera.Chat
+    PrivateBroker, // A broker on this host, as run beside a gateway node
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "libera-longfast" => Ok(Profile::LiberaLongfast),
+            "private-broker" => Ok(Profile::PrivateBroker),
+            _ => Err(format!("unknown profile \"{}\", expected \"libera-longfast\" or \"private-broker\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::LiberaLongfast => write!(f, "libera-longfast"),
+            Profile::PrivateBroker => write!(f, "private-broker"),
+        }
+    }
+}
+
+impl Profile {
+    /// The settings the profile fills in, as a config file would give them
+    fn settings(self) -> Value {
+        match self {
+            Profile::LiberaLongfast => serde_json::json!({
+                "irc": {
+                    "server": "irc.libera.chat",
+                    "port": 6697,
+                    "use_tls": true,
+                    "channel": "#meshtastic"
+                },
+                "meshtastic": {
+                    "channel": "LongFast",
+                    "rate_limit": { "messages_per_minute": 6 }
+                }
+            }),
+            Profile::PrivateBroker => serde_json::json!({
+                "meshtastic": {
+                    "channel": "LongFast",
+                    "mqtt": {
+                        "broker_address": "localhost",
+                        "port": 1883,
+                        "topic": "msh/+/2/e/#",
+                        "downlink": true
+                    }
+                }
+            }),
+        }
+    }
+}
+
+/// How `config init` writes the starter config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Jsonc, // With a comment on every setting
+    Json,
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonc" => Ok(ConfigFormat::Jsonc),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!("unknown config format \"{}\", expected \"jsonc\" or \"json\"", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MqttConfig {
-    pub broker_address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>, // Defaults to true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<MqttPreset>,
+    #[serde(default)]
+    pub broker_address: String, // Required unless a preset supplies it
+    #[serde(default)]
     pub port: u16,
+    #[serde(default)]
     pub topic: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<String>, // Further topics to subscribe to, e.g. other regions
     pub username: Option<String>,
+    #[serde(default, deserialize_with = "optional_secret")]
     pub password: Option<String>,
     pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive_secs: Option<u64>, // Ping interval, defaults to 30, 0 disables
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>, // Defaults to 5
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_inflight: Option<u16>, // Unacknowledged QoS 1/2 messages, defaults to 100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_capacity: Option<usize>, // Outgoing requests queued for the connection, defaults to 100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persistent_session: Option<bool>, // Keep the broker session between connections, defaults to false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_topic: Option<String>, // e.g. "msh/US", defaults to the part of topic before "/2/"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway_id: Option<String>, // Node ID like "!a1b2c3d4" IRC messages are published under, random if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>, // Channel name IRC messages are published to, defaults to the mesh channel's name or "LongFast"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_filter: Option<Vec<String>>, // Channel names relayed to IRC, e.g. ["LongFast", "Medium*"], all if unset
+    #[serde(default)]
+    pub retained: RetainedPolicy,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<TopicRoute>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscribe_qos: Option<u8>, // 0, 1 or 2, defaults to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_qos: Option<u8>, // 0, 1 or 2, defaults to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_per_minute: Option<u32>, // Messages from IRC published per minute, unlimited by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_burst: Option<u32>, // Messages published at once before publish_per_minute applies, defaults to 5
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_topic: Option<String>, // Retained "online", or "offline" as last will, e.g. "msh/bridge/meshtastic-irc/status"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed_topic: Option<String>, // Traffic bridged both ways as JSON, e.g. "meshtastic-irc/feed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uplink: Option<bool>, // Publish packets the radios hear, as an MQTT gateway does, defaults to false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_topic: Option<String>, // Retained JSON bridge statistics, e.g. "msh/bridge/meshtastic-irc/stats"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_interval_secs: Option<u64>, // How often stats_topic is updated, defaults to 60
+    #[serde(default)]
+    pub use_tls: bool, // mqtts, usually on port 8883
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>, // PEM CA certificates, the system roots if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>, // PEM client certificate, for brokers that require one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>, // PEM key for client_cert
+    #[serde(default, deserialize_with = "optional_secret", skip_serializing_if = "Option::is_none")]
+    pub client_key_passphrase: Option<String>, // For an encrypted PKCS#8 client_key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downlink: Option<bool>, // Publish IRC messages to the mesh, defaults to true (false with the public preset)
+}
+
+impl MqttConfig {
+    /// Fill in what the preset provides and the config leaves unset, then
+    /// check a broker and topic are known
+    pub fn apply_preset(&mut self) -> anyhow::Result<()> {
+        if let Some(MqttPreset::Public) = self.preset {
+            if self.broker_address.is_empty() {
+                self.broker_address = "mqtt.meshtastic.org".to_string();
+            }
+            if self.port == 0 {
+                self.port = if self.use_tls { 8883 } else { 1883 };
+            }
+            if self.topic.is_empty() {
+                self.topic = "msh/US/2/e/#".to_string();
+            }
+            if self.username.is_none() && self.password.is_none() {
+                self.username = Some("meshdev".to_string());
+                self.password = Some("large4cats".to_string());
+            }
+            // The public broker is for listening; sending needs opting in
+            self.downlink.get_or_insert(false);
+        }
+        if self.broker_address.is_empty() {
+            return Err(anyhow::anyhow!("MQTT needs a broker_address or a preset"));
+        }
+        if self.port == 0 {
+            self.port = if self.use_tls { 8883 } else { 1883 };
+        }
+        if self.topic.is_empty() {
+            return Err(anyhow::anyhow!("MQTT needs a topic or a preset"));
+        }
+        Ok(())
+    }
+
+    /// Fill in `{region}` and `{gateway_id}` in the topics, from the region in
+    /// meshtastic.rate_limit and this broker's gateway_id
+    pub fn expand_topics(&mut self, region: Option<&str>) -> anyhow::Result<()> {
+        // As published, e.g. "!a1b2c3d4" for "A1B2C3D4"
+        let gateway_id = self.gateway_id.as_deref()
+            .map(|id| parse_node_id(id).map(|node| format!("!{:08x}", node)).unwrap_or_else(|| id.to_string()));
+        let placeholders = [
+            ("{region}", region, "meshtastic.rate_limit.region"),
+            ("{gateway_id}", gateway_id.as_deref(), "gateway_id"),
+        ];
+        let topics = std::iter::once(&mut self.topic)
+            .chain(self.topics.iter_mut())
+            .chain(self.routes.iter_mut().map(|route| &mut route.topic))
+            .chain(self.root_topic.iter_mut())
+            .chain(self.status_topic.iter_mut())
+            .chain(self.feed_topic.iter_mut())
+            .chain(self.stats_topic.iter_mut());
+        for topic in topics {
+            for (placeholder, value, setting) in placeholders {
+                if topic.contains(placeholder) {
+                    let value = value.ok_or_else(|| {
+                        anyhow::anyhow!("MQTT topic \"{}\" uses {} but {} is not set", topic, placeholder, setting)
+                    })?;
+                    *topic = topic.replace(placeholder, value);
+                }
+            }
+            if topic.contains('{') {
+                return Err(anyhow::anyhow!(
+                    "MQTT topic \"{}\" has an unknown placeholder; {{region}} and {{gateway_id}} are supported", topic
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every topic filter subscribed to, `topic` first
+    pub fn subscriptions(&self) -> Vec<String> {
+        let mut subscriptions = vec![self.topic.clone()];
+        let topics = self.topics.iter().chain(self.routes.iter().map(|route| &route.topic));
+        for topic in topics {
+            if !subscriptions.contains(topic) {
+                subscriptions.push(topic.clone());
+            }
+        }
+        subscriptions
+    }
+}
+
+/// What's wrong with a topic filter subscribed to, if anything
+fn topic_filter_problem(filter: &str) -> Option<String> {
+    if filter.is_empty() {
+        return Some("MQTT topic filters can't be empty".to_string());
+    }
+    if filter.ends_with('/') {
+        return Some(format!(
+            "MQTT topic filter \"{}\" ends in '/', so it only matches topics with an empty last level; use \"{}#\"",
+            filter, filter
+        ));
+    }
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (i, level) in levels.iter().enumerate() {
+        if level.contains('#') && (*level != "#" || i != levels.len() - 1) {
+            return Some(format!("MQTT topic filter \"{}\" may only have '#' as its whole last level, e.g. \"msh/US/#\"", filter));
+        }
+        if level.contains('+') && *level != "+" {
+            return Some(format!("MQTT topic filter \"{}\" may only have '+' as a whole level, e.g. \"msh/+/2/e/#\"", filter));
+        }
+    }
+    // Gateways publish under their own node ID, so a filter without a
+    // wildcard only hears one gateway, and only if it ends in that ID
+    let last = levels[levels.len() - 1];
+    if !filter.contains(['#', '+']) && parse_node_id(last).is_none() {
+        return Some(format!(
+            "MQTT topic filter \"{}\" has no wildcard, but gateways publish to topics below it; use \"{}/#\"",
+            filter, filter
+        ));
+    }
+    None
+}
+
+/// What's wrong with a topic the bridge publishes to, if anything
+fn topic_name_problem(topic: &str) -> Option<String> {
+    if topic.contains(['#', '+']) {
+        Some(format!("MQTT topic \"{}\" is published to, so it can't have wildcards", topic))
+    } else if topic.is_empty() || topic.ends_with('/') {
+        Some(format!("MQTT topic \"{}\" is published to, so it can't be empty or end in '/'", topic))
+    } else {
+        None
+    }
+}
+
+/// Stands in for secrets in a config that is shown
+const REDACTED: &str = "<redacted>";
+
+impl Config {
+    /// A copy with the passwords, passphrases and channel keys hidden, for
+    /// showing
+    pub fn redacted(&self) -> Config {
+        let redact = |secret: &mut Option<String>| {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_string());
+            }
+        };
+        let mut config = self.clone();
+        redact(&mut config.irc.password);
+        for mqtt in config.meshtastic.mqtt.iter_mut().chain(config.meshtastic.mqtt_brokers.iter_mut()) {
+            redact(&mut mqtt.password);
+            redact(&mut mqtt.client_key_passphrase);
+        }
+        if let Some(proxy) = &mut config.proxy {
+            redact(&mut proxy.password);
+        }
+        for key in &mut config.meshtastic.channel_keys {
+            key.psk = REDACTED.to_string();
+        }
+        config
+    }
+
+    /// Apply overrides from MESHIRC_* environment variables, so containers
+    /// can keep secrets out of config files and command lines
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Some(server) = env_var("IRC_SERVER") {
+            self.irc.server = server;
+        }
+        if let Some(port) = env_parse("IRC_PORT")? {
+            self.irc.port = port;
+        }
+        if let Some(channel) = env_var("IRC_CHANNEL") {
+            self.irc.channel = channel;
+        }
+        if let Some(nickname) = env_var("IRC_NICK") {
+            self.irc.nickname = nickname;
+        }
+        if let Some(username) = env_var("IRC_USERNAME") {
+            self.irc.username = Some(username);
+        }
+        if let Some(password) = env_var("IRC_PASSWORD") {
+            self.irc.password = Some(password);
+        }
+        if let Some(use_tls) = env_parse("IRC_TLS")? {
+            self.irc.use_tls = use_tls;
+        }
+        if let Some(serial_port) = env_var("SERIAL_PORT") {
+            self.meshtastic.serial_port = Some(PathBuf::from(serial_port));
+        }
+        if let Some(tcp_address) = env_var("TCP_ADDRESS") {
+            self.meshtastic.tcp_address = Some(tcp_address);
+        }
+        
+        // A broker address is enough to set up MQTT; the other settings
+        // apply to an mqtt section that already exists
+        if let Some(broker_address) = env_var("MQTT_BROKER") {
+            self.meshtastic.mqtt.get_or_insert_with(MqttConfig::default).broker_address = broker_address;
+        }
+        if let Some(mqtt) = &mut self.meshtastic.mqtt {
+            if let Some(port) = env_parse("MQTT_PORT")? {
+                mqtt.port = port;
+            }
+            if let Some(topic) = env_var("MQTT_TOPIC") {
+                mqtt.topic = topic;
+            }
+            if let Some(username) = env_var("MQTT_USERNAME") {
+                mqtt.username = Some(username);
+            }
+            if let Some(password) = env_var("MQTT_PASSWORD") {
+                mqtt.password = Some(password);
+            }
+            if let Some(passphrase) = env_var("MQTT_CLIENT_KEY_PASSPHRASE") {
+                mqtt.client_key_passphrase = Some(passphrase);
+            }
+        }
+        
+        if let Some(address) = env_var("PROXY") {
+            let proxy = self.proxy.get_or_insert_with(|| ProxyConfig {
+                address: String::new(),
+                username: None,
+                password: None,
+            });
+            proxy.address = address;
+        }
+        if let Some(proxy) = &mut self.proxy {
+            if let Some(username) = env_var("PROXY_USERNAME") {
+                proxy.username = Some(username);
+            }
+            if let Some(password) = env_var("PROXY_PASSWORD") {
+                proxy.password = Some(password);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Problems that would make the bridge misbehave rather than fail, each
+    /// worded so it says what to change
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        
+        if self.irc.server.trim().is_empty() {
+            problems.push("irc.server is empty; set it to the IRC server's host name, e.g. \"irc.libera.chat\"".to_string());
+        }
+        if self.irc.port == 0 {
+            problems.push("irc.port must be between 1 and 65535, usually 6697 for TLS or 6667 without".to_string());
+        }
+        if self.irc.nickname.is_empty() || self.irc.nickname.contains(char::is_whitespace) {
+            problems.push(format!("irc.nickname \"{}\" must be a single word", self.irc.nickname));
+        }
+        if !self.irc.channel.starts_with('#') {
+            problems.push(format!("irc.channel \"{}\" must start with '#', e.g. \"#{}\"",
+                self.irc.channel, self.irc.channel.trim_start_matches('&')));
+        }
+        
+        let meshtastic = &self.meshtastic;
+        let mapped = meshtastic.channels.iter()
+            .chain(meshtastic.devices.iter().flat_map(|device| device.channels.iter()))
+            .filter_map(|mapping| mapping.irc_channel.as_ref())
+            .map(|irc_channel| ("meshtastic.channels", irc_channel));
+        let channel_routes = meshtastic.routes.iter()
+            .chain(meshtastic.devices.iter().flat_map(|device| device.routes.iter()))
+            .filter_map(|route| route.irc_channel.as_ref())
+            .map(|irc_channel| ("meshtastic.routes", irc_channel));
+        let routed = meshtastic.all_brokers().into_iter()
+            .flat_map(|mqtt| mqtt.routes.iter())
+            .map(|route| ("mqtt.routes", &route.irc_channel));
+        let keyed = meshtastic.channel_keys.iter()
+            .filter_map(|key| key.irc_channel.as_ref())
+            .map(|irc_channel| ("meshtastic.channel_keys", irc_channel));
+        for (section, irc_channel) in mapped.chain(channel_routes).chain(routed).chain(keyed) {
+            if !irc_channel.starts_with('#') {
+                problems.push(format!("IRC channel \"{}\" in {} must start with '#'", irc_channel, section));
+            }
+        }
+        if let Err(e) = Aliases::load(meshtastic.aliases_file.as_deref()) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = Templates::try_from(&meshtastic.templates) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = Filters::try_from(&meshtastic.filters) {
+            problems.push(e.to_string());
+        }
+        // A bad timezone was reported with the templates
+        if let Err(e) = QuietHours::try_from(meshtastic) {
+            if !problems.contains(&e.to_string()) {
+                problems.push(e.to_string());
+            }
+        }
+        
+        let devices = meshtastic.all_devices();
+        for device in &devices {
+            let transports = [device.serial_port.is_some(), device.tcp_address.is_some(), device.ble.is_some()];
+            if transports.iter().filter(|set| **set).count() > 1 {
+                problems.push(format!(
+                    "Radio {} sets more than one of serial_port, tcp_address and ble; keep only the one it is connected by",
+                    device.display_name()
+                ));
+            }
+            for route in device.channel_routes() {
+                if let ChannelRef::Index(index) = route.channel {
+                    if index > 7 {
+                        problems.push(format!("Mesh channel index {} on radio {} must be between 0 and 7", index, device.display_name()));
+                    }
+                }
+            }
+        }
+        
+        let radios = devices.iter().map(|device| device.enabled);
+        let brokers = meshtastic.all_brokers().into_iter().map(|mqtt| mqtt.enabled);
+        let mut legs = radios.chain(brokers).peekable();
+        if legs.peek().is_some() && legs.all(|enabled| enabled == Some(false)) {
+            problems.push("Every radio and MQTT broker is disabled, so there is nothing to bridge".to_string());
+        }
+        
+        if let Some(hop_limit) = meshtastic.hop_limit {
+            if hop_limit > MAX_HOP_LIMIT {
+                problems.push(format!("meshtastic.hop_limit {} is out of range (0-{})", hop_limit, MAX_HOP_LIMIT));
+            }
+        }
+        
+        if let Some(multiplier) = meshtastic.reconnect.multiplier {
+            if !(1.0..=100.0).contains(&multiplier) {
+                problems.push(format!("meshtastic.reconnect.multiplier {} must be between 1 and 100", multiplier));
+            }
+        }
+        if let (Some(initial_delay), Some(max_delay)) = (meshtastic.reconnect.initial_delay_secs, meshtastic.reconnect.max_delay_secs) {
+            if initial_delay > max_delay {
+                problems.push(format!(
+                    "meshtastic.reconnect.initial_delay_secs ({}) is longer than max_delay_secs ({})", initial_delay, max_delay
+                ));
+            }
+        }
+        if meshtastic.reconnect.restart_window_secs == Some(0) {
+            problems.push("meshtastic.reconnect.restart_window_secs must be at least 1".to_string());
+        }
+        
+        for mqtt in meshtastic.all_brokers() {
+            problems.extend(mqtt.subscriptions().iter().filter_map(|filter| topic_filter_problem(filter)));
+            let published = [&mqtt.root_topic, &mqtt.status_topic, &mqtt.feed_topic, &mqtt.stats_topic];
+            problems.extend(published.into_iter().flatten().filter_map(|topic| topic_name_problem(topic)));
+            if mqtt.uplink.unwrap_or(false) && devices.is_empty() {
+                problems.push(format!(
+                    "MQTT broker {} has uplink enabled, which needs a serial, TCP or BLE radio to uplink from",
+                    mqtt.broker_address
+                ));
+            }
+        }
+        if let Some(proxy) = &self.proxy {
+            if proxy.address.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).is_none() {
+                problems.push(format!("proxy.address \"{}\" must be host:port, e.g. \"127.0.0.1:9050\"", proxy.address));
+            }
+            if proxy.password.is_some() && proxy.username.is_none() {
+                problems.push("proxy.password is set without proxy.username".to_string());
+            }
+        }
+        for (module, level) in self.logging.levels() {
+            if level.parse::<log::LevelFilter>().is_err() {
+                let setting = match module {
+                    Some(module) => format!("logging.modules[\"{}\"]", module),
+                    None => "logging.level".to_string(),
+                };
+                problems.push(format!("{} \"{}\" must be one of off, error, warn, info, debug or trace", setting, level));
+            }
+        }
+        if let Err(e) = Links::new(self) {
+            problems.push(e.to_string());
+        }
+        problems
+    }
 }
 
 impl Default for Config {
@@ -50,12 +1506,56 @@ impl Default for Config {
                 realname: None,
                 password: None,
                 use_tls: true,
+                admins: Vec::new(),
+                enabled: None,
+                buffer: BufferConfig::default(),
             },
             meshtastic: MeshtasticConfig {
+                enabled: None,
                 serial_port: None, // Will be auto-detected
+                serial: SerialConfig::default(),
+                tcp_address: None,
+                ble: None,
                 mqtt: None,
-                channel: 0,
+                mqtt_brokers: Vec::new(),
+                channel: ChannelRef::Index(0),
+                channels: Vec::new(),
+                routes: Vec::new(),
+                devices: Vec::new(),
+                hop_limit: None,
+                default_destination: None,
+                positions: PositionConfig::default(),
+                telemetry: TelemetryConfig::default(),
+                detection: DetectionConfig::default(),
+                presence: PresenceConfig::default(),
+                store_forward: StoreForwardConfig::default(),
+                topology: TopologyConfig::default(),
+                node_export_path: None,
+                sender_format: SenderFormat::default(),
+                aliases_file: None,
+                reconnect: ReconnectConfig::default(),
+                buffer: BufferConfig::default(),
+                retry: RetryConfig::default(),
+                rate_limit: RateLimitConfig::default(),
+                report_delivery_errors: false,
+                blocked_nodes: Vec::new(),
+                allowed_nodes: None,
+                relay_ports: None,
+                skip_via_mqtt: false,
+                range_test: RangeTestMode::default(),
+                unknown_ports: UnknownPortsConfig::default(),
+                show_signal: false,
+                direct_messages: DirectMessageConfig::default(),
+                owner: None,
+                time_sync: TimeSyncConfig::default(),
+                channel_keys: Vec::new(),
+                templates: TemplateConfig::default(),
+                filters: FilterConfig::default(),
+                schedule: ScheduleConfig::default(),
             },
+            proxy: None,
+            logging: LoggingConfig::default(),
+            links: Vec::new(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,153 @@
+use aes::{Aes128, Aes256};
+use anyhow::Result;
+use base64::Engine;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use meshtastic::protobufs::{mesh_packet, Data, MeshPacket};
+use meshtastic::Message;
+
+use crate::config::ChannelKeyConfig;
+
+/// The key a one-byte PSK of 1 ("AQ==") stands for, the mesh-wide default
+const DEFAULT_KEY: [u8; 16] = [
+    0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59,
+    0xf0, 0xbc, 0xff, 0xab, 0xcf, 0x4e, 0x69, 0x01,
+];
+
+/// The name of the primary channel on the default modem preset
+const DEFAULT_CHANNEL_NAME: &str = "LongFast";
+
+/// A channel whose packets can be decrypted, with the IRC channel they go to
+/// (None for the main IRC channel)
+#[derive(Debug, Clone)]
+pub struct ChannelKey {
+    pub name: String,
+    pub irc_channel: Option<String>,
+    key: Vec<u8>,
+    hash: u32,
+}
+
+impl TryFrom<&ChannelKeyConfig> for ChannelKey {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &ChannelKeyConfig) -> Result<Self> {
+        let psk = base64::engine::general_purpose::STANDARD.decode(&config.psk)
+            .map_err(|e| anyhow::anyhow!("PSK of channel {} is not valid base64: {}", config.name, e))?;
+        let key = expand_psk(&psk)
+            .ok_or_else(|| anyhow::anyhow!("Channel {} has no encryption, its packets need no key", config.name))?;
+        Ok(Self {
+            name: config.name.clone(),
+            irc_channel: config.irc_channel.clone(),
+            hash: channel_hash(&config.name, &key),
+            key,
+        })
+    }
+}
+
+/// Keys for channels the bridge decrypts itself, matched to packets by
+/// channel hash
+#[derive(Debug, Clone, Default)]
+pub struct ChannelKeys {
+    keys: Vec<ChannelKey>,
+}
+
+impl TryFrom<&[ChannelKeyConfig]> for ChannelKeys {
+    type Error = anyhow::Error;
+
+    fn try_from(configs: &[ChannelKeyConfig]) -> Result<Self> {
+        let keys = configs.iter()
+            .map(ChannelKey::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { keys })
+    }
+}
+
+impl ChannelKeys {
+    /// The default LongFast channel and key, which most public traffic uses
+    pub fn default_channel() -> Self {
+        let key = DEFAULT_KEY.to_vec();
+        Self {
+            keys: vec![ChannelKey {
+                name: DEFAULT_CHANNEL_NAME.to_string(),
+                irc_channel: None,
+                hash: channel_hash(DEFAULT_CHANNEL_NAME, &key),
+                key,
+            }],
+        }
+    }
+
+    /// Decrypt an encrypted packet with the first key whose channel hash
+    /// matches and that yields a valid payload. Hashes are a single byte, so
+    /// several channels can share one.
+    pub fn decrypt(&self, packet: &MeshPacket) -> Option<(Data, &ChannelKey)> {
+        let Some(mesh_packet::PayloadVariant::Encrypted(ciphertext)) = &packet.payload_variant else {
+            return None;
+        };
+        self.keys.iter()
+            .filter(|key| key.hash == packet.channel)
+            .find_map(|key| {
+                let plaintext = apply_keystream(&key.key, packet.id, packet.from, ciphertext);
+                let data = Data::decode(plaintext.as_slice()).ok()?;
+                // The wrong key usually decodes to garbage or an empty port
+                (data.portnum != 0).then_some((data, key))
+            })
+    }
+}
+
+/// A decoded packet as it goes over the air: its payload encrypted with the
+/// channel's key, and its channel index replaced by the channel hash. None
+/// if the channel is unencrypted.
+pub fn encrypt(packet: &MeshPacket, channel_name: &str, psk: &[u8]) -> Option<MeshPacket> {
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        return None;
+    };
+    let key = expand_psk(psk)?;
+    let ciphertext = apply_keystream(&key, packet.id, packet.from, &data.encode_to_vec());
+    Some(MeshPacket {
+        channel: channel_hash(channel_name, &key),
+        payload_variant: Some(mesh_packet::PayloadVariant::Encrypted(ciphertext)),
+        ..packet.clone()
+    })
+}
+
+/// The AES key a PSK stands for. One-byte PSKs select a variant of the
+/// default key, and short keys are zero-padded as the firmware does. None if
+/// the channel is unencrypted.
+fn expand_psk(psk: &[u8]) -> Option<Vec<u8>> {
+    match psk {
+        [] | [0] => None,
+        [index] => {
+            let mut key = DEFAULT_KEY.to_vec();
+            key[15] = key[15].wrapping_add(index - 1);
+            Some(key)
+        }
+        psk => {
+            let mut key = psk.to_vec();
+            key.resize(if psk.len() <= 16 { 16 } else { 32 }, 0);
+            key.truncate(32);
+            Some(key)
+        }
+    }
+}
+
+/// The one-byte hash a packet carries in place of its channel index: the
+/// XOR of the channel name's bytes and the key's bytes
+fn channel_hash(name: &str, key: &[u8]) -> u32 {
+    let xor = |bytes: &[u8]| bytes.iter().fold(0u8, |hash, byte| hash ^ byte);
+    (xor(name.as_bytes()) ^ xor(key)) as u32
+}
+
+/// AES-CTR with the firmware's nonce: the packet ID and sender, little
+/// endian, followed by a 32-bit block counter
+fn apply_keystream(key: &[u8], packet_id: u32, from: u32, ciphertext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; 16];
+    nonce[..8].copy_from_slice(&(packet_id as u64).to_le_bytes());
+    nonce[8..12].copy_from_slice(&from.to_le_bytes());
+
+    let mut buffer = ciphertext.to_vec();
+    if key.len() == 16 {
+        ctr::Ctr32BE::<Aes128>::new(key.into(), &nonce.into()).apply_keystream(&mut buffer);
+    } else {
+        ctr::Ctr32BE::<Aes256>::new(key.into(), &nonce.into()).apply_keystream(&mut buffer);
+    }
+    buffer
+}
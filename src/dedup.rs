@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// How long a packet ID is remembered
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(600);
+
+// Packets remembered at most; the least recently seen are forgotten first
+const DEDUP_CAPACITY: usize = 1024;
+
+/// Remembers recently seen mesh packets so the same packet, heard by more
+/// than one radio or uploaded by more than one MQTT gateway, is only relayed
+/// once. Packets are keyed by sender and packet ID, which together are
+/// unique on the mesh.
+///
+/// Also remembers the IDs of packets the bridge sent itself, so they aren't
+/// relayed back to IRC when they return through a radio or the MQTT
+/// subscription.
+#[derive(Debug)]
+pub struct PacketDedup {
+    seen: HashMap<(u32, u32), Instant>,
+    sent: HashMap<u32, Instant>,
+    window: Duration,
+}
+
+impl PacketDedup {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            seen: HashMap::new(),
+            sent: HashMap::new(),
+            window,
+        }
+    }
+
+    /// Remember a packet the bridge sent. Only the ID is kept, since a
+    /// gateway may fill in the sender of a packet published over MQTT.
+    pub fn record_sent(&mut self, id: u32) {
+        if id == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let window = self.window;
+        self.sent.retain(|_, sent_at| now.duration_since(*sent_at) < window);
+        self.sent.insert(id, now);
+    }
+
+    /// Whether a packet is one the bridge sent within the window
+    pub fn was_sent(&self, id: u32) -> bool {
+        self.sent.get(&id).is_some_and(|sent_at| sent_at.elapsed() < self.window)
+    }
+
+    /// Record a packet and report whether it was already seen within the window.
+    /// Packets without an ID cannot be told apart and are never duplicates.
+    pub fn is_duplicate(&mut self, from: u32, id: u32) -> bool {
+        if id == 0 {
+            return false;
+        }
+
+        let now = Instant::now();
+        let window = self.window;
+        self.seen.retain(|_, seen_at| now.duration_since(*seen_at) < window);
+
+        let duplicate = self.seen.insert((from, id), now).is_some();
+        if self.seen.len() > DEDUP_CAPACITY {
+            let oldest = self.seen.iter()
+                .min_by_key(|(_, seen_at)| **seen_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.seen.remove(&oldest);
+            }
+        }
+        duplicate
+    }
+}
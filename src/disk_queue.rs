@@ -0,0 +1,66 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// How long to wait for another handler writing to the same file
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages held for one leg of the bridge in an SQLite file, so they survive
+/// a restart or crash. Several legs can share a file.
+#[derive(Debug)]
+pub struct DiskQueue {
+    connection: Mutex<Connection>,  // A Connection can't be shared between threads on its own
+    leg: String,
+}
+
+impl DiskQueue {
+    pub fn open(path: &Path, leg: &str) -> Result<Self> {
+        let connection = Connection::open(path)
+            .map_err(|e| anyhow::anyhow!("Can't open message queue {}: {}", path.display(), e))?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS held (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                leg TEXT NOT NULL,
+                held_since INTEGER NOT NULL,
+                message TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS held_by_leg ON held (leg, id);"
+        ).map_err(|e| anyhow::anyhow!("Can't set up message queue {}: {}", path.display(), e))?;
+        Ok(Self { connection: Mutex::new(connection), leg: leg.to_string() })
+    }
+
+    /// Append a message, held since `held_since` seconds after the epoch
+    pub fn push(&self, held_since: u64, message: &str) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO held (leg, held_since, message) VALUES (?1, ?2, ?3)",
+            params![self.leg, held_since as i64, message],
+        )?;
+        Ok(())
+    }
+
+    /// Drop the oldest messages beyond `max_messages`, returning how many
+    pub fn trim(&self, max_messages: usize) -> Result<usize> {
+        Ok(self.connection.lock().unwrap().execute(
+            "DELETE FROM held WHERE leg = ?1 AND id NOT IN
+                (SELECT id FROM held WHERE leg = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![self.leg, max_messages as i64],
+        )?)
+    }
+
+    /// Remove and return every held message, oldest first
+    pub fn take(&self) -> Result<Vec<(u64, String)>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let messages = {
+            let mut statement = transaction.prepare("SELECT held_since, message FROM held WHERE leg = ?1 ORDER BY id")?;
+            let rows = statement.query_map(params![self.leg], |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        transaction.execute("DELETE FROM held WHERE leg = ?1", params![self.leg])?;
+        transaction.commit()?;
+        Ok(messages)
+    }
+}
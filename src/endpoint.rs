@@ -0,0 +1,173 @@
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use log::debug;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::dedup::PacketDedup;
+use crate::irc_handler::{IrcMessage, MeshMessage};
+use crate::metrics::Metrics;
+use crate::node_db::NodeDb;
+use crate::presence::PresenceTracker;
+
+// Room in the channels between an endpoint and its running handler
+const SESSION_CAPACITY: usize = 100;
+
+/// Whether an endpoint is connected, as reported in the stats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Up,
+    Down,
+}
+
+impl Health {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Health::Up => "up",
+            Health::Down => "down",
+        }
+    }
+}
+
+/// Somewhere the bridge passes messages to and from: IRC, a radio or an MQTT
+/// broker. The bridge connects each endpoint, restarts it when it fails and
+/// moves what one hears to the others.
+pub trait ChatEndpoint: Send {
+    /// What the endpoint is given to deliver
+    type Incoming: Send;
+    /// What the endpoint hears
+    type Outgoing: Send;
+
+    /// Names the endpoint in logs and outage notices, e.g. "Radio base"
+    fn name(&self) -> String;
+
+    /// Connect, replacing any earlier connection
+    fn connect(&mut self) -> BoxFuture<'_, Result<()>>;
+
+    /// The next message heard. None once the endpoint has stopped cleanly, an
+    /// error once the connection has failed.
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<Self::Outgoing>>>;
+
+    /// Deliver a message, which fails when the endpoint isn't connected
+    fn send(&mut self, message: Self::Incoming) -> BoxFuture<'_, Result<()>>;
+
+    fn health(&self) -> Health;
+}
+
+/// IRC, which delivers messages from the mesh and hears ones for it
+pub type IrcLeg = dyn ChatEndpoint<Incoming = MeshMessage, Outgoing = IrcMessage>;
+
+/// A radio or MQTT broker, which delivers messages from IRC and hears ones
+/// for it
+pub type MeshLeg = dyn ChatEndpoint<Incoming = IrcMessage, Outgoing = MeshMessage>;
+
+/// What the radios and MQTT brokers share, so a packet heard by several of
+/// them is relayed once
+#[derive(Clone)]
+pub struct MeshState {
+    pub dedup: Arc<Mutex<PacketDedup>>,
+    pub presence: Arc<Mutex<PresenceTracker>>,
+    pub node_db: Arc<Mutex<NodeDb>>,
+    pub metrics: Metrics,
+}
+
+/// A handler's run loop going in a task of its own, with the channels to and
+/// from it
+struct Running<I, O> {
+    input: mpsc::Sender<I>,
+    output: mpsc::Receiver<O>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl<I, O> Drop for Running<I, O> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The connection of an endpoint built on one of the handlers, which run
+/// their own loop over a channel in and a channel out
+pub struct Session<I, O> {
+    running: Option<Running<I, O>>,
+}
+
+impl<I, O> Default for Session<I, O> {
+    fn default() -> Self {
+        Self { running: None }
+    }
+}
+
+impl<I: Send + 'static, O: Send + 'static> Session<I, O> {
+    /// Start a connected handler's run loop, stopping the one before
+    pub fn start<F, Fut>(&mut self, run: F)
+    where
+        F: FnOnce(mpsc::Receiver<I>, mpsc::Sender<O>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let (input, input_rx) = mpsc::channel(SESSION_CAPACITY);
+        let (output_tx, output) = mpsc::channel(SESSION_CAPACITY);
+        let task = tokio::spawn(run(input_rx, output_tx));
+        self.running = Some(Running { input, output, task });
+    }
+
+    pub async fn recv(&mut self) -> Result<Option<O>> {
+        let Some(running) = &mut self.running else {
+            return Err(anyhow::anyhow!("Not connected"));
+        };
+        let result = tokio::select! {
+            biased;
+            Some(message) = running.output.recv() => return Ok(Some(message)),
+            result = &mut running.task => result,
+        };
+        self.running = None;
+        match result {
+            Ok(result) => result.map(|()| None),
+            Err(e) => Err(anyhow::anyhow!("Handler stopped unexpectedly: {}", e)),
+        }
+    }
+
+    pub async fn send(&self, message: I) -> Result<()> {
+        let Some(running) = &self.running else {
+            return Err(anyhow::anyhow!("Not connected"));
+        };
+        running.input.send(message).await.map_err(|_| anyhow::anyhow!("Not connected"))
+    }
+
+    pub fn health(&self) -> Health {
+        match &self.running {
+            Some(running) if !running.task.is_finished() => Health::Up,
+            _ => Health::Down,
+        }
+    }
+}
+
+/// Connect an endpoint and pass messages through it until it fails, or stops
+/// cleanly
+pub async fn serve<E: ChatEndpoint + ?Sized>(
+    endpoint: &mut E,
+    mut input: mpsc::Receiver<E::Incoming>,
+    output: &mpsc::Sender<E::Outgoing>,
+    metrics: &Metrics,
+) -> Result<()> {
+    endpoint.connect().await?;
+    metrics.record_endpoint(&endpoint.name(), endpoint.health());
+    loop {
+        tokio::select! {
+            heard = endpoint.recv() => {
+                let Some(message) = heard? else {
+                    return Ok(());
+                };
+                if output.send(message).await.is_err() {
+                    debug!("Nowhere to pass on what {} heard", endpoint.name());
+                }
+            }
+            Some(message) = input.recv() => {
+                if let Err(e) = endpoint.send(message).await {
+                    debug!("Dropping a message for {}: {}", endpoint.name(), e);
+                }
+            }
+        }
+    }
+}
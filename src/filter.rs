@@ -0,0 +1,73 @@
+use anyhow::Result;
+use regex::Regex;
+use std::borrow::Cow;
+
+use crate::config::{FilterConfig, FilterRules, TooLongAction};
+
+/// One direction's rules, with the regexes compiled
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    allow: Vec<Regex>,
+    deny: Vec<Regex>,
+    max_length: Option<usize>,
+    too_long: TooLongAction,
+}
+
+impl Filter {
+    pub fn compile(setting: &str, rules: &FilterRules) -> Result<Self> {
+        let compile_all = |list: &str, patterns: &[String]| {
+            patterns.iter()
+                .map(|pattern| Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("{}.{} has an invalid regex \"{}\": {}", setting, list, pattern, e)))
+                .collect::<Result<Vec<_>>>()
+        };
+        if rules.max_length == Some(0) {
+            return Err(anyhow::anyhow!("{}.max_length must be at least 1", setting));
+        }
+        Ok(Self {
+            allow: compile_all("allow", &rules.allow)?,
+            deny: compile_all("deny", &rules.deny)?,
+            max_length: rules.max_length,
+            too_long: rules.too_long,
+        })
+    }
+
+    /// The text as it should be bridged, or None if it is dropped
+    pub fn apply<'a>(&self, text: &'a str) -> Option<Cow<'a, str>> {
+        if !self.allow.is_empty() && !self.allow.iter().any(|regex| regex.is_match(text)) {
+            return None;
+        }
+        if self.deny.iter().any(|regex| regex.is_match(text)) {
+            return None;
+        }
+        match self.max_length {
+            Some(max_length) if text.chars().count() > max_length => match self.too_long {
+                TooLongAction::Drop => None,
+                TooLongAction::Truncate => {
+                    let kept: String = text.chars().take(max_length - 1).collect();
+                    Some(Cow::Owned(kept + "…"))
+                }
+            },
+            _ => Some(Cow::Borrowed(text)),
+        }
+    }
+}
+
+/// The filters for both directions, shared by the IRC, radio and MQTT
+/// handlers
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    pub irc_to_mesh: Filter,
+    pub mesh_to_irc: Filter,
+}
+
+impl TryFrom<&FilterConfig> for Filters {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &FilterConfig) -> Result<Self> {
+        Ok(Self {
+            irc_to_mesh: Filter::compile("filters.irc_to_mesh", &config.irc_to_mesh)?,
+            mesh_to_irc: Filter::compile("filters.mesh_to_irc", &config.mesh_to_irc)?,
+        })
+    }
+}
@@ -1,33 +1,349 @@
 use anyhow::Result;
 use irc::client::prelude::*;
 use log::{debug, error, info};
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use futures_util::future::BoxFuture;
 use futures_util::StreamExt;
+use std::time::{Duration, Instant};
 
-use crate::config::IrcConfig;
+use crate::backlog::Backlog;
+use crate::backoff::Backoff;
+use crate::config::{self, IrcConfig, ProxyConfig, ReconnectConfig};
+use crate::endpoint::{ChatEndpoint, Health, Session};
+use crate::filter::Filters;
+use crate::schedule::QuietHours;
+use crate::proxy::{self, ForwardTls};
+use crate::metrics::{format_duration, Metrics};
+
+// How often we send our own PING to measure server lag
+const LAG_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+// What messages held for IRC are filed under in a backlog file
+pub const IRC_LEG: &str = "irc";
 
 pub struct IrcHandler {
     client: Client,
-    channel: String,
+    config: IrcConfig,
+    proxy: Option<ProxyConfig>,
+    nickname: Option<String>,  // Set by a nick change on a reload, which the client doesn't track
+    extra_channels: Vec<String>,  // Joined for mesh channels routed away from the main one
+    filters: Filters,
+    quiet_hours: QuietHours,
+    reconnect: ReconnectConfig,
+    metrics: Metrics,
+    lag_probe: Option<(String, Instant)>,
+    ready: bool,  // Registered and joined, so messages can be posted
+    backlog: Backlog<MeshMessage>,  // Messages from the mesh held until ready
 }
 
-#[derive(Debug, Clone)]
+/// IRC as an endpoint of the bridge, connected with the current config
+pub struct IrcEndpoint {
+    reload: watch::Receiver<config::Config>,
+    metrics: Metrics,
+    session: Session<MeshMessage, IrcMessage>,
+}
+
+impl IrcEndpoint {
+    pub fn new(reload: watch::Receiver<config::Config>, metrics: Metrics) -> Self {
+        Self { reload, metrics, session: Session::default() }
+    }
+}
+
+impl ChatEndpoint for IrcEndpoint {
+    type Incoming = MeshMessage;
+    type Outgoing = IrcMessage;
+
+    fn name(&self) -> String {
+        "IRC".to_string()
+    }
+
+    fn connect(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // Marked as seen, so the handler only applies later reloads
+            let config = self.reload.borrow_and_update().clone();
+            info!("Initializing IRC connection...");
+            let handler = IrcHandler::new(
+                &config.irc,
+                config.meshtastic.irc_channels(),
+                Filters::try_from(&config.meshtastic.filters)?,
+                QuietHours::try_from(&config.meshtastic)?,
+                config.meshtastic.reconnect.clone(),
+                config.proxy.as_ref(),
+                self.metrics.clone(),
+            ).await.map_err(|e| anyhow::anyhow!("Failed to initialize IRC handler: {}", e))?;
+            info!("IRC handler initialized successfully");
+            info!("Starting IRC message handler loop");
+            let reload = self.reload.clone();
+            self.session.start(|from_mesh, to_mesh| handler.run(from_mesh, to_mesh, reload));
+            Ok(())
+        })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<IrcMessage>>> {
+        Box::pin(self.session.recv())
+    }
+
+    fn send(&mut self, message: MeshMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.session.send(message))
+    }
+
+    fn health(&self) -> Health {
+        self.session.health()
+    }
+}
+
+/// A message from IRC to the mesh. `channel` is the IRC channel it was said
+/// in, or None for the main channel. `destination` is set for direct
+/// messages and holds the node's short name or `!id`. `mesh_channel` picks
+/// the mesh channel index to send on, instead of the channels mapped to
+/// `channel`. `command` is set for bridge commands the mesh side answers
+/// instead of relaying.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IrcMessage {
     pub sender: String,
     pub content: String,
+    pub channel: Option<String>,
+    pub destination: Option<String>,
+    pub mesh_channel: Option<u32>,
+    #[serde(skip)]
+    pub command: Option<MeshCommand>,
+}
+
+/// Bridge commands answered from the mesh handler's state
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshCommand {
+    Telemetry { node: String },
+    Environment { node: String },
+    Traceroute { node: String },
+    History { minutes: Option<u32> },
+    Topology { node: Option<String> },
+    Stats { node: String },
+    Admin(AdminCommand),
+}
+
+/// Administration of the bridge's own radio, restricted to IRC admins
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Reboot { seconds: i32 },
+    SetOwner { short_name: String, long_name: String },
+    GetConfig { section: String },
+    SetChannelName { index: u32, name: String },
+    ExportNodes,
+}
+
+// Seconds before the radio reboots when !admin reboot is given no delay
+const DEFAULT_REBOOT_DELAY_SECS: i32 = 5;
+
+const ADMIN_USAGE: &str =
+    "Usage: !admin reboot [seconds] | owner <short> <long name> | config <section> | channel <index> <name>";
+
+impl AdminCommand {
+    fn parse(args: &str) -> std::result::Result<Self, &'static str> {
+        let mut words = args.split_whitespace();
+        match words.next() {
+            Some("reboot") => match words.next() {
+                None => Ok(AdminCommand::Reboot { seconds: DEFAULT_REBOOT_DELAY_SECS }),
+                Some(seconds) => seconds.parse()
+                    .map(|seconds| AdminCommand::Reboot { seconds })
+                    .map_err(|_| "Usage: !admin reboot [seconds]"),
+            },
+            Some("owner") => {
+                let short_name = words.next().ok_or("Usage: !admin owner <short> <long name>")?;
+                let long_name = words.collect::<Vec<_>>().join(" ");
+                if long_name.is_empty() {
+                    return Err("Usage: !admin owner <short> <long name>");
+                }
+                Ok(AdminCommand::SetOwner { short_name: short_name.to_string(), long_name })
+            }
+            Some("config") => match words.next() {
+                Some(section) => Ok(AdminCommand::GetConfig { section: section.to_string() }),
+                None => Err("Usage: !admin config <device|position|power|network|display|lora|bluetooth|security>"),
+            },
+            Some("channel") => {
+                let index = words.next().and_then(|index| index.parse().ok());
+                let name = words.collect::<Vec<_>>().join(" ");
+                match index {
+                    Some(index) if !name.is_empty() => Ok(AdminCommand::SetChannelName { index, name }),
+                    _ => Err("Usage: !admin channel <index> <name>"),
+                }
+            }
+            _ => Err(ADMIN_USAGE),
+        }
+    }
+}
+
+impl MeshCommand {
+    /// The node the command is about, if any
+    pub fn node(&self) -> Option<&str> {
+        match self {
+            MeshCommand::Telemetry { node }
+            | MeshCommand::Environment { node }
+            | MeshCommand::Traceroute { node }
+            | MeshCommand::Stats { node } => Some(node),
+            MeshCommand::Topology { node } => node.as_deref(),
+            MeshCommand::History { .. } | MeshCommand::Admin(_) => None,
+        }
+    }
+
+    /// Parse a command, returning a usage message if it is malformed
+    fn parse(content: &str) -> Option<std::result::Result<Self, &'static str>> {
+        let mut words = content.split_whitespace();
+        let command = words.next()?;
+        let arg = words.next();
+        match command {
+            "!telemetry" => Some(match arg {
+                Some(node) => Ok(MeshCommand::Telemetry { node: node.to_string() }),
+                None => Err("Usage: !telemetry <node>"),
+            }),
+            "!env" => Some(match arg {
+                Some(node) => Ok(MeshCommand::Environment { node: node.to_string() }),
+                None => Err("Usage: !env <node>"),
+            }),
+            "!export" => Some(match arg {
+                Some("nodes") => Ok(MeshCommand::Admin(AdminCommand::ExportNodes)),
+                _ => Err("Usage: !export nodes"),
+            }),
+            "!admin" => Some(AdminCommand::parse(content.trim()["!admin".len()..].trim()).map(MeshCommand::Admin)),
+            "!traceroute" => Some(match arg {
+                Some(node) => Ok(MeshCommand::Traceroute { node: node.to_string() }),
+                None => Err("Usage: !traceroute <node>"),
+            }),
+            "!topology" => Some(Ok(MeshCommand::Topology { node: arg.map(str::to_string) })),
+            "!stats" => Some(match arg {
+                Some(node) => Ok(MeshCommand::Stats { node: node.to_string() }),
+                None => Err("Usage: !stats <node>"),
+            }),
+            "!history" => Some(match arg.map(str::parse) {
+                None => Ok(MeshCommand::History { minutes: None }),
+                Some(Ok(minutes)) => Ok(MeshCommand::History { minutes: Some(minutes) }),
+                Some(Err(_)) => Err("Usage: !history [minutes]"),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A message from the mesh to IRC. `channel` is the IRC channel to post it
+/// to, or None for the main channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeshMessage {
+    pub content: String,
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub chat: Option<Chat>, // None for the bridge's own notices and replies, which are only for IRC
+}
+
+/// Chat relayed from the mesh as it was said, before it was laid out for IRC,
+/// so links can pass it on to other radios and brokers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chat {
+    pub sender: String,
+    pub text: String,
+}
+
+impl MeshMessage {
+    pub fn new(content: String) -> Self {
+        Self {
+            content,
+            channel: None,
+            chat: None,
+        }
+    }
 }
 
 impl IrcHandler {
-    pub async fn new(config: &IrcConfig) -> Result<Self> {
+    pub async fn new(
+        config: &IrcConfig,
+        extra_channels: Vec<String>,
+        filters: Filters,
+        quiet_hours: QuietHours,
+        reconnect: ReconnectConfig,
+        proxy: Option<&ProxyConfig>,
+        metrics: Metrics,
+    ) -> Result<Self> {
+        let client = Self::connect(config, &extra_channels, proxy).await?;
+        metrics.record_irc_connect();
+
+        Ok(Self {
+            client,
+            config: config.clone(),
+            proxy: proxy.cloned(),
+            nickname: None,
+            extra_channels,
+            filters,
+            quiet_hours,
+            reconnect,
+            metrics,
+            lag_probe: None,
+            ready: false,
+            backlog: Backlog::new(&config.buffer, IRC_LEG)?,
+        })
+    }
+
+    /// Connect to the server again after losing the connection, backing off
+    /// between attempts. Messages from the mesh meanwhile are held in the
+    /// backlog rather than left to hold up the radios. Fails once
+    /// reconnect.max_attempts have failed.
+    async fn reconnect(&mut self, from_meshtastic: &mut mpsc::Receiver<MeshMessage>) -> Result<()> {
+        let mut backoff = Backoff::new(&self.reconnect);
+        while let Some(delay) = backoff.next_delay() {
+            info!("Reconnecting to IRC server {}:{} in {:?}", self.config.server, self.config.port, delay);
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    Some(message) = from_meshtastic.recv() => {
+                        debug!("Holding message for IRC while disconnected: {}", message.content);
+                        self.backlog.push(message);
+                    }
+                }
+            }
+            match Self::connect(&self.config, &self.extra_channels, self.proxy.as_ref()).await {
+                Ok(client) => {
+                    self.client = client;
+                    self.nickname = None;
+                    self.lag_probe = None;
+                    self.ready = false;
+                    self.metrics.record_irc_connect();
+                    return Ok(());
+                }
+                Err(e) => error!("Failed to reconnect to IRC: {}", e),
+            }
+        }
+        Err(anyhow::anyhow!("Gave up reconnecting to IRC after {} attempts", backoff.attempts()))
+    }
+
+    /// Connect and register, joining the main channel and `extra_channels`
+    async fn connect(config: &IrcConfig, extra_channels: &[String], proxy: Option<&ProxyConfig>) -> Result<Client> {
+        let mut channels = vec![config.channel.clone()];
+        channels.extend(extra_channels.iter().cloned());
+        
+        // Through a proxy the client connects to a local forwarder, which
+        // does the TLS so the server's certificate is checked against its name
+        let (server, port, use_tls) = match proxy {
+            Some(proxy) => {
+                let tls = if config.use_tls {
+                    Some(ForwardTls::Native(native_tls::TlsConnector::new()?.into()))
+                } else {
+                    None
+                };
+                let local = proxy::forward(proxy.clone(), config.server.clone(), config.port, tls).await?;
+                (local.ip().to_string(), local.port(), false)
+            }
+            None => (config.server.clone(), config.port, config.use_tls),
+        };
+        
         let irc_config = Config {
             nickname: Some(config.nickname.clone()),
             username: config.username.clone(),
             realname: config.realname.clone(),
-            server: Some(config.server.clone()),
-            port: Some(config.port),
-            channels: vec![config.channel.clone()],
+            server: Some(server),
+            port: Some(port),
+            channels: channels.clone(),
             password: config.password.clone(),
-            use_tls: Some(config.use_tls),
+            use_tls: Some(use_tls),
             ..Config::default()
         };
 
@@ -38,20 +354,72 @@ impl IrcHandler {
         client.identify()?;
 
         info!("Connected to IRC server: {}:{}", config.server, config.port);
-        info!("Joining channel: {}", config.channel);
+        info!("Joining channels: {}", channels.join(", "));
+        Ok(client)
+    }
 
-        Ok(Self {
-            client,
-            channel: config.channel.clone(),
-        })
+    /// Apply a reloaded config. The nick, channels and admins change on the
+    /// live connection, other server settings need a new one. Returns
+    /// whether the client was replaced.
+    async fn apply_config(&mut self, config: &config::Config) -> Result<bool> {
+        let irc = &config.irc;
+        let extra_channels = config.meshtastic.irc_channels();
+        self.filters = Filters::try_from(&config.meshtastic.filters)?;
+        self.quiet_hours = QuietHours::try_from(&config.meshtastic)?;
+        self.reconnect = config.meshtastic.reconnect.clone();
+        self.backlog.configure(&irc.buffer);
+        let reconnect = irc.server != self.config.server
+            || irc.port != self.config.port
+            || irc.use_tls != self.config.use_tls
+            || irc.username != self.config.username
+            || irc.realname != self.config.realname
+            || irc.password != self.config.password
+            || config.proxy != self.proxy;
+        
+        if reconnect {
+            info!("IRC server settings changed, reconnecting to {}:{}", irc.server, irc.port);
+            // The old connection stays up if the new one can't be made
+            let client = Self::connect(irc, &extra_channels, config.proxy.as_ref()).await?;
+            let _ = self.client.send_quit("Reconnecting");
+            self.client = client;
+            self.nickname = None;
+            self.ready = false;
+            self.metrics.record_irc_connect();
+        } else {
+            if irc.nickname != self.config.nickname {
+                info!("Changing IRC nick to {}", irc.nickname);
+                self.client.send(Command::NICK(irc.nickname.clone()))?;
+            }
+            let joined: Vec<&String> = std::iter::once(&self.config.channel).chain(&self.extra_channels).collect();
+            let wanted: Vec<&String> = std::iter::once(&irc.channel).chain(&extra_channels).collect();
+            for channel in wanted.iter().filter(|channel| !joined.contains(channel)) {
+                info!("Joining {}", channel);
+                self.client.send_join(channel)?;
+            }
+            for channel in joined.iter().filter(|channel| !wanted.contains(channel)) {
+                info!("Leaving {}", channel);
+                self.client.send_part(channel)?;
+            }
+        }
+        
+        self.config = irc.clone();
+        self.proxy = config.proxy.clone();
+        self.extra_channels = extra_channels;
+        Ok(reconnect)
+    }
+
+    fn current_nickname(&self) -> &str {
+        self.nickname.as_deref().unwrap_or_else(|| self.client.current_nickname())
     }
 
     pub async fn run(
         mut self,
-        mut from_meshtastic: mpsc::Receiver<String>,
+        mut from_meshtastic: mpsc::Receiver<MeshMessage>,
         to_meshtastic: mpsc::Sender<IrcMessage>,
+        mut reload: watch::Receiver<config::Config>,
     ) -> Result<()> {
         let mut stream = self.client.stream()?;
+        let mut lag_timer = tokio::time::interval(LAG_PROBE_INTERVAL);
         info!("IRC handler run loop started");
 
         loop {
@@ -59,56 +427,140 @@ impl IrcHandler {
                 result = stream.next() => {
                     if let Some(Ok(message)) = result {
                         debug!("Received IRC message: {:?}", message);
+                        self.metrics.record_irc_message();
                         if let Err(e) = self.handle_irc_message(message, &to_meshtastic).await {
                             error!("Error handling IRC message: {}", e);
                         }
                     } else if result.is_none() {
                         error!("IRC stream ended");
-                        break;
+                        self.metrics.record_irc_disconnect();
+                        self.reconnect(&mut from_meshtastic).await?;
+                        stream = self.client.stream()?;
                     }
                 }
                 Some(message) = from_meshtastic.recv() => {
-                    info!("Received message from Meshtastic to send to IRC: {}", message);
-                    if let Err(e) = self.send_to_irc(&message).await {
+                    info!("Received message from Meshtastic to send to IRC: {}", message.content);
+                    if !self.ready {
+                        self.backlog.push(message);
+                    } else if let Err(e) = self.send_to_irc(&message).await {
                         error!("Error sending to IRC: {}", e);
                     }
                 }
+                _ = lag_timer.tick() => {
+                    if let Err(e) = self.send_lag_probe() {
+                        error!("Error sending lag probe: {}", e);
+                    }
+                }
+                Ok(()) = reload.changed() => {
+                    let config = reload.borrow_and_update().clone();
+                    match self.apply_config(&config).await {
+                        Ok(true) => stream = self.client.stream()?,
+                        Ok(false) => {}
+                        Err(e) => error!("Error applying the reloaded IRC config: {}", e),
+                    }
+                }
                 else => {
                     debug!("No messages in either channel");
                     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                 }
             }
         }
-        
-        error!("IRC handler run loop ended");
-        Ok(())
     }
 
     async fn handle_irc_message(
-        &self,
+        &mut self,
         message: Message,
         to_meshtastic: &mpsc::Sender<IrcMessage>,
     ) -> Result<()> {
         match message.command {
             Command::PRIVMSG(target, content) => {
-                if target == self.channel {
-                    if let Some(Prefix::Nickname(nick, _, _)) = message.prefix {
+                if target == self.config.channel || self.extra_channels.contains(&target) {
+                    if let Some(Prefix::Nickname(nick, user, host)) = message.prefix {
                         // Ignore our own messages to prevent loops
-                        if nick == self.client.current_nickname() {
+                        if nick == self.current_nickname() {
                             debug!("Ignoring own message");
                             return Ok(());
                         }
                         
+                        if self.handle_command(&target, &content)? {
+                            return Ok(());
+                        }
+
+                        let command = match MeshCommand::parse(&content) {
+                            Some(Ok(MeshCommand::Admin(_))) if !self.is_admin(&nick, &user, &host) => {
+                                info!("Refused admin command from {}!{}@{}", nick, user, host);
+                                self.client.send_privmsg(&target, "Permission denied")?;
+                                return Ok(());
+                            }
+                            Some(Ok(command)) => Some(command),
+                            Some(Err(usage)) => {
+                                self.client.send_privmsg(&target, usage)?;
+                                return Ok(());
+                            }
+                            None => None,
+                        };
+
+                        // !dm <node> <message> sends a direct message to one node
+                        let (destination, content) = match content.strip_prefix("!dm ") {
+                            Some(rest) => match rest.trim().split_once(' ') {
+                                Some((node, text)) if !text.trim().is_empty() => {
+                                    (Some(node.to_string()), text.trim().to_string())
+                                }
+                                _ => {
+                                    self.client.send_privmsg(&target, "Usage: !dm <node> <message>")?;
+                                    return Ok(());
+                                }
+                            },
+                            None => (None, content),
+                        };
+
+                        // !ch <index> <message> sends on one mesh channel
+                        let (mesh_channel, content) = match content.strip_prefix("!ch ") {
+                            Some(rest) if destination.is_none() => {
+                                let parsed = rest.trim().split_once(' ')
+                                    .and_then(|(index, text)| Some((index.parse::<u32>().ok()?, text.trim())));
+                                match parsed {
+                                    Some((index, text)) if !text.is_empty() => (Some(index), text.to_string()),
+                                    _ => {
+                                        self.client.send_privmsg(&target, "Usage: !ch <channel index> <message>")?;
+                                        return Ok(());
+                                    }
+                                }
+                            }
+                            _ => (None, content),
+                        };
+
+                        if let (None, Some(until)) = (&command, self.quiet_hours.quiet_until()) {
+                            info!("Quiet hours, not sending IRC message from {} to the mesh", nick);
+                            self.client.send_notice(&nick, format!("Quiet hours until {}: messages aren't sent to the mesh", until))?;
+                            return Ok(());
+                        }
+                        let content = if command.is_some() {
+                            content
+                        } else if let Some(filtered) = self.filters.irc_to_mesh.apply(&content) {
+                            filtered.into_owned()
+                        } else {
+                            info!("Filtered out IRC message from {}: {}", nick, content);
+                            self.metrics.record_filtered();
+                            self.client.send_notice(&nick, "Not sent to the mesh: the message doesn't pass the bridge's filters")?;
+                            return Ok(());
+                        };
+
                         info!("IRC message from {}: {}", nick, content);
                         
                         let irc_msg = IrcMessage {
                             sender: nick,
                             content,
+                            channel: (target != self.config.channel).then_some(target),
+                            destination,
+                            mesh_channel,
+                            command,
                         };
                         
                         match to_meshtastic.send(irc_msg).await {
                             Ok(_) => {
                                 info!("Successfully sent IRC message to Meshtastic channel");
+                                self.metrics.record_relayed_to_mesh();
                             }
                             Err(e) => {
                                 error!("Failed to send to Meshtastic channel: {}", e);
@@ -120,7 +572,18 @@ impl IrcHandler {
             }
             Command::Response(Response::RPL_ENDOFMOTD, _) |
             Command::Response(Response::ERR_NOMOTD, _) => {
-                info!("IRC connection ready - fully connected to {}", self.channel);
+                info!("IRC connection ready - fully connected to {}", self.config.channel);
+                // The channels are joined first, so what was held can be posted
+                self.ready = true;
+                let held = self.backlog.drain();
+                if !held.is_empty() {
+                    info!("Replaying {} messages held while IRC was down", held.len());
+                }
+                for message in held {
+                    if let Err(e) = self.send_to_irc(&message).await {
+                        error!("Error sending to IRC: {}", e);
+                    }
+                }
             }
             Command::PING(server1, server2) => {
                 // Respond to PING to keep connection alive
@@ -130,9 +593,27 @@ impl IrcHandler {
                     self.client.send_pong(&server2)?;
                 }
             }
+            Command::PONG(_, Some(token)) => {
+                if let Some((probe, sent_at)) = &self.lag_probe {
+                    if *probe == token {
+                        let lag = sent_at.elapsed();
+                        debug!("IRC lag measured at {} ms", lag.as_millis());
+                        self.metrics.record_irc_lag(lag);
+                        self.lag_probe = None;
+                    }
+                }
+            }
+            Command::NICK(new_nick) => {
+                if let Some(Prefix::Nickname(nick, _, _)) = message.prefix {
+                    if nick == self.current_nickname() {
+                        info!("IRC nick is now {}", new_nick);
+                        self.nickname = Some(new_nick);
+                    }
+                }
+            }
             Command::JOIN(channel, _, _) => {
                 if let Some(Prefix::Nickname(nick, _, _)) = message.prefix {
-                    if nick == self.client.current_nickname() {
+                    if nick == self.current_nickname() {
                         info!("Successfully joined {}", channel);
                     }
                 }
@@ -150,10 +631,95 @@ impl IrcHandler {
         Ok(())
     }
 
-    async fn send_to_irc(&self, message: &str) -> Result<()> {
-        info!("Sending to IRC channel {}: {}", self.channel, message);
-        self.client.send_privmsg(&self.channel, message)?;
+    /// Answer bridge commands addressed to the channel. Returns true if the
+    /// message was a command and should not be relayed.
+    fn handle_command(&self, target: &str, content: &str) -> Result<bool> {
+        let irc = self.metrics.irc();
+        let reply = match content.trim() {
+            "!lag" => match irc.lag {
+                Some(lag) => format!("IRC lag: {} ms", lag.as_millis()),
+                None => "IRC lag: not measured yet".to_string(),
+            },
+            "!uptime" => {
+                let connected = irc.connected_at
+                    .map(|t| format_duration(t.elapsed()))
+                    .unwrap_or_else(|| "never".to_string());
+                let last_message = irc.last_message_at
+                    .map(|t| format!("{} ago", format_duration(t.elapsed())))
+                    .unwrap_or_else(|| "never".to_string());
+                let mut reply = format!("Bridge up {}, IRC connected {}, reconnects {}, last IRC message {}",
+                                        format_duration(self.metrics.uptime()), connected,
+                                        irc.reconnects, last_message);
+                let mesh = self.metrics.mesh();
+                if mesh.dropped_unlisted > 0 {
+                    reply.push_str(&format!(", dropped {} messages of unlisted nodes", mesh.dropped_unlisted));
+                }
+                if mesh.filtered > 0 {
+                    reply.push_str(&format!(", filtered out {} messages", mesh.filtered));
+                }
+                if mesh.publish_dropped > 0 {
+                    reply.push_str(&format!(", dropped {} messages over the MQTT publish limit", mesh.publish_dropped));
+                }
+                if mesh.duplicates > 0 {
+                    reply.push_str(&format!(", suppressed {} duplicate packets", mesh.duplicates));
+                }
+                reply
+            }
+            _ => return Ok(false),
+        };
+
+        self.client.send_privmsg(target, reply)?;
+        Ok(true)
+    }
+
+    fn is_admin(&self, nick: &str, user: &str, host: &str) -> bool {
+        let hostmask = format!("{}!{}@{}", nick, user, host);
+        self.config.admins.iter().any(|mask| mask_matches(mask, &hostmask))
+    }
+
+    fn send_lag_probe(&mut self) -> Result<()> {
+        let token = format!("lag-{}", std::process::id());
+        self.client.send(Command::PING(token.clone(), None))?;
+        self.lag_probe = Some((token, Instant::now()));
+        Ok(())
+    }
+
+    async fn send_to_irc(&self, message: &MeshMessage) -> Result<()> {
+        let channel = message.channel.as_deref().unwrap_or(&self.config.channel);
+        info!("Sending to IRC channel {}: {}", channel, message.content);
+        // A PRIVMSG can't span lines, so longer replies go out line by line
+        for line in message.content.lines() {
+            self.client.send_privmsg(channel, line)?;
+        }
         info!("Successfully sent to IRC");
+        self.metrics.record_relayed_to_irc();
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Case-insensitive wildcard match, e.g. of IRC hostmasks, where `*` matches
+/// any run of characters and `?` any single character
+pub fn mask_matches(mask: &str, hostmask: &str) -> bool {
+    let mask: Vec<char> = mask.to_lowercase().chars().collect();
+    let hostmask: Vec<char> = hostmask.to_lowercase().chars().collect();
+
+    // Classic wildcard matching, backtracking to the last `*`
+    let (mut m, mut h) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while h < hostmask.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == hostmask[h]) {
+            m += 1;
+            h += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            star = Some((m, h));
+            m += 1;
+        } else if let Some((star_m, star_h)) = star {
+            m = star_m + 1;
+            h = star_h + 1;
+            star = Some((star_m, star_h + 1));
+        } else {
+            return false;
+        }
+    }
+    mask[m..].iter().all(|c| *c == '*')
+}
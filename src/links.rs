@@ -0,0 +1,148 @@
+use anyhow::Result;
+
+use crate::config::{Config, LinkDirection};
+use crate::filter::Filter;
+use crate::irc_handler::{IrcMessage, MeshMessage, IRC_LEG};
+use crate::meshtastic_handler::radio_leg;
+use crate::mqtt_handler::mqtt_leg;
+
+/// A message heard by an endpoint, on its way to the endpoints linked to it
+pub enum Heard {
+    Irc(IrcMessage),
+    Mesh(MeshMessage),
+}
+
+impl From<IrcMessage> for Heard {
+    fn from(message: IrcMessage) -> Self {
+        Heard::Irc(message)
+    }
+}
+
+impl From<MeshMessage> for Heard {
+    fn from(message: MeshMessage) -> Self {
+        Heard::Mesh(message)
+    }
+}
+
+impl Heard {
+    /// The IRC channel it was said in or is for, None for the main one
+    pub fn irc_channel(&self) -> Option<&str> {
+        match self {
+            Heard::Irc(message) => message.channel.as_deref(),
+            Heard::Mesh(message) => message.channel.as_deref(),
+        }
+    }
+}
+
+/// One link, with its filter compiled
+#[derive(Debug)]
+struct Link {
+    from: String,
+    to: String,
+    irc_channel: Option<String>,
+    both_ways: bool,
+    filter: Filter,
+}
+
+impl Link {
+    /// The endpoint this link carries messages to from `from`, if any
+    fn target(&self, from: &str) -> Option<&str> {
+        if self.from == from {
+            Some(&self.to)
+        } else if self.both_ways && self.to == from {
+            Some(&self.from)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where each endpoint's messages go, from the config's links. Without
+/// links, IRC and every radio and broker pass messages both ways, and the
+/// radios and brokers don't pass them among themselves.
+#[derive(Debug)]
+pub struct Links {
+    links: Vec<Link>,
+    main_channel: String,
+}
+
+impl Links {
+    pub fn new(config: &Config) -> Result<Self> {
+        let endpoints = endpoint_names(config);
+        let mut links = Vec::new();
+        if config.links.is_empty() {
+            for endpoint in endpoints.iter().filter(|endpoint| *endpoint != IRC_LEG) {
+                links.push(Link {
+                    from: IRC_LEG.to_string(),
+                    to: endpoint.clone(),
+                    irc_channel: None,
+                    both_ways: true,
+                    filter: Filter::default(),
+                });
+            }
+        }
+        for (index, link) in config.links.iter().enumerate() {
+            for end in [&link.from, &link.to] {
+                if !endpoints.contains(end) {
+                    return Err(anyhow::anyhow!(
+                        "links[{}] names no endpoint \"{}\", expected one of \"{}\"",
+                        index, end, endpoints.join("\", \"")
+                    ));
+                }
+            }
+            if link.from == link.to {
+                return Err(anyhow::anyhow!("links[{}] links {} to itself", index, link.from));
+            }
+            if link.irc_channel.as_ref().is_some_and(|irc_channel| !irc_channel.starts_with('#')) {
+                return Err(anyhow::anyhow!("links[{}].irc_channel must start with '#'", index));
+            }
+            links.push(Link {
+                from: link.from.clone(),
+                to: link.to.clone(),
+                irc_channel: link.irc_channel.clone(),
+                both_ways: link.direction == LinkDirection::Both,
+                filter: Filter::compile(&format!("links[{}].filters", index), &link.filters)?,
+            });
+        }
+        Ok(Self { links, main_channel: config.irc.channel.clone() })
+    }
+
+    /// The endpoints a message heard by `from` goes to, each with the filter
+    /// of the first link carrying it there
+    pub fn targets(&self, from: &str, heard: &Heard) -> Vec<(&str, &Filter)> {
+        let irc_channel = heard.irc_channel().unwrap_or(&self.main_channel);
+        let mut targets: Vec<(&str, &Filter)> = Vec::new();
+        for link in &self.links {
+            if link.irc_channel.as_ref().is_some_and(|only| !only.eq_ignore_ascii_case(irc_channel)) {
+                continue;
+            }
+            if let Some(to) = link.target(from) {
+                if !targets.iter().any(|(target, _)| *target == to) {
+                    targets.push((to, &link.filter));
+                }
+            }
+        }
+        targets
+    }
+
+    /// e.g. "irc <-> radio base, radio base -> mqtt mqtt.meshtastic.org:1883"
+    pub fn describe(&self) -> String {
+        self.links.iter()
+            .map(|link| {
+                let arrow = if link.both_ways { "<->" } else { "->" };
+                match &link.irc_channel {
+                    Some(irc_channel) => format!("{} {} {} ({})", link.from, arrow, link.to, irc_channel),
+                    None => format!("{} {} {}", link.from, arrow, link.to),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// What links call every configured endpoint, enabled or not
+pub fn endpoint_names(config: &Config) -> Vec<String> {
+    let radios = config.meshtastic.all_devices().into_iter().map(|device| radio_leg(&device));
+    let brokers = config.meshtastic.all_brokers().into_iter().map(mqtt_leg);
+    std::iter::once(IRC_LEG.to_string()).chain(radios).chain(brokers).collect()
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use env_logger::{Builder, Env, Logger, Target};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{OnceLock, RwLock};
+
+use crate::config::{LogFormat, LoggingConfig};
+
+/// The installed logger, replaced when the config is loaded or reloaded
+struct Reloadable(RwLock<Logger>);
+
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.read().map(|logger| logger.enabled(metadata)).unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(logger) = self.0.read() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(logger) = self.0.read() {
+            logger.flush();
+        }
+    }
+}
+
+static LOGGER: OnceLock<Reloadable> = OnceLock::new();
+
+/// Log to stderr at the default levels until the config has been read
+pub fn init() -> Result<()> {
+    let logger = build(&LoggingConfig::default())?;
+    log::set_max_level(logger.filter());
+    log::set_logger(LOGGER.get_or_init(|| Reloadable(RwLock::new(logger))))
+        .map_err(|e| anyhow::anyhow!("Failed to install the logger: {}", e))
+}
+
+/// Log as the config's logging section says from now on
+pub fn apply(config: &LoggingConfig) -> Result<()> {
+    let logger = build(config)?;
+    let filter = logger.filter();
+    if let Some(Reloadable(installed)) = LOGGER.get() {
+        if let Ok(mut installed) = installed.write() {
+            *installed = logger;
+        }
+    }
+    log::set_max_level(filter);
+    Ok(())
+}
+
+fn build(config: &LoggingConfig) -> Result<Logger> {
+    let mut builder = Builder::new();
+    // The serial stream buffer is noisy about partial packets
+    builder.filter_module("meshtastic::connections::stream_buffer", LevelFilter::Error);
+    for (module, level) in config.levels() {
+        let level = level.parse()
+            .map_err(|_| anyhow::anyhow!("Unknown log level \"{}\"", level))?;
+        match module {
+            Some(module) => builder.filter_module(module, level),
+            None => builder.filter_level(level),
+        };
+    }
+    // Parsed last so RUST_LOG overrides the levels above
+    builder.parse_env(Env::default());
+
+    if let Some(path) = &config.file {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open log file {}: {}", path.display(), e))?;
+        builder.target(Target::Pipe(Box::new(file)));
+    }
+    if config.format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = json!({
+                "time": buf.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    Ok(builder.build())
+}
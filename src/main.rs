@@ -1,23 +1,56 @@
+mod aliases;
+mod backlog;
+#[cfg(feature = "bluetooth")]
+mod ble_connection;
+mod backoff;
 mod bridge;
+mod check;
+mod channel_table;
+mod chunking;
 mod config;
+mod crypto;
+mod disk_queue;
+mod endpoint;
+mod dedup;
+mod filter;
 mod irc_handler;
+mod links;
+mod logging;
 mod meshtastic_handler;
+mod metrics;
 mod mqtt_handler;
+mod node_db;
+mod nodes;
+mod outbound;
+mod presence;
+mod proxy;
+mod range_test;
+mod rate_limit;
+mod relay;
+mod routing;
+mod schedule;
+mod sealed;
 mod serial_detector;
+mod supervisor;
+mod telemetry;
+mod template;
+mod topology;
 
 use anyhow::Result;
 use bridge::Bridge;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use config::Config;
-use env_logger;
-use log::{error, info};
+use log::{error, info, warn};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Bridge between Meshtastic and IRC", long_about = None)]
 struct Args {
-    #[arg(short, long, value_name = "FILE", help = "Configuration file path", default_value = "config.json")]
-    config: PathBuf,
+    #[arg(short, long, value_name = "FILE", help = "Configuration file path; given more than once, later files override earlier ones", default_value = "config.json")]
+    config: Vec<PathBuf>,
+    
+    #[arg(long, value_name = "NAME", help = "Built-in settings to start from: libera-longfast or private-broker")]
+    profile: Option<config::Profile>,
     
     #[arg(long, help = "IRC server address")]
     irc_server: Option<String>,
@@ -37,17 +70,29 @@ struct Args {
     #[arg(long, help = "Meshtastic serial port")]
     serial_port: Option<PathBuf>,
     
-    #[arg(long, help = "Meshtastic channel number")]
-    meshtastic_channel: Option<u32>,
+    #[arg(long, help = "Meshtastic device TCP address (host or host:port)")]
+    tcp_address: Option<String>,
+    
+    #[arg(long, help = "Meshtastic BLE device name or MAC address")]
+    ble_device: Option<String>,
+    
+    #[arg(long, help = "Meshtastic channel number or name")]
+    meshtastic_channel: Option<config::ChannelRef>,
+    
+    #[arg(long, help = "Hop limit for messages sent from IRC (0-7)")]
+    hop_limit: Option<u32>,
     
     #[arg(long, help = "MQTT broker address")]
     mqtt_broker: Option<String>,
     
+    #[arg(long, help = "MQTT broker preset: public (mqtt.meshtastic.org, receive only)")]
+    mqtt_preset: Option<config::MqttPreset>,
+    
     #[arg(long, help = "MQTT broker port")]
     mqtt_port: Option<u16>,
     
-    #[arg(long, help = "MQTT topic")]
-    mqtt_topic: Option<String>,
+    #[arg(long, help = "MQTT topic, repeat to subscribe to several")]
+    mqtt_topic: Vec<String>,
     
     #[arg(long, help = "MQTT username")]
     mqtt_username: Option<String>,
@@ -55,16 +100,183 @@ struct Args {
     #[arg(long, help = "MQTT password")]
     mqtt_password: Option<String>,
     
+    #[arg(long, help = "Use TLS for the MQTT connection")]
+    mqtt_tls: bool,
+    
+    #[arg(long, help = "SOCKS5 proxy (host:port) for the IRC and MQTT connections")]
+    proxy: Option<String>,
+    
+    #[arg(long, help = "Start even if the config file is malformed or fails validation")]
+    ignore_config_errors: bool,
+    
     #[arg(long, help = "List available serial ports and exit")]
     list_ports: bool,
+    
+    #[arg(long, help = "Print the configuration in effect, after the config files, environment and options, with secrets hidden, and exit")]
+    dump_config: bool,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the radio's node database and exit
+    Nodes {
+        #[arg(long, help = "Print as JSON, for mapping tools")]
+        json: bool,
+    },
+    /// Check the config, serial ports and server names, then exit without bridging
+    Check,
+    /// Manage the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Write a starter config, with every setting at its default, to the --config path
+    Init {
+        #[arg(long, help = "jsonc (commented, default) or json", default_value = "jsonc")]
+        format: config::ConfigFormat,
+        #[arg(long, help = "Fill in the serial port of a connected radio")]
+        detect_port: bool,
+        #[arg(long, help = "Overwrite an existing file")]
+        force: bool,
+    },
+    /// Encrypt the --config file in place with a passphrase
+    Encrypt,
+    /// Print the decrypted --config file, e.g. to edit it
+    Decrypt,
+}
+
+/// Read the config file, apply the environment and command-line overrides on
+/// top of it, then check the result
+fn load_config(args: &Args, ignore_errors: bool) -> Result<Config> {
+    let config = read_config(args, ignore_errors)?;
+    let problems = config.validate();
+    if !problems.is_empty() {
+        for problem in &problems {
+            if ignore_errors {
+                warn!("Config: {}", problem);
+            } else {
+                error!("Config: {}", problem);
+            }
+        }
+        if !ignore_errors {
+            return Err(anyhow::anyhow!(
+                "{} problem(s) in the configuration. Fix them, or pass --ignore-config-errors to start anyway",
+                problems.len()
+            ));
+        }
+    }
+    Ok(config)
+}
+
+/// Read the config file and apply the environment and command-line
+/// overrides on top of it
+fn read_config(args: &Args, ignore_errors: bool) -> Result<Config> {
+    // A single missing file means there is no config yet; among several
+    // it's a mistake
+    let mut config = match args.config.as_slice() {
+        [path] if !path.exists() => {
+            info!("Config file not found at {}. Using {}.", path.display(),
+                  args.profile.map(|profile| format!("profile {}", profile)).unwrap_or_else(|| "defaults".to_string()));
+            config::load_files(&[], args.profile)?
+        }
+        paths => match config::load_files(paths, args.profile) {
+            Ok(c) => {
+                info!("Successfully loaded config from file");
+                c
+            }
+            Err(e) if ignore_errors => {
+                error!("{}. Using defaults.", e);
+                Config::default()
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!("{}. Fix it, or pass --ignore-config-errors to start with defaults", e));
+            }
+        },
+    };
+    // Environment variables override the file, and command-line flags both
+    config.apply_env()?;
+    
+    if let Some(server) = &args.irc_server {
+        config.irc.server = server.clone();
+    }
+    if let Some(port) = args.irc_port {
+        config.irc.port = port;
+    }
+    if let Some(channel) = &args.irc_channel {
+        config.irc.channel = channel.clone();
+    }
+    if let Some(nick) = &args.irc_nick {
+        config.irc.nickname = nick.clone();
+    }
+    if let Some(tls) = args.irc_tls {
+        config.irc.use_tls = tls;
+    }
+    if let Some(address) = &args.proxy {
+        config.proxy = Some(config::ProxyConfig {
+            address: address.clone(),
+            username: None,
+            password: None,
+        });
+    }
+    // Handle serial port configuration
+    if let Some(port) = &args.serial_port {
+        config.meshtastic.serial_port = Some(port.clone());
+    }
+    if let Some(address) = &args.tcp_address {
+        config.meshtastic.tcp_address = Some(address.clone());
+    }
+    if let Some(device) = &args.ble_device {
+        config.meshtastic.ble = Some(config::BleConfig {
+            device: device.clone(),
+            scan_timeout_secs: None,
+        });
+    }
+    
+    // Handle MQTT configuration
+    if args.mqtt_broker.is_some() || args.mqtt_preset.is_some() {
+        // If an MQTT broker or preset is specified, create MQTT config
+        let mqtt_config = config::MqttConfig {
+            preset: args.mqtt_preset,
+            broker_address: args.mqtt_broker.clone().unwrap_or_default(),
+            port: args.mqtt_port.unwrap_or_default(),
+            topic: args.mqtt_topic.first().cloned().unwrap_or_default(),
+            topics: args.mqtt_topic.iter().skip(1).cloned().collect(),
+            username: args.mqtt_username.clone(),
+            password: args.mqtt_password.clone(),
+            use_tls: args.mqtt_tls,
+            ..Default::default()
+        };
+        config.meshtastic.mqtt = Some(mqtt_config);
+    }
+    let region = config.meshtastic.rate_limit.region.clone();
+    for mqtt in config.meshtastic.mqtt.iter_mut().chain(config.meshtastic.mqtt_brokers.iter_mut()) {
+        if mqtt.preset.is_none() && mqtt.topic.is_empty() {
+            mqtt.topic = "meshtastic/2/e/#".to_string();
+        }
+        mqtt.apply_preset()?;
+        mqtt.expand_topics(region.as_deref())?;
+    }
+    
+    if let Some(channel) = &args.meshtastic_channel {
+        config.meshtastic.channel = channel.clone();
+    }
+    if let Some(hop_limit) = args.hop_limit {
+        config.meshtastic.hop_limit = Some(hop_limit);
+    }
+    Ok(config)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logger with custom settings to reduce noise
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .filter_module("meshtastic::connections::stream_buffer", log::LevelFilter::Error)
-        .init();
+    // Defaults until the config's logging section is read
+    logging::init()?;
     
     let args = Args::parse();
     
@@ -97,93 +309,133 @@ async fn main() -> Result<()> {
         return Ok(());
     }
     
-    let mut config = if args.config.exists() {
-        info!("Loading config from: {}", args.config.display());
-        let config_str = std::fs::read_to_string(&args.config)?;
-        match serde_json::from_str::<Config>(&config_str) {
-            Ok(c) => {
-                info!("Successfully loaded config from file");
-                c
-            }
-            Err(e) => {
-                error!("Could not parse config file: {}. Using defaults.", e);
-                Config::default()
-            }
+    if let Some(Command::Config { command: ConfigCommand::Init { format, detect_port, force } }) = &args.command {
+        // Overlays are written by hand, so only the first file is a starter
+        let path = &args.config[0];
+        if path.exists() && !force {
+            return Err(anyhow::anyhow!("{} already exists, pass --force to overwrite it", path.display()));
         }
-    } else {
-        info!("Config file not found at {}. Using defaults.", args.config.display());
-        Config::default()
-    };
-    
-    if let Some(server) = args.irc_server {
-        config.irc.server = server;
-    }
-    if let Some(port) = args.irc_port {
-        config.irc.port = port;
-    }
-    if let Some(channel) = args.irc_channel {
-        config.irc.channel = channel;
+        let serial_port = if *detect_port {
+            let port = serial_detector::detect_meshtastic_port().await?;
+            println!("Detected a radio on {}", port.display());
+            Some(port)
+        } else {
+            None
+        };
+        std::fs::write(path, config::starter_config(serial_port.as_deref(), *format)?)?;
+        println!("Wrote {}, edit the IRC settings and start the bridge", path.display());
+        return Ok(());
     }
-    if let Some(nick) = args.irc_nick {
-        config.irc.nickname = nick;
+    
+    if let Some(Command::Config { command: ConfigCommand::Encrypt }) = &args.command {
+        let path = &args.config[0];
+        let text = std::fs::read_to_string(path)?;
+        if sealed::is_sealed(&text) {
+            return Err(anyhow::anyhow!("{} is already encrypted", path.display()));
+        }
+        serde_json::from_str::<serde_json::Value>(&config::strip_comments(&text))
+            .map_err(|e| anyhow::anyhow!("{} doesn't parse, so it wasn't encrypted: {}", path.display(), e))?;
+        std::fs::write(path, sealed::seal(&text, &sealed::passphrase(true)?)?)?;
+        println!("Encrypted {}; the bridge asks for the passphrase at start, or reads it from {}", path.display(), sealed::PASSPHRASE_VAR);
+        return Ok(());
     }
-    if let Some(tls) = args.irc_tls {
-        config.irc.use_tls = tls;
+    
+    if let Some(Command::Config { command: ConfigCommand::Decrypt }) = &args.command {
+        let path = &args.config[0];
+        let text = std::fs::read_to_string(path)?;
+        if !sealed::is_sealed(&text) {
+            return Err(anyhow::anyhow!("{} isn't encrypted", path.display()));
+        }
+        print!("{}", sealed::open(&text, &sealed::passphrase(false)?)?);
+        return Ok(());
     }
-    // Handle serial port configuration
-    if let Some(port) = args.serial_port {
-        config.meshtastic.serial_port = Some(port);
+    
+    // Before validation, so a setting that fails it can be looked at
+    if args.dump_config {
+        let config = read_config(&args, false)?;
+        println!("{}", serde_json::to_string_pretty(&config.redacted())?);
+        return Ok(());
     }
     
-    // Handle MQTT configuration
-    if let Some(broker) = args.mqtt_broker {
-        // If MQTT broker is specified, create MQTT config
-        let mqtt_config = config::MqttConfig {
-            broker_address: broker,
-            port: args.mqtt_port.unwrap_or(1883),
-            topic: args.mqtt_topic.unwrap_or_else(|| "meshtastic/2/e/#".to_string()),
-            username: args.mqtt_username,
-            password: args.mqtt_password,
-            client_id: None,
-        };
-        config.meshtastic.mqtt = Some(mqtt_config);
+    // Problems are part of the report rather than stopping it
+    if let Some(Command::Check) = &args.command {
+        let config = read_config(&args, false)?;
+        return check::run(&config).await;
     }
     
-    // Auto-detect serial port if neither serial nor MQTT is configured
-    if config.meshtastic.serial_port.is_none() && config.meshtastic.mqtt.is_none() {
+    let mut config = load_config(&args, args.ignore_config_errors)?;
+    logging::apply(&config.logging)?;
+    
+    // Auto-detect serial port if no serial, TCP, BLE or MQTT connection is configured
+    let mut detected_port = None;
+    if config.meshtastic.all_devices().is_empty() && config.meshtastic.all_brokers().is_empty() {
         // Try auto-detection
         match serial_detector::detect_meshtastic_port().await {
-            Ok(detected_port) => {
-                info!("Auto-detected serial port: {}", detected_port.display());
-                config.meshtastic.serial_port = Some(detected_port);
+            Ok(port) => {
+                info!("Auto-detected serial port: {}", port.display());
+                config.meshtastic.serial_port = Some(port.clone());
+                // The device may come back under another name after being replugged
+                config.meshtastic.reconnect.redetect_serial_port = true;
+                detected_port = Some(port);
             }
             Err(e) => {
                 return Err(anyhow::anyhow!("Failed to auto-detect serial port: {}. Please specify with --serial-port or configure MQTT", e));
             }
         }
     }
-    
-    if let Some(channel) = args.meshtastic_channel {
-        config.meshtastic.channel = channel;
+
+    // Handle the nodes subcommand, using the first configured radio
+    if let Some(Command::Nodes { json }) = args.command {
+        let device = config.meshtastic.all_devices().into_iter().next()
+            .ok_or_else(|| anyhow::anyhow!("Reading the node database needs a serial, TCP or BLE radio"))?;
+        let node_db = meshtastic_handler::MeshtasticHandler::read_node_db(&device).await?;
+        if json {
+            println!("{}", node_db.to_json()?);
+        } else {
+            for line in node_db.describe_all() {
+                println!("{}", line);
+            }
+        }
+        return Ok(());
     }
     
     info!("Starting Meshtastic-IRC bridge");
-    info!("IRC: {}:{} channel {} as {}", 
-          config.irc.server, config.irc.port, config.irc.channel, config.irc.nickname);
+    let disabled = |enabled: Option<bool>| if enabled.unwrap_or(true) { "" } else { " (disabled)" };
+    info!("IRC: {}:{} channel {} as {}{}", 
+          config.irc.server, config.irc.port, config.irc.channel, config.irc.nickname, disabled(config.irc.enabled));
     
     // Log Meshtastic connection type
-    if let Some(mqtt) = &config.meshtastic.mqtt {
-        info!("Meshtastic: MQTT {}:{} topic {} channel {}", 
-              mqtt.broker_address, mqtt.port, mqtt.topic, config.meshtastic.channel);
-    } else {
-        info!("Meshtastic: Serial {} channel {}", 
-              config.meshtastic.serial_port.as_ref()
-                  .map(|p| p.display().to_string())
-                  .unwrap_or_else(|| "auto-detect".to_string()), 
-              config.meshtastic.channel);
+    for mqtt in config.meshtastic.all_brokers() {
+        info!("Meshtastic: MQTT {}:{} topic {} channel {}{}", 
+              mqtt.broker_address, mqtt.port, mqtt.subscriptions().join(", "), config.meshtastic.channel, disabled(mqtt.enabled));
+    }
+    for device in config.meshtastic.all_devices() {
+        let transport = if device.tcp_address.is_some() {
+            "TCP"
+        } else if device.ble.is_some() {
+            "BLE"
+        } else {
+            "Serial"
+        };
+        info!("Meshtastic: {} {} channel {}{}", transport, device.display_name(), device.channel, disabled(device.enabled));
     }
+    // Broken links were reported with the other config problems
+    if let Ok(links) = links::Links::new(&config) {
+        info!("Links: {}", links.describe());
+    }
+    
+    // SIGHUP reads the config again; a broken file is then refused rather
+    // than replaced by defaults
+    let reload: bridge::ConfigLoader = Box::new(move || {
+        let mut config = load_config(&args, false)?;
+        if config.meshtastic.all_devices().is_empty() && config.meshtastic.all_brokers().is_empty() {
+            config.meshtastic.serial_port = detected_port.clone();
+            config.meshtastic.reconnect.redetect_serial_port = true;
+        }
+        Ok(config)
+    });
     
     info!("Initializing connections...");
-    let bridge = Bridge::new(config);
+    let bridge = Bridge::new(config, reload);
     bridge.run().await
 }
\ No newline at end of file
@@ -1,40 +1,828 @@
 use anyhow::Result;
-use log::{debug, error, info};
-use meshtastic::api::StreamApi;
-use meshtastic::protobufs::{mesh_packet, FromRadio, MeshPacket, PortNum, Data};
+use log::{debug, error, info, warn};
+use meshtastic::api::{state, ConnectedStreamApi, StreamApi};
+use meshtastic::protobufs::config::lo_ra_config::{ModemPreset, RegionCode};
+use meshtastic::protobufs::{admin_message, from_radio, mesh_packet, routing, store_and_forward, telemetry, to_radio, AdminMessage, ChannelSettings, FromRadio, MeshPacket, NeighborInfo, PortNum, Data, Position, RouteDiscovery, Routing, StoreAndForward, Telemetry, User, Waypoint};
+use meshtastic::Message;
 use meshtastic::utils;
-use tokio::sync::mpsc;
-use std::collections::HashMap;
+use futures_util::future::BoxFuture;
+use tokio::sync::{mpsc, watch};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::config::MeshtasticConfig;
-use crate::irc_handler::IrcMessage;
+use crate::aliases::Aliases;
+use crate::backlog::Backlog;
+use crate::backoff::Backoff;
+use crate::channel_table::ChannelTable;
+use crate::chunking::{split_text, CHUNK_DELAY, MAX_TEXT_BYTES};
+use crate::crypto::{self, ChannelKeys};
+use crate::config::{BleConfig, ChannelRef, Config, DetectionConfig, DeviceConfig, DumpSink, MeshtasticConfig, OwnerConfig, RangeTestMode, PositionConfig, RateLimitConfig, ReconnectConfig, RetryConfig, RouteDirection, StoreForwardConfig, UnknownPortsConfig};
+use crate::dedup::PacketDedup;
+use crate::endpoint::{ChatEndpoint, Health, MeshState, Session};
+use crate::filter::Filters;
+use crate::metrics::{format_duration, Metrics};
+use crate::mqtt_handler::Uplink;
+use crate::irc_handler::{AdminCommand, Chat, IrcMessage, MeshCommand, MeshMessage};
+use crate::node_db::NodeDb;
+use crate::nodes::{default_destination, parse_node_id, parse_node_ids, NodeDirectory, BROADCAST_ADDR};
+use crate::outbound::{OutboundQueue, Priority};
+use crate::presence::PresenceTracker;
+use crate::range_test::{is_range_test, RangeTestTracker, RANGE_TEST_IDLE};
+use crate::rate_limit::{estimate_airtime, region_duty_cycle, RateLimiter};
+use crate::routing::{BridgedChannel, Router};
+use crate::relay::{dump_packet, format_position, format_waypoint, is_unknown_port, relay_ports, DUMP_MAX_BYTES};
+use crate::serial_detector;
+use crate::telemetry::{NodeTelemetry, TelemetryStore};
+use crate::template::{TemplateValues, Templates};
+use crate::topology::Topology;
+
+pub const DEFAULT_TCP_PORT: u16 = 4403;
+
+// Standard Meshtastic serial settings
+const DEFAULT_BAUD_RATE: u32 = 115200;
+
+// Minimum time between relayed positions from the same node
+const DEFAULT_POSITION_INTERVAL_SECS: u64 = 900;
+
+// How long to wait for the device to send its configuration after connecting
+const CONFIG_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+// Time given to the disconnect notice to reach the radio before the
+// connection is closed
+const DISCONNECT_FLUSH_DELAY: Duration = Duration::from_millis(200);
+
+// A radio that sends nothing for this long, even when probed halfway
+// through, is considered wedged and reconnected
+const DEFAULT_STALL_TIMEOUT_SECS: u64 = 300;
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+// Retransmission of unacknowledged messages from IRC
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_ACK_TIMEOUT_SECS: u64 = 30;
+const ACK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// How often queued messages from IRC are checked against the rate limit
+const SEND_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// Queued messages from IRC before a warning is posted to IRC
+const DEFAULT_QUEUE_WARNING: usize = 5;
+
+// Queued packets before the oldest of the lowest priority is dropped
+const DEFAULT_MAX_QUEUE: usize = 50;
+
+// Minimum time between NodeInfo requests to the same unknown node
+const NODEINFO_REQUEST_INTERVAL: Duration = Duration::from_secs(600);
+
+// Longest owner names the firmware stores
+const MAX_SHORT_NAME_BYTES: usize = 4;
+const MAX_LONG_NAME_BYTES: usize = 39;
+
+// Where !export nodes writes the node database
+const DEFAULT_NODE_EXPORT_PATH: &str = "nodes.json";
+
+// Minimum time between auto-replies to the same node, so two bots can't
+// keep answering each other
+const AUTO_REPLY_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Traceroutes not answered within this time are forgotten
+const TRACEROUTE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// How often nodes are checked for having gone offline
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// How often finished range tests are looked for
+const RANGE_TEST_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// How often the radio's clock is set from the host
+const DEFAULT_TIME_SYNC_INTERVAL_MINUTES: u64 = 60;
+
+// Admin requests not answered within this time are forgotten
+const ADMIN_RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Minutes of Store & Forward history requested when not configured
+pub const DEFAULT_HISTORY_WINDOW_MINUTES: u32 = 60;
+
+// RouteDiscovery uses this SNR for hops that didn't record one
+const UNKNOWN_SNR: i32 = i8::MIN as i32;
+
+/// Whether and how often positions are relayed to IRC
+#[derive(Debug, Clone)]
+struct PositionRelay {
+    interval: Duration,
+}
+
+impl From<&PositionConfig> for PositionRelay {
+    fn from(config: &PositionConfig) -> Self {
+        Self {
+            interval: Duration::from_secs(config.min_interval_secs.unwrap_or(DEFAULT_POSITION_INTERVAL_SECS)),
+        }
+    }
+}
+
+/// Which nodes' detection sensor alerts are relayed
+#[derive(Debug, Clone)]
+struct DetectionRelay {
+    nodes: HashSet<u32>,  // Every node when empty
+}
+
+impl TryFrom<&DetectionConfig> for DetectionRelay {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &DetectionConfig) -> Result<Self> {
+        Ok(Self {
+            nodes: parse_node_ids(&config.nodes, "detection.nodes")?,
+        })
+    }
+}
+
+impl DetectionRelay {
+    fn relays(&self, node: u32) -> bool {
+        self.nodes.is_empty() || self.nodes.contains(&node)
+    }
+}
+
+/// Our side of the Store & Forward protocol: which router to ask and what
+/// it already sent us
+#[derive(Debug, Clone)]
+struct StoreForwardClient {
+    router: Option<u32>,  // Configured, or learned from the router's heartbeat
+    window_minutes: u32,
+    request_on_startup: bool,  // Still to be sent once the router is known
+    last_request: u32,  // The router's history index after its last replay to us
+    pending: Option<Option<String>>,  // IRC channel awaiting the router's answer
+}
+
+impl TryFrom<&StoreForwardConfig> for StoreForwardClient {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &StoreForwardConfig) -> Result<Self> {
+        let router = config.router.as_deref()
+            .map(|node| parse_node_id(node).ok_or_else(|| {
+                anyhow::anyhow!("Invalid Store & Forward router \"{}\", expected e.g. \"!a1b2c3d4\"", node)
+            }))
+            .transpose()?;
+        Ok(Self {
+            router,
+            window_minutes: config.window_minutes.unwrap_or(DEFAULT_HISTORY_WINDOW_MINUTES),
+            request_on_startup: config.history_on_startup,
+            last_request: 0,
+            pending: None,
+        })
+    }
+}
+
+/// How often and how patiently unacknowledged messages are retried
+#[derive(Debug, Clone)]
+struct RetryPolicy {
+    max_retries: u32,
+    ack_timeout: Duration,
+}
+
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(config: &RetryConfig) -> Self {
+        Self {
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            ack_timeout: Duration::from_secs(config.ack_timeout_secs.unwrap_or(DEFAULT_ACK_TIMEOUT_SECS)),
+        }
+    }
+}
+
+/// A packet waiting to be sent or acknowledged, with what is needed to send
+/// it again or report that it failed
+#[derive(Debug, Clone)]
+struct PendingAck {
+    packet: MeshPacket,
+    description: String,  // e.g. "message from alice to ABCD"
+    irc_channel: Option<String>,
+    retry: bool,  // Messages from IRC wait for an ACK, other packets are sent once
+    attempts: u32,
+    sent_at: Instant,
+}
+
+impl PendingAck {
+    /// A packet that is sent once as it is
+    fn once(packet: MeshPacket, description: String) -> Self {
+        Self {
+            packet,
+            description,
+            irc_channel: None,
+            retry: false,
+            attempts: 1,
+            sent_at: Instant::now(),
+        }
+    }
+}
+
+impl PendingAck {
+    /// Each retry waits twice as long as the one before
+    fn timed_out(&self, ack_timeout: Duration) -> bool {
+        self.sent_at.elapsed() >= ack_timeout * 2u32.pow(self.attempts.saturating_sub(1).min(16))
+    }
+}
+
+/// A traceroute we sent and are waiting on, with the IRC channel that asked
+/// for it (None for the main IRC channel)
+#[derive(Debug, Clone)]
+struct PendingTraceroute {
+    node: u32,
+    irc_channel: Option<String>,
+    sent_at: Instant,
+}
+
+/// An admin request to our radio whose response goes to IRC
+#[derive(Debug, Clone)]
+struct PendingAdmin {
+    irc_channel: Option<String>,
+    sent_at: Instant,
+}
+
+/// An open, configured connection to a radio
+struct Connection {
+    stream_api: ConnectedStreamApi,
+    decoded_listener: mpsc::UnboundedReceiver<FromRadio>,
+    channel_table: ChannelTable,
+    startup_packets: Vec<FromRadio>,
+}
+
+/// The canned answer to direct messages, checked to fit in one packet
+fn auto_reply(settings: &MeshtasticConfig) -> Result<Option<String>> {
+    let auto_reply = settings.direct_messages.auto_reply.clone();
+    if let Some(reply) = &auto_reply {
+        if reply.len() > MAX_TEXT_BYTES {
+            return Err(anyhow::anyhow!(
+                "direct_messages.auto_reply is {} bytes, the mesh allows {}", reply.len(), MAX_TEXT_BYTES
+            ));
+        }
+    }
+    Ok(auto_reply)
+}
+
+/// The name of the mesh channel a packet was heard on, or its index without one
+fn channel_name(packet: &MeshPacket, bridged: Option<&BridgedChannel>) -> String {
+    bridged.map(|bridged| bridged.name.clone()).unwrap_or_else(|| packet.channel.to_string())
+}
+
+/// The SNR the radio heard a packet at, None if it didn't report one
+fn heard_snr(packet: &MeshPacket) -> Option<f32> {
+    (packet.rx_snr != 0.0).then_some(packet.rx_snr)
+}
+
+/// What messages held for a radio are filed under in a backlog file
+pub fn radio_leg(device: &DeviceConfig) -> String {
+    format!("radio {}", device.display_name())
+}
+
+/// Silence before a radio is reconnected, None when the watchdog is off
+fn stall_timeout(settings: &MeshtasticConfig) -> Option<Duration> {
+    match settings.reconnect.stall_timeout_secs.unwrap_or(DEFAULT_STALL_TIMEOUT_SECS) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// How often the radio's clock is set, None if never
+fn time_sync_interval(settings: &MeshtasticConfig) -> Option<Duration> {
+    settings.time_sync.enabled.then(|| Duration::from_secs(60 * settings.time_sync.interval_minutes
+        .unwrap_or(DEFAULT_TIME_SYNC_INTERVAL_MINUTES)
+        .max(1)))
+}
+
+/// A human-readable explanation of a routing error
+fn describe_routing_error(error: routing::Error) -> &'static str {
+    match error {
+        routing::Error::None => "delivered",
+        routing::Error::NoRoute => "no route to the node",
+        routing::Error::GotNak => "rejected by the next hop",
+        routing::Error::Timeout => "timed out",
+        routing::Error::NoInterface => "no radio interface available",
+        routing::Error::MaxRetransmit => "no ACK after the maximum number of retransmissions",
+        routing::Error::NoChannel => "the receiver doesn't know the channel",
+        routing::Error::TooLarge => "packet too large",
+        routing::Error::NoResponse => "the node did not respond",
+        routing::Error::DutyCycleLimit => "the radio reached its duty cycle limit",
+        routing::Error::BadRequest => "bad request",
+        routing::Error::NotAuthorized => "not authorized",
+        routing::Error::PkiFailed => "encryption failed",
+        routing::Error::PkiUnknownPubkey => "the node's public key is unknown",
+        routing::Error::AdminBadSessionKey => "bad admin session key",
+        routing::Error::AdminPublicKeyUnauthorized => "admin key not authorized",
+    }
+}
+
+/// How long ago a packet was received, from its receive time in seconds
+/// since the epoch, e.g. "25m ago"
+fn format_age(rx_time: u32) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    let secs = now.saturating_sub(u64::from(rx_time));
+    if rx_time == 0 {
+        "earlier".to_string()
+    } else if secs < 60 {
+        "just now".to_string()
+    } else if secs < 3600 {
+        format!("{}m ago", secs / 60)
+    } else {
+        format!("{}h{:02}m ago", secs / 3600, secs / 60 % 60)
+    }
+}
+
+/// How a packet reached our radio, e.g. " (SNR 6.75, RSSI -92, 2 hops)".
+/// Values the radio didn't record are left out.
+fn signal_suffix(packet: &MeshPacket) -> String {
+    let mut parts = Vec::new();
+    if packet.rx_snr != 0.0 {
+        parts.push(format!("SNR {:.2}", packet.rx_snr));
+    }
+    if packet.rx_rssi != 0 {
+        parts.push(format!("RSSI {}", packet.rx_rssi));
+    }
+    // Firmware older than 2.3 doesn't set hop_start
+    if packet.hop_start != 0 {
+        match packet.hop_start.saturating_sub(packet.hop_limit) {
+            0 => parts.push("direct".to_string()),
+            1 => parts.push("1 hop".to_string()),
+            hops => parts.push(format!("{} hops", hops)),
+        }
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", parts.join(", "))
+    }
+}
+
+/// A radio as an endpoint of the bridge, connected with the current config
+pub struct RadioEndpoint {
+    device_index: usize,  // The radio's place in the config's list of radios
+    name: String,
+    state: MeshState,
+    uplink: Option<mpsc::Sender<Uplink>>,
+    shutdown: watch::Receiver<bool>,
+    reload: watch::Receiver<Config>,
+    session: Session<IrcMessage, MeshMessage>,
+}
+
+impl RadioEndpoint {
+    pub fn new(
+        device: &DeviceConfig,
+        device_index: usize,
+        state: MeshState,
+        uplink: Option<mpsc::Sender<Uplink>>,
+        shutdown: watch::Receiver<bool>,
+        reload: watch::Receiver<Config>,
+    ) -> Self {
+        Self {
+            device_index,
+            name: device.display_name(),
+            state,
+            uplink,
+            shutdown,
+            reload,
+            session: Session::default(),
+        }
+    }
+}
+
+impl ChatEndpoint for RadioEndpoint {
+    type Incoming = IrcMessage;
+    type Outgoing = MeshMessage;
+
+    fn name(&self) -> String {
+        format!("Radio {}", self.name)
+    }
+
+    fn connect(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // Marked as seen, so the handler only applies later reloads
+            let config = self.reload.borrow_and_update().clone();
+            let device = config.meshtastic.all_devices().into_iter().nth(self.device_index)
+                .ok_or_else(|| anyhow::anyhow!("Radio {} is no longer configured", self.name))?;
+            info!("Initializing Meshtastic connection to {}...", self.name);
+            let state = self.state.clone();
+            let handler = MeshtasticHandler::new(&device, &config.meshtastic, state.dedup, state.presence, state.node_db, self.uplink.clone(), state.metrics).await
+                .map_err(|e| anyhow::anyhow!("Failed to initialize Meshtastic handler: {}", e))?;
+            info!("Meshtastic handler for {} initialized successfully", self.name);
+            info!("Starting Meshtastic message handler loop");
+            let (shutdown, reload, device_index) = (self.shutdown.clone(), self.reload.clone(), self.device_index);
+            self.session.start(|from_irc, to_irc| handler.run(from_irc, to_irc, shutdown, reload, device_index));
+            Ok(())
+        })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<MeshMessage>>> {
+        Box::pin(self.session.recv())
+    }
+
+    fn send(&mut self, message: IrcMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.session.send(message))
+    }
+
+    fn health(&self) -> Health {
+        self.session.health()
+    }
+}
 
 pub struct MeshtasticHandler {
-    stream_api: meshtastic::api::ConnectedStreamApi,
+    stream_api: Option<ConnectedStreamApi>,  // None only while reconnecting
     decoded_listener: mpsc::UnboundedReceiver<FromRadio>,
-    channel: u32,
-    node_names: HashMap<u32, String>,  // Map node IDs to short names
+    startup_packets: Vec<FromRadio>,  // Received while reading the device config
+    device: DeviceConfig,
+    reconnect: ReconnectConfig,
+    stall_timeout: Option<Duration>,  // None when the watchdog is off
+    last_from_radio: Instant,
+    stall_probed: bool,  // A config request went out since the radio last spoke
+    name: String,
+    leg: String,  // What links and backlogs call this radio
+    router: Router,  // Which mesh channels go to which IRC channels
+    channel_table: ChannelTable,  // The device's channels as last reported
+    channel_keys: ChannelKeys,  // For channels the device doesn't have
+    relay_ports: HashSet<PortNum>,  // Ports whose packets are posted to IRC
+    blocked_nodes: HashSet<u32>,  // Never relayed to IRC
+    allowed_nodes: Option<HashSet<u32>>,  // When set, the only nodes bridged either way
+    unknown_ports: UnknownPortsConfig,
+    range_test: RangeTestMode,
+    range_tests: RangeTestTracker,
+    skip_via_mqtt: bool,
+    hop_limit: Option<u32>,
+    default_destination: u32,  // Where IRC channel messages go, usually broadcast
+    my_node_num: u32,  // Our own node ID, 0 until the device reports it
+    reboot_count: Option<u32>,
+    nodes: NodeDirectory,
+    my_user: Option<User>,  // Our own user info, sent along with NodeInfo requests
+    nodeinfo_requested: HashMap<u32, Instant>,  // When each unknown node was last asked for its NodeInfo
+    positions: PositionRelay,
+    last_position: HashMap<u32, Instant>,  // When each node's position was last relayed
+    telemetry: TelemetryStore,
+    announce_telemetry: bool,
+    detection: DetectionRelay,
+    presence: Arc<Mutex<PresenceTracker>>,  // Shared with the other radios
+    announce_presence: bool,
+    presence_muted: HashSet<u32>,  // Never announced online or offline
+    store_forward: StoreForwardClient,
+    topology: Topology,
+    topology_export: Option<String>,  // Path the topology is written to as JSON
+    node_db: Arc<Mutex<NodeDb>>,  // Exported for mapping tools, shared with the other radios and MQTT
+    node_export_path: String,
+    pending_traceroutes: HashMap<u32, PendingTraceroute>,  // Keyed by request packet ID
+    pending_admin: HashMap<u32, PendingAdmin>,  // Keyed by request packet ID
+    retry: RetryPolicy,
+    report_delivery_errors: bool,
+    show_signal: bool,
+    templates: Templates,
+    filters: Filters,
+    auto_reply: Option<String>,  // Canned answer to direct messages
+    auto_replied: HashMap<u32, Instant>,
+    dm_forward_to: Option<String>,  // IRC nick that gets direct messages
+    owner: Option<OwnerConfig>,  // Names to give the radio at startup
+    time_sync: Option<Duration>,  // How often the radio's clock is set, never if None
+    pending_acks: HashMap<u32, PendingAck>,  // Keyed by packet ID
+    outgoing: OutboundQueue<PendingAck>,  // Waiting for the rate limiter
+    dropped: Vec<PendingAck>,  // Messages from IRC pushed out of a full queue
+    limiter: RateLimiter,
+    rate_limit: RateLimitConfig,  // What limiter was built from
+    backlog: Backlog<IrcMessage>,  // Messages from IRC held while reconnecting
+    modem_preset: ModemPreset,  // For airtime estimates
+    next_send_at: Instant,
+    queue_warning: usize,
+    queue_warned: bool,
+    dedup: Arc<Mutex<PacketDedup>>,  // Shared with the other radios
+    uplink: Option<mpsc::Sender<Uplink>>,  // Packets heard, for MQTT in gateway mode
+    metrics: Metrics,
 }
 
 impl MeshtasticHandler {
-    pub async fn new(config: &MeshtasticConfig) -> Result<Self> {
+    pub async fn new(
+        config: &DeviceConfig,
+        settings: &MeshtasticConfig,
+        dedup: Arc<Mutex<PacketDedup>>,
+        presence: Arc<Mutex<PresenceTracker>>,
+        node_db: Arc<Mutex<NodeDb>>,
+        uplink: Option<mpsc::Sender<Uplink>>,
+        metrics: Metrics,
+    ) -> Result<Self> {
+        let connection = Self::open(config).await?;
+        let channel_table = &connection.channel_table;
+        
+        info!("Device channels: {}", channel_table.describe());
+        
+        let rate_limit = &settings.rate_limit;
+        let limiter = Self::rate_limiter(rate_limit, channel_table)?;
+        let router = Self::resolve_routes(config, channel_table)?;
+        let detection = DetectionRelay::try_from(&settings.detection)?;
+        let presence_muted = parse_node_ids(&settings.presence.muted, "presence.muted")?;
+        if let Some(owner) = &settings.owner {
+            if owner.short_name.is_empty() || owner.short_name.len() > MAX_SHORT_NAME_BYTES {
+                return Err(anyhow::anyhow!("owner.short_name must be 1 to {} bytes", MAX_SHORT_NAME_BYTES));
+            }
+            if owner.long_name.len() > MAX_LONG_NAME_BYTES {
+                return Err(anyhow::anyhow!("owner.long_name must be at most {} bytes", MAX_LONG_NAME_BYTES));
+            }
+        }
+        let auto_reply = auto_reply(settings)?;
+        let relay_ports = relay_ports(settings)?;
+        let blocked_nodes = parse_node_ids(&settings.blocked_nodes, "blocked_nodes")?;
+        let allowed_nodes = settings.allowed_nodes.as_deref()
+            .map(|nodes| parse_node_ids(nodes, "allowed_nodes"))
+            .transpose()?;
+        let store_forward = StoreForwardClient::try_from(&settings.store_forward)?;
+        let channel_keys = ChannelKeys::try_from(settings.channel_keys.as_slice())?;
+        let aliases = Aliases::load(settings.aliases_file.as_deref())?;
+        
+        Ok(Self {
+            stream_api: Some(connection.stream_api),
+            decoded_listener: connection.decoded_listener,
+            startup_packets: connection.startup_packets,
+            device: config.clone(),
+            reconnect: settings.reconnect.clone(),
+            stall_timeout: stall_timeout(settings),
+            last_from_radio: Instant::now(),
+            stall_probed: false,
+            name: config.display_name(),
+            leg: radio_leg(config),
+            router,
+            relay_ports,
+            blocked_nodes,
+            allowed_nodes,
+            unknown_ports: settings.unknown_ports.clone(),
+            range_test: settings.range_test,
+            range_tests: RangeTestTracker::new(),
+            skip_via_mqtt: settings.skip_via_mqtt,
+            hop_limit: settings.hop_limit,
+            default_destination: default_destination(settings)?,
+            my_node_num: 0,
+            reboot_count: None,
+            nodes: NodeDirectory::new(settings.sender_format, aliases),
+            my_user: None,
+            nodeinfo_requested: HashMap::new(),
+            positions: PositionRelay::from(&settings.positions),
+            last_position: HashMap::new(),
+            telemetry: TelemetryStore::new(&settings.telemetry),
+            announce_telemetry: settings.telemetry.announce,
+            detection,
+            presence,
+            announce_presence: settings.presence.announce,
+            presence_muted,
+            store_forward,
+            topology: Topology::new(),
+            topology_export: settings.topology.export_path.clone(),
+            node_db,
+            node_export_path: settings.node_export_path.clone()
+                .unwrap_or_else(|| DEFAULT_NODE_EXPORT_PATH.to_string()),
+            pending_traceroutes: HashMap::new(),
+            pending_admin: HashMap::new(),
+            retry: RetryPolicy::from(&settings.retry),
+            report_delivery_errors: settings.report_delivery_errors,
+            show_signal: settings.show_signal,
+            templates: Templates::try_from(&settings.templates)?,
+            filters: Filters::try_from(&settings.filters)?,
+            auto_reply,
+            auto_replied: HashMap::new(),
+            dm_forward_to: settings.direct_messages.forward_to.clone(),
+            owner: settings.owner.clone(),
+            time_sync: time_sync_interval(settings),
+            pending_acks: HashMap::new(),
+            outgoing: OutboundQueue::new(rate_limit.max_queue.unwrap_or(DEFAULT_MAX_QUEUE)),
+            dropped: Vec::new(),
+            limiter,
+            rate_limit: rate_limit.clone(),
+            backlog: Backlog::new(&settings.buffer, &radio_leg(config))?,
+            modem_preset: channel_table.modem_preset().unwrap_or(ModemPreset::LongFast),
+            next_send_at: Instant::now(),
+            queue_warning: rate_limit.queue_warning.unwrap_or(DEFAULT_QUEUE_WARNING),
+            queue_warned: false,
+            channel_table: connection.channel_table,
+            channel_keys,
+            dedup,
+            uplink,
+            metrics,
+        })
+    }
+
+    /// The airtime budget for messages from IRC. The configured region
+    /// overrides the device's.
+    fn rate_limiter(rate_limit: &RateLimitConfig, channel_table: &ChannelTable) -> Result<RateLimiter> {
+        let region = match &rate_limit.region {
+            Some(name) => Some(RegionCode::from_str_name(name).ok_or_else(|| {
+                anyhow::anyhow!("Unknown LoRa region \"{}\", expected e.g. \"EU_868\" or \"US\"", name)
+            })?),
+            None => channel_table.region(),
+        };
+        let duty_cycle = rate_limit.duty_cycle_percent.or_else(|| region.and_then(region_duty_cycle));
+        if let Some(duty_cycle) = duty_cycle {
+            info!("Limiting airtime of messages from IRC to {}% per hour", duty_cycle);
+        }
+        Ok(RateLimiter::new(rate_limit.messages_per_minute, duty_cycle))
+    }
+
+    /// Apply a reloaded config. Filters, channel mappings, rate limits and
+    /// the like change in place; a different serial port, TCP address or
+    /// BLE device needs the radio reconnected. Nothing changes if any setting
+    /// is invalid. Returns whether to reconnect.
+    async fn apply_config(&mut self, device: &DeviceConfig, settings: &MeshtasticConfig) -> Result<bool> {
+        let relay_ports = relay_ports(settings)?;
+        let blocked_nodes = parse_node_ids(&settings.blocked_nodes, "blocked_nodes")?;
+        let allowed_nodes = settings.allowed_nodes.as_deref()
+            .map(|nodes| parse_node_ids(nodes, "allowed_nodes"))
+            .transpose()?;
+        let presence_muted = parse_node_ids(&settings.presence.muted, "presence.muted")?;
+        let detection = DetectionRelay::try_from(&settings.detection)?;
+        let channel_keys = ChannelKeys::try_from(settings.channel_keys.as_slice())?;
+        let default_destination = default_destination(settings)?;
+        let auto_reply = auto_reply(settings)?;
+        let templates = Templates::try_from(&settings.templates)?;
+        let filters = Filters::try_from(&settings.filters)?;
+        let aliases = Aliases::load(settings.aliases_file.as_deref())?;
+        // A redetected port differs from the configured one without the
+        // radio having moved
+        let reconnect = (device.serial_port != self.device.serial_port && !settings.reconnect.redetect_serial_port)
+            || device.serial != self.device.serial
+            || device.tcp_address != self.device.tcp_address
+            || device.ble != self.device.ble;
+        let router = if reconnect {
+            None
+        } else {
+            Some(Self::resolve_routes(device, &self.channel_table)?)
+        };
+        if settings.rate_limit != self.rate_limit {
+            self.limiter = Self::rate_limiter(&settings.rate_limit, &self.channel_table)?;
+            self.rate_limit = settings.rate_limit.clone();
+        }
+        self.backlog.configure(&settings.buffer);
+        
+        self.outgoing.set_max_depth(settings.rate_limit.max_queue.unwrap_or(DEFAULT_MAX_QUEUE));
+        self.queue_warning = settings.rate_limit.queue_warning.unwrap_or(DEFAULT_QUEUE_WARNING);
+        self.relay_ports = relay_ports;
+        self.blocked_nodes = blocked_nodes;
+        self.allowed_nodes = allowed_nodes;
+        self.presence_muted = presence_muted;
+        self.announce_presence = settings.presence.announce;
+        self.detection = detection;
+        self.channel_keys = channel_keys;
+        self.default_destination = default_destination;
+        self.auto_reply = auto_reply;
+        self.dm_forward_to = settings.direct_messages.forward_to.clone();
+        self.unknown_ports = settings.unknown_ports.clone();
+        self.range_test = settings.range_test;
+        self.skip_via_mqtt = settings.skip_via_mqtt;
+        self.hop_limit = settings.hop_limit;
+        self.nodes.set_format(settings.sender_format);
+        self.nodes.set_aliases(aliases);
+        self.positions = PositionRelay::from(&settings.positions);
+        self.announce_telemetry = settings.telemetry.announce;
+        self.topology_export = settings.topology.export_path.clone();
+        self.node_export_path = settings.node_export_path.clone()
+            .unwrap_or_else(|| DEFAULT_NODE_EXPORT_PATH.to_string());
+        self.retry = RetryPolicy::from(&settings.retry);
+        self.report_delivery_errors = settings.report_delivery_errors;
+        self.show_signal = settings.show_signal;
+        self.templates = templates;
+        self.filters = filters;
+        self.time_sync = time_sync_interval(settings);
+        self.reconnect = settings.reconnect.clone();
+        self.stall_timeout = stall_timeout(settings);
+        self.device = device.clone();
+        self.name = device.display_name();
+        self.leg = radio_leg(device);
+        
+        if let Some(router) = router {
+            self.router = router;
+        }
+        Ok(reconnect)
+    }
+
+    /// Look up the device's index for the mesh channel of every route
+    fn resolve_routes(config: &DeviceConfig, channel_table: &ChannelTable) -> Result<Router> {
+        let mut routes = Vec::new();
+        for route in config.channel_routes() {
+            let index = if channel_table.is_empty() {
+                match &route.channel {
+                    ChannelRef::Index(index) => {
+                        warn!("The device did not report its channels, channel {} can't be checked", index);
+                        *index
+                    }
+                    ChannelRef::Name(name) => {
+                        return Err(anyhow::anyhow!(
+                            "Channel \"{}\" can't be resolved: the device did not report its channels",
+                            name
+                        ));
+                    }
+                }
+            } else {
+                channel_table.resolve(&route.channel)?
+            };
+            routes.push(BridgedChannel {
+                index,
+                name: channel_table.name(index).unwrap_or_else(|| index.to_string()),
+                irc_channel: route.irc_channel,
+                direction: route.direction,
+            });
+        }
+        Ok(Router::new(routes))
+    }
+
+    /// Pick up channel and modem changes after the device was configured again
+    fn refresh_channels(&mut self, channel_table: ChannelTable) {
+        info!("Device channels: {}", channel_table.describe());
+        match Self::resolve_routes(&self.device, &channel_table) {
+            Ok(router) => self.router = router,
+            Err(e) => error!("Keeping the previous channels of {}: {}", self.name, e),
+        }
+        if let Some(preset) = channel_table.modem_preset() {
+            self.modem_preset = preset;
+        }
+        self.channel_table = channel_table;
+    }
+
+    /// Connect to the radio and read its configuration
+    async fn open(config: &DeviceConfig) -> Result<Connection> {
         let stream_api = StreamApi::new();
         
+        // TCP and BLE take precedence over serial when configured
+        let (mut decoded_listener, stream_api) = if let Some(tcp_address) = &config.tcp_address {
+            Self::connect_tcp(stream_api, tcp_address).await?
+        } else if let Some(ble) = &config.ble {
+            Self::connect_ble(stream_api, ble).await?
+        } else {
+            Self::connect_serial(stream_api, config).await?
+        };
+        
+        // Give the device a moment to settle after connection
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        
+        info!("Meshtastic device connected, skipping initial packet wait");
+        
+        // Configure with a random ID
+        let config_id = utils::generate_rand_id();
+        let stream_api = stream_api
+            .configure(config_id)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to configure: {}", e))?;
+        
+        // Collect the channel table so channels can be selected by name
+        let (channel_table, startup_packets) =
+            Self::read_device_config(&mut decoded_listener, config_id).await;
+        
+        Ok(Connection {
+            stream_api,
+            decoded_listener,
+            channel_table,
+            startup_packets,
+        })
+    }
+
+    /// Read the packets the device sends in response to `configure` until it
+    /// signals completion, building the channel table along the way. The
+    /// packets are returned so `run` can still process them (node info etc).
+    async fn read_device_config(
+        decoded_listener: &mut mpsc::UnboundedReceiver<FromRadio>,
+        config_id: u32,
+    ) -> (ChannelTable, Vec<FromRadio>) {
+        let mut channel_table = ChannelTable::new();
+        let mut packets = Vec::new();
+        
+        let result = tokio::time::timeout(CONFIG_TIMEOUT, async {
+            while let Some(from_radio) = decoded_listener.recv().await {
+                channel_table.update(&from_radio);
+                let complete = matches!(
+                    from_radio.payload_variant,
+                    Some(from_radio::PayloadVariant::ConfigCompleteId(id)) if id == config_id
+                );
+                packets.push(from_radio);
+                if complete {
+                    break;
+                }
+            }
+        }).await;
+        
+        if result.is_err() {
+            error!("Timed out waiting for device configuration");
+        }
+        
+        (channel_table, packets)
+    }
+
+    async fn connect_serial(
+        stream_api: StreamApi,
+        config: &DeviceConfig,
+    ) -> Result<(mpsc::UnboundedReceiver<FromRadio>, ConnectedStreamApi<state::Connected>)> {
         let serial_port = config.serial_port.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Serial port not specified"))?;
             
-        info!("Connecting to Meshtastic device at {}", serial_port.display());
+        let baud_rate = config.serial.baud_rate.unwrap_or(DEFAULT_BAUD_RATE);
+        let dtr = config.serial.dtr.unwrap_or(true);  // Data Terminal Ready
+        let rts = config.serial.rts.unwrap_or(true);  // Request To Send
         
-        // Standard Meshtastic serial settings
-        let baud_rate = Some(115200);
-        let dtr = Some(true);  // Data Terminal Ready
-        let rts = Some(true);  // Request To Send
+        info!("Connecting to Meshtastic device at {} ({} baud, DTR {}, RTS {})",
+              serial_port.display(), baud_rate, dtr, rts);
         
         let serial_stream = utils::stream::build_serial_stream(
             serial_port.to_str().unwrap().to_string(),
-            baud_rate,
-            dtr,
-            rts,
+            Some(baud_rate),
+            Some(dtr),
+            Some(rts),
         ).map_err(|e| {
             if e.to_string().contains("Device or resource busy") {
                 anyhow::anyhow!(
@@ -52,50 +840,167 @@ impl MeshtasticHandler {
             }
         })?;
         
-        let (decoded_listener, stream_api) = stream_api.connect(serial_stream).await;
-        
-        // Give the device a moment to settle after connection
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        Ok(stream_api.connect(serial_stream).await)
+    }
+
+    async fn connect_tcp(
+        stream_api: StreamApi,
+        tcp_address: &str,
+    ) -> Result<(mpsc::UnboundedReceiver<FromRadio>, ConnectedStreamApi<state::Connected>)> {
+        // Meshtastic devices listen on port 4403 by default
+        let address = if tcp_address.contains(':') {
+            tcp_address.to_string()
+        } else {
+            format!("{}:{}", tcp_address, DEFAULT_TCP_PORT)
+        };
         
-        info!("Meshtastic device connected, skipping initial packet wait");
+        info!("Connecting to Meshtastic device at {} over TCP", address);
         
-        // Configure with a random ID
-        let config_id = utils::generate_rand_id();
-        let stream_api = stream_api
-            .configure(config_id)
+        let tcp_stream = utils::stream::build_tcp_stream(address.clone())
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to configure: {}", e))?;
+            .map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", address, e))?;
         
-        Ok(Self {
-            stream_api,
-            decoded_listener,
-            channel: config.channel,
-            node_names: HashMap::new(),
-        })
+        Ok(stream_api.connect(tcp_stream).await)
+    }
+
+    #[cfg(feature = "bluetooth")]
+    async fn connect_ble(
+        stream_api: StreamApi,
+        ble: &BleConfig,
+    ) -> Result<(mpsc::UnboundedReceiver<FromRadio>, ConnectedStreamApi<state::Connected>)> {
+        let ble_stream = crate::ble_connection::build_ble_stream(ble).await?;
+        Ok(stream_api.connect(ble_stream).await)
+    }
+
+    #[cfg(not(feature = "bluetooth"))]
+    async fn connect_ble(
+        _stream_api: StreamApi,
+        ble: &BleConfig,
+    ) -> Result<(mpsc::UnboundedReceiver<FromRadio>, ConnectedStreamApi<state::Connected>)> {
+        Err(anyhow::anyhow!(
+            "BLE device {} configured, but this build has no Bluetooth support. \
+            Rebuild with `cargo build --features bluetooth`",
+            ble.device
+        ))
     }
 
     pub async fn run(
         mut self,
         mut from_irc: mpsc::Receiver<IrcMessage>,
-        to_irc: mpsc::Sender<String>,
+        to_irc: mpsc::Sender<MeshMessage>,
+        mut shutdown: watch::Receiver<bool>,
+        mut reload: watch::Receiver<Config>,
+        device_index: usize,  // This radio's place in the config's list of radios
     ) -> Result<()> {
-        info!("Meshtastic handler run loop started, listening on channel {}", self.channel);
+        info!("Meshtastic handler for {} started, routing channels {}",
+              self.name, self.router.describe());
+        
+        self.handle_startup_packets(&to_irc).await;
+        if let Err(e) = self.apply_owner().await {
+            error!("{}", e);
+        }
+        if let Err(e) = self.request_startup_history().await {
+            error!("{}", e);
+        }
         
+        let mut ack_timer = tokio::time::interval(ACK_CHECK_INTERVAL);
+        let mut send_timer = tokio::time::interval(SEND_CHECK_INTERVAL);
+        let mut range_test_timer = tokio::time::interval(RANGE_TEST_CHECK_INTERVAL);
+        let mut presence_timer = tokio::time::interval(PRESENCE_CHECK_INTERVAL);
+        let mut stall_timer = tokio::time::interval(STALL_CHECK_INTERVAL);
+        // The first tick is immediate, so the clock is also set on startup
+        let mut time_sync_timer = tokio::time::interval(
+            self.time_sync.unwrap_or(Duration::from_secs(60 * DEFAULT_TIME_SYNC_INTERVAL_MINUTES))
+        );
         loop {
             tokio::select! {
-                Some(from_radio) = self.decoded_listener.recv() => {
+                from_radio = self.decoded_listener.recv() => {
+                    let Some(from_radio) = from_radio else {
+                        // The stream closes when the radio is unplugged, reboots or drops the connection
+                        error!("Lost connection to Meshtastic device {}", self.name);
+                        self.reconnect(&mut from_irc, &to_irc).await?;
+                        self.handle_startup_packets(&to_irc).await;
+                        continue;
+                    };
                     debug!("Received packet from Meshtastic radio");
+                    self.last_from_radio = Instant::now();
+                    self.stall_probed = false;
                     if let Err(e) = self.handle_meshtastic_packet(from_radio, &to_irc).await {
                         error!("Error handling Meshtastic packet: {}", e);
                     }
+                    // Set when the device rebooted and was configured again
+                    self.handle_startup_packets(&to_irc).await;
                 }
                 Some(message) = from_irc.recv() => {
                     info!("Received message from IRC to send to Meshtastic: {} - {}", 
                           message.sender, message.content);
-                    if let Err(e) = self.send_to_meshtastic(&message).await {
+                    if let Err(e) = self.send_to_meshtastic(&message, &to_irc).await {
                         error!("Error sending to Meshtastic: {}", e);
                     }
                 }
+                _ = send_timer.tick(), if !self.outgoing.is_empty() => {
+                    if let Err(e) = self.flush_outgoing(&to_irc).await {
+                        error!("Error sending queued messages: {}", e);
+                    }
+                }
+                _ = ack_timer.tick() => {
+                    if let Err(e) = self.check_ack_timeouts(&to_irc).await {
+                        error!("Error retrying unacknowledged messages: {}", e);
+                    }
+                }
+                _ = presence_timer.tick(), if self.announce_presence => {
+                    if let Err(e) = self.announce_offline_nodes(&to_irc).await {
+                        error!("Error announcing offline nodes: {}", e);
+                    }
+                }
+                _ = range_test_timer.tick(), if self.range_test == RangeTestMode::Summarize => {
+                    if let Err(e) = self.summarize_range_tests(&to_irc).await {
+                        error!("Error summarizing range tests: {}", e);
+                    }
+                }
+                _ = stall_timer.tick(), if self.stall_timeout.is_some() => {
+                    match self.check_stall(&to_irc).await {
+                        Ok(true) => {
+                            self.reconnect(&mut from_irc, &to_irc).await?;
+                            self.handle_startup_packets(&to_irc).await;
+                        }
+                        Ok(false) => {}
+                        Err(e) => error!("Error checking on {}: {}", self.name, e),
+                    }
+                }
+                _ = shutdown.changed() => {
+                    self.disconnect().await;
+                    return Ok(());
+                }
+                Ok(()) = reload.changed() => {
+                    let config = reload.borrow_and_update().clone();
+                    // Radios added to the config only start with the bridge
+                    let Some(device) = config.meshtastic.all_devices().into_iter().nth(device_index) else {
+                        continue;
+                    };
+                    let time_sync = self.time_sync;
+                    match self.apply_config(&device, &config.meshtastic).await {
+                        Ok(reconnect) => {
+                            info!("Applied the reloaded config to {}", self.name);
+                            if reconnect {
+                                info!("Connection settings of {} changed, reconnecting", self.name);
+                                self.reconnect(&mut from_irc, &to_irc).await?;
+                            }
+                        }
+                        Err(e) => error!("Keeping the previous config of {}: {}", self.name, e),
+                    }
+                    if self.time_sync != time_sync {
+                        time_sync_timer = tokio::time::interval(
+                            self.time_sync.unwrap_or(Duration::from_secs(60 * DEFAULT_TIME_SYNC_INTERVAL_MINUTES))
+                        );
+                    }
+                    self.handle_startup_packets(&to_irc).await;
+                }
+                _ = time_sync_timer.tick(), if self.time_sync.is_some() => {
+                    if let Err(e) = self.sync_time().await {
+                        error!("Error setting the time on {}: {}", self.name, e);
+                    }
+                }
                 else => {
                     debug!("No messages in either channel");
                     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -104,134 +1009,1481 @@ impl MeshtasticHandler {
         }
     }
 
-    async fn handle_meshtastic_packet(
-        &mut self,
-        from_radio: FromRadio,
-        to_irc: &mpsc::Sender<String>,
-    ) -> Result<()> {
-        match from_radio.payload_variant {
-            Some(meshtastic::protobufs::from_radio::PayloadVariant::Packet(mesh_packet)) => {
-                debug!("Received MeshPacket on channel {}, configured channel is {}", 
-                      mesh_packet.channel, self.channel);
-                
-                // Only process messages from our configured channel
-                if mesh_packet.channel == self.channel {
-                    self.process_mesh_packet(mesh_packet, to_irc).await?;
-                } else {
-                    debug!("Ignoring packet from channel {}", mesh_packet.channel);
-                }
-            }
-            Some(meshtastic::protobufs::from_radio::PayloadVariant::NodeInfo(node_info)) => {
-                // Store node information
-                let node_id = node_info.num;
-                if let Some(user) = node_info.user {
-                    let short_name = user.short_name.clone();
-                    if !short_name.is_empty() {
-                        info!("Discovered node: {} (ID: {:08x})", short_name, node_id);
-                        self.node_names.insert(node_id, short_name);
-                    }
-                }
-            }
-            Some(meshtastic::protobufs::from_radio::PayloadVariant::MyInfo(my_info)) => {
-                info!("Connected to Meshtastic node: ID {:08x}", my_info.my_node_num);
-            }
-            Some(other) => {
-                debug!("Received non-packet payload: {:?}", other);
-            }
-            None => {
-                debug!("Received empty payload");
+    async fn handle_startup_packets(&mut self, to_irc: &mpsc::Sender<MeshMessage>) {
+        for from_radio in std::mem::take(&mut self.startup_packets) {
+            if let Err(e) = self.handle_meshtastic_packet(from_radio, to_irc).await {
+                error!("Error handling Meshtastic packet: {}", e);
             }
         }
+    }
+
+    /// The radio rebooted without dropping the connection: ask for its
+    /// configuration again, as on connect, and pick up any channel changes.
+    /// The config packets are left in `startup_packets` for `run` to replay.
+    async fn reconfigure(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        info!("Meshtastic device {} rebooted, reloading its configuration", self.name);
+        to_irc.send(MeshMessage::new(format!(
+            "Meshtastic device {} rebooted, reloading its configuration", self.name
+        ))).await?;
         
+        let config_id = utils::generate_rand_id();
+        self.send_to_radio(Some(to_radio::PayloadVariant::WantConfigId(config_id))).await?;
+        let (channel_table, packets) =
+            Self::read_device_config(&mut self.decoded_listener, config_id).await;
+        self.refresh_channels(channel_table);
+        self.set_startup_packets(packets);
         Ok(())
     }
 
-    async fn process_mesh_packet(
-        &mut self,
-        packet: MeshPacket,
-        to_irc: &mpsc::Sender<String>,
-    ) -> Result<()> {
-        // Check if the packet wants an ACK
-        let wants_ack = packet.want_ack;
-        let packet_id = packet.id;
-        let from_node = packet.from;
-        
-        // Check if this is a Data packet with decoded payload
-        if let Some(payload_variant) = &packet.payload_variant {
-            match payload_variant {
-                mesh_packet::PayloadVariant::Decoded(data) => {
-                    // Only process text messages
-                    if data.portnum() == PortNum::TextMessageApp {
-                        if data.payload.len() > 0 {
-                            if let Ok(text) = std::str::from_utf8(&data.payload) {
-                                // Use short name if available, otherwise use ID
-                                let sender = self.node_names.get(&packet.from)
-                                    .cloned()
-                                    .unwrap_or_else(|| format!("{:08x}", packet.from));
-                                let message = format!("[mesh-{}]: {}", sender, text);
-                                
-                                info!("Received Meshtastic message: {}", message);
-                                to_irc.send(message).await?;
-                                debug!("Forwarded Meshtastic message to IRC");
-                                
-                                // Send ACK if requested
-                                if wants_ack && packet_id != 0 {
-                                    self.send_ack(packet_id, from_node).await?;
-                                }
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    // Ignore encrypted or other packet types
-                }
+    /// Queue freshly read config packets for `run` to replay. Their MyInfo is
+    /// noted first so replaying it doesn't look like another reboot.
+    fn set_startup_packets(&mut self, packets: Vec<FromRadio>) {
+        for from_radio in &packets {
+            if let Some(from_radio::PayloadVariant::MyInfo(my_info)) = &from_radio.payload_variant {
+                self.reboot_count = Some(my_info.reboot_count);
             }
         }
-        
-        Ok(())
+        self.startup_packets = packets;
     }
 
-    async fn send_to_meshtastic(&mut self, message: &IrcMessage) -> Result<()> {
-        let text = format!("[IRC-{}] {}", message.sender, message.content);
-        
-        // Create a text message data payload
-        let data = Data {
-            portnum: PortNum::TextMessageApp as i32,
-            payload: text.as_bytes().to_vec(),
-            want_response: false,
+    /// Watchdog for firmware that wedges with the connection still open. A
+    /// quiet radio is asked for its config halfway through the stall timeout,
+    /// which any live radio answers; if it still says nothing by the end of
+    /// the timeout, it needs reconnecting. Returns whether it does.
+    async fn check_stall(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<bool> {
+        let Some(stall_timeout) = self.stall_timeout else {
+            return Ok(false);
+        };
+        let quiet = self.last_from_radio.elapsed();
+        if quiet >= stall_timeout {
+            error!("Meshtastic device {} sent nothing for {}, reconnecting", self.name, format_duration(quiet));
+            to_irc.send(MeshMessage::new(format!(
+                "Meshtastic device {} stopped responding, reconnecting", self.name
+            ))).await?;
+            return Ok(true);
+        }
+        if quiet >= stall_timeout / 2 && !self.stall_probed {
+            debug!("Meshtastic device {} quiet for {}, checking it is alive", self.name, format_duration(quiet));
+            self.stall_probed = true;
+            self.send_to_radio(Some(to_radio::PayloadVariant::WantConfigId(utils::generate_rand_id()))).await?;
+        }
+        Ok(false)
+    }
+
+    /// Tell the radio we are leaving and close the connection cleanly, so the
+    /// device doesn't hold a half-open session that keeps the next client out
+    async fn disconnect(&mut self) {
+        if let Some(stream_api) = self.stream_api.take() {
+            Self::close(stream_api, &self.name).await;
+        }
+    }
+
+    async fn close(mut stream_api: ConnectedStreamApi, name: &str) {
+        info!("Disconnecting from Meshtastic device {}", name);
+        if let Err(e) = stream_api.send_to_radio_packet(Some(to_radio::PayloadVariant::Disconnect(true))).await {
+            debug!("Error telling {} we are disconnecting: {}", name, e);
+        }
+        // Closing the connection cancels the writer, so let the notice go out first
+        tokio::time::sleep(DISCONNECT_FLUSH_DELAY).await;
+        if let Err(e) = stream_api.disconnect().await {
+            error!("Error closing connection to {}: {}", name, e);
+        }
+    }
+
+    /// Tear down the dead connection and retry opening the device, backing
+    /// off between attempts, until it comes back. Messages from IRC meanwhile
+    /// are held in the backlog and sent once it is. Fails once
+    /// reconnect.max_attempts have failed.
+    async fn reconnect(&mut self, from_irc: &mut mpsc::Receiver<IrcMessage>, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        if let Some(stream_api) = self.stream_api.take() {
+            if let Err(e) = stream_api.disconnect().await {
+                debug!("Error closing old connection to {}: {}", self.name, e);
+            }
+        }
+        
+        let mut backoff = Backoff::new(&self.reconnect);
+        while let Some(delay) = backoff.next_delay() {
+            info!("Reconnecting to Meshtastic device {} in {:?}", self.name, delay);
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    Some(message) = from_irc.recv() => {
+                        debug!("Holding message for {} while disconnected: {}", self.name, message.content);
+                        self.backlog.push(message);
+                    }
+                }
+            }
+            
+            // A replugged device may come back under a different name
+            let is_serial = self.device.tcp_address.is_none() && self.device.ble.is_none();
+            if is_serial && self.reconnect.redetect_serial_port {
+                match serial_detector::detect_meshtastic_port().await {
+                    Ok(port) => self.device.serial_port = Some(port),
+                    Err(e) => {
+                        error!("Failed to auto-detect serial port: {}", e);
+                        continue;
+                    }
+                }
+            }
+            
+            match Self::open(&self.device).await {
+                Ok(connection) => {
+                    info!("Reconnected to Meshtastic device {}", self.name);
+                    self.refresh_channels(connection.channel_table);
+                    self.stream_api = Some(connection.stream_api);
+                    self.decoded_listener = connection.decoded_listener;
+                    self.set_startup_packets(connection.startup_packets);
+                    self.last_from_radio = Instant::now();
+                    self.stall_probed = false;
+                    let held = self.backlog.drain();
+                    if !held.is_empty() {
+                        info!("Sending {} messages held while {} was down", held.len(), self.name);
+                    }
+                    for message in held {
+                        if let Err(e) = self.send_to_meshtastic(&message, to_irc).await {
+                            error!("Error sending to Meshtastic: {}", e);
+                        }
+                    }
+                    return Ok(());
+                }
+                Err(e) => error!("Failed to reconnect to {}: {}", self.name, e),
+            }
+        }
+        Err(anyhow::anyhow!("Gave up reconnecting to {} after {} attempts", self.name, backoff.attempts()))
+    }
+
+    async fn handle_meshtastic_packet(
+        &mut self,
+        from_radio: FromRadio,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        match from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::Packet(mut mesh_packet)) => {
+                debug!("Received MeshPacket on channel {}", mesh_packet.channel);
+                self.uplink(&mesh_packet);
+                
+                // Only process messages from our configured channels, channels
+                // we have a key for, or direct messages to our own node on any channel
+                let bridged = match self.decrypt(&mut mesh_packet) {
+                    Some(decrypted) => Some(decrypted),
+                    None => self.router.relaying(mesh_packet.channel).cloned(),
+                };
+                let direct = self.my_node_num != 0 && mesh_packet.to == self.my_node_num;
+                if self.skip_via_mqtt && mesh_packet.via_mqtt {
+                    // The same message usually reaches IRC by another path
+                    // too, e.g. over LoRa or through an MQTT bridge
+                    debug!("Ignoring packet {} from {:08x} that arrived via MQTT", mesh_packet.id, mesh_packet.from);
+                } else if bridged.is_some() || direct {
+                    if self.dedup.lock().unwrap().was_sent(mesh_packet.id) {
+                        debug!("Ignoring our own packet {} from {:08x}", mesh_packet.id, mesh_packet.from);
+                        return Ok(());
+                    }
+                    // Another radio may already have relayed this packet
+                    if self.dedup.lock().unwrap().is_duplicate(mesh_packet.from, mesh_packet.id) {
+                        debug!("Ignoring duplicate packet {} from {:08x}", mesh_packet.id, mesh_packet.from);
+                        self.metrics.record_duplicate();
+                        return Ok(());
+                    }
+                    self.process_mesh_packet(mesh_packet, bridged.as_ref(), to_irc).await?;
+                } else {
+                    debug!("Ignoring packet from channel {}", mesh_packet.channel);
+                }
+            }
+            Some(from_radio::PayloadVariant::NodeInfo(node_info)) => {
+                // Store node information
+                self.node_db.lock().unwrap().update_from_node_info(&node_info);
+                if let Some(user) = node_info.user {
+                    self.learn_node(node_info.num, user);
+                }
+            }
+            Some(from_radio::PayloadVariant::MyInfo(my_info)) => {
+                info!("Connected to Meshtastic node {}: ID {:08x}", self.name, my_info.my_node_num);
+                self.my_node_num = my_info.my_node_num;
+                // A new reboot count mid-session means we missed the reboot notice
+                let rebooted = self.reboot_count.is_some_and(|count| count != my_info.reboot_count);
+                self.reboot_count = Some(my_info.reboot_count);
+                if rebooted {
+                    self.reconfigure(to_irc).await?;
+                }
+            }
+            Some(from_radio::PayloadVariant::Rebooted(_)) => {
+                self.reconfigure(to_irc).await?;
+            }
+            Some(other) => {
+                debug!("Received non-packet payload: {:?}", other);
+            }
+            None => {
+                debug!("Received empty payload");
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Pass a packet heard over the air on to MQTT, encrypted as a gateway
+    /// would publish it
+    fn uplink(&self, packet: &MeshPacket) {
+        let Some(uplink) = &self.uplink else {
+            return;
+        };
+        if packet.via_mqtt || self.my_node_num == 0 {
+            return;
+        }
+        // Packets the radio couldn't decrypt carry a channel hash rather than
+        // an index, so their channel is unknown
+        if !matches!(packet.payload_variant, Some(mesh_packet::PayloadVariant::Decoded(_))) {
+            return;
+        }
+        let (Some(channel), Some(name)) = (self.channel_table.channel(packet.channel), self.channel_table.name(packet.channel)) else {
+            return;
+        };
+        let psk = channel.settings.as_ref().map(|settings| settings.psk.as_slice()).unwrap_or_default();
+        let message = Uplink {
+            packet: crypto::encrypt(packet, &name, psk).unwrap_or_else(|| packet.clone()),
+            channel_id: name,
+            gateway_id: format!("!{:08x}", self.my_node_num),
+        };
+        if uplink.try_send(message).is_err() {
+            debug!("MQTT uplink queue full, dropping packet {} from {:08x}", packet.id, packet.from);
+        }
+    }
+
+    /// Decrypt a packet the radio couldn't, with a configured channel key.
+    /// The key's channel stands in for a bridged one; its index is the
+    /// channel hash the packet carries.
+    fn decrypt(&self, packet: &mut MeshPacket) -> Option<BridgedChannel> {
+        let (data, key) = self.channel_keys.decrypt(packet)?;
+        debug!("Decrypted packet {} from {:08x} on channel {}", packet.id, packet.from, key.name);
+        packet.payload_variant = Some(mesh_packet::PayloadVariant::Decoded(data));
+        Some(BridgedChannel {
+            index: packet.channel,
+            name: key.name.clone(),
+            irc_channel: key.irc_channel.clone(),
+            direction: RouteDirection::MeshToIrc,
+        })
+    }
+
+    async fn process_mesh_packet(
+        &mut self,
+        packet: MeshPacket,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        // Ignore packets still encrypted; there is no key for their channel
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+            return Ok(());
+        };
+        
+        if self.blocked_nodes.contains(&packet.from) {
+            debug!("Ignoring packet from blocked node {:08x}", packet.from);
+            return Ok(());
+        }
+        if !self.is_allowed(packet.from) {
+            debug!("Dropping packet from unlisted node {:08x}", packet.from);
+            self.metrics.record_dropped_unlisted();
+            return Ok(());
+        }
+        
+        // Our own radio doesn't hear itself, so it is never tracked
+        let back_online = packet.from != self.my_node_num && self.presence.lock().unwrap().heard(packet.from);
+        if back_online {
+            self.announce_presence_change(packet.from, "is back online", to_irc).await?;
+        }
+        
+        // Packets relayed through MQTT have no SNR
+        let snr = (packet.rx_snr != 0.0 && !packet.via_mqtt).then_some(packet.rx_snr);
+        let text = data.portnum() == PortNum::TextMessageApp;
+        self.metrics.record_node_packet(packet.from, data.payload.len(), text, snr);
+        self.node_db.lock().unwrap().heard(packet.from, snr, &self.leg);
+        if data.portnum() == PortNum::PositionApp {
+            if let Ok(position) = Position::decode(data.payload.as_slice()) {
+                self.node_db.lock().unwrap().update_position(packet.from, &position);
+            }
+        }
+        
+        if is_unknown_port(data.portnum) {
+            return self.dump_unknown_port(&packet, data, bridged, to_irc).await;
+        }
+        
+        // Telemetry is always recorded for !telemetry, other ports only
+        // matter to IRC when they are relayed
+        let relayed = self.relay_ports.contains(&data.portnum());
+        match data.portnum() {
+            PortNum::RangeTestApp => self.process_range_test(&packet, data, bridged, to_irc).await,
+            PortNum::TextMessageApp if relayed => self.process_text_message(&packet, data, bridged, to_irc).await,
+            PortNum::PositionApp if relayed => self.process_position(&packet, data, bridged, to_irc).await,
+            PortNum::WaypointApp if relayed => self.process_waypoint(&packet, data, bridged, to_irc).await,
+            PortNum::TelemetryApp => self.process_telemetry(&packet, data, bridged, to_irc).await,
+            PortNum::DetectionSensorApp if relayed => self.process_detection(&packet, data, bridged, to_irc).await,
+            PortNum::NeighborinfoApp => self.process_neighbor_info(&packet, data).await,
+            PortNum::StoreForwardApp => self.process_store_forward(&packet, data, bridged, to_irc).await,
+            PortNum::NodeinfoApp => {
+                let user = User::decode(data.payload.as_slice())?;
+                self.learn_node(packet.from, user);
+                Ok(())
+            }
+            PortNum::RoutingApp => self.process_routing(&packet, data, to_irc).await,
+            PortNum::TracerouteApp => self.process_traceroute(&packet, data, to_irc).await,
+            PortNum::AdminApp => self.process_admin(&packet, data, to_irc).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn process_range_test(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if self.range_test != RangeTestMode::Relay {
+            self.count_range_test(packet, bridged);
+            return Ok(());
+        }
+        let Ok(text) = std::str::from_utf8(&data.payload) else {
+            return Ok(());
+        };
+        let line = self.report_line(packet, bridged, &format!("range test: {}", text.trim()));
+        self.relay_to_irc(to_irc, bridged, line, None).await
+    }
+
+    fn count_range_test(&mut self, packet: &MeshPacket, bridged: Option<&BridgedChannel>) {
+        debug!("Suppressing range test packet from {:08x}", packet.from);
+        if self.range_test == RangeTestMode::Summarize {
+            self.range_tests.record(packet.from, &channel_name(packet, bridged), bridged.and_then(|bridged| bridged.irc_channel.clone()));
+        }
+    }
+
+    /// Post one line for each range test that has finished
+    async fn summarize_range_tests(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        for summary in self.range_tests.finished(RANGE_TEST_IDLE) {
+            let sender = self.sender_name(summary.node);
+            let values = TemplateValues { sender: &sender, channel: &summary.channel, snr: None };
+            let content = self.templates.to_irc(&values, &format!("sent {} range test packets", summary.packets));
+            info!("{}", content);
+            to_irc.send(MeshMessage {
+                content,
+                channel: summary.irc_channel,
+                chat: None,
+            }).await?;
+        }
+        Ok(())
+    }
+
+    async fn announce_offline_nodes(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        let offline = self.presence.lock().unwrap().newly_offline();
+        for (node, silent) in offline {
+            let state = format!("appears offline, last heard {} ago", format_duration(silent));
+            self.announce_presence_change(node, &state, to_irc).await?;
+        }
+        Ok(())
+    }
+
+    async fn announce_presence_change(
+        &self,
+        node: u32,
+        state: &str,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if !self.announce_presence || self.presence_muted.contains(&node) {
+            return Ok(());
+        }
+        let content = format!("Node {} {}", self.sender_name(node), state);
+        info!("{}", content);
+        to_irc.send(MeshMessage::new(content)).await?;
+        Ok(())
+    }
+
+    /// Show traffic of apps the bridge doesn't know, when enabled
+    async fn dump_unknown_port(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if !self.unknown_ports.enabled {
+            return Ok(());
+        }
+        match self.unknown_ports.sink {
+            DumpSink::Log => {
+                let dump = dump_packet(packet, data, self.unknown_ports.format, None);
+                info!("Packet from {:08x}: {}", packet.from, dump);
+                Ok(())
+            }
+            DumpSink::Irc => {
+                let dump = dump_packet(packet, data, self.unknown_ports.format, Some(DUMP_MAX_BYTES));
+                let line = self.report_line(packet, bridged, &dump);
+                self.relay_to_irc(to_irc, bridged, line, None).await
+            }
+        }
+    }
+
+    async fn process_text_message(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if data.payload.is_empty() {
+            return Ok(());
+        }
+        let Ok(text) = std::str::from_utf8(&data.payload) else {
+            return Ok(());
+        };
+        
+        // Range tests are sometimes run over the text port
+        if is_range_test(text) && self.range_test != RangeTestMode::Relay {
+            self.count_range_test(packet, bridged);
+            return Ok(());
+        }
+        
+        if !self.nodes.contains(packet.from) {
+            self.request_nodeinfo(packet.from, packet.channel);
+        }
+        
+        let Some(filtered) = self.filters.mesh_to_irc.apply(text) else {
+            debug!("Filtered out message from {:08x}: {}", packet.from, text);
+            self.metrics.record_filtered();
+            return Ok(());
+        };
+        let sender = self.sender_name(packet.from);
+        let chat = Chat { sender: sender.clone(), text: filtered.to_string() };
+        let filtered = self.nodes.aliases().mentions_to_irc(&filtered);
+        let text: &str = &filtered;
+        let signal = if self.show_signal { signal_suffix(packet) } else { String::new() };
+        if self.my_node_num != 0 && packet.to == self.my_node_num {
+            // Direct messages go to the main IRC channel, or privately to
+            // the nick they are forwarded to
+            let message = format!("[mesh-{}] (DM): {}{}", sender, text, signal);
+            info!("Received Meshtastic message: {}", message);
+            to_irc.send(MeshMessage {
+                content: message,
+                channel: self.dm_forward_to.clone(),
+                chat: None,
+            }).await?;
+            self.send_auto_reply(packet.from, packet.channel);
+        } else {
+            let channel = channel_name(packet, bridged);
+            let values = TemplateValues {
+                sender: &sender,
+                channel: &channel,
+                snr: heard_snr(packet),
+            };
+            let line = format!("{}{}", self.templates.to_irc(&values, text), signal);
+            self.relay_to_irc(to_irc, bridged, line, Some(chat)).await?;
+        }
+        debug!("Forwarded Meshtastic message to IRC");
+        
+        // Send ACK if requested
+        if packet.want_ack && packet.id != 0 {
+            self.send_ack(packet.id, packet.from);
+        }
+        
+        Ok(())
+    }
+
+    async fn process_position(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        let position = Position::decode(data.payload.as_slice())?;
+        let Some(description) = format_position(&position) else {
+            debug!("Ignoring position without a fix from {:08x}", packet.from);
+            return Ok(());
+        };
+        
+        // Rate limit per node so GPS beacons don't flood the channel
+        let now = Instant::now();
+        if let Some(last) = self.last_position.get(&packet.from) {
+            if now.duration_since(*last) < self.positions.interval {
+                debug!("Rate limiting position from {:08x}", packet.from);
+                return Ok(());
+            }
+        }
+        self.last_position.insert(packet.from, now);
+        
+        let line = self.report_line(packet, bridged, &format!("position: {}", description));
+        self.relay_to_irc(to_irc, bridged, line, None).await
+    }
+
+    async fn process_waypoint(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        let waypoint = Waypoint::decode(data.payload.as_slice())?;
+        let line = self.report_line(packet, bridged, &format!("waypoint: {}", format_waypoint(&waypoint)));
+        self.relay_to_irc(to_irc, bridged, line, None).await
+    }
+
+    async fn process_detection(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if !self.detection.relays(packet.from) {
+            return Ok(());
+        }
+        
+        // The sensor module sends its configured alert name as plain text
+        let Ok(text) = std::str::from_utf8(&data.payload) else {
+            debug!("Ignoring detection alert that is not UTF-8 from {:08x}", packet.from);
+            return Ok(());
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        
+        info!("Detection alert from {:08x}: {}", packet.from, text);
+        let line = format!("[sensor-{}] {}", self.sender_name(packet.from), text);
+        self.relay_to_irc(to_irc, bridged, line, None).await
+    }
+
+    async fn process_neighbor_info(&mut self, packet: &MeshPacket, data: &Data) -> Result<()> {
+        let info = NeighborInfo::decode(data.payload.as_slice())?;
+        // The report names the node it describes; fall back to the sender
+        let node = if info.node_id != 0 { info.node_id } else { packet.from };
+        debug!("NeighborInfo from {:08x}: {} neighbors", node, info.neighbors.len());
+        self.topology.update(node, &info);
+        
+        if let Some(path) = &self.topology_export {
+            let json = self.topology.to_json(&self.nodes)?;
+            tokio::fs::write(path, json).await
+                .map_err(|e| anyhow::anyhow!("Failed to write topology to {}: {}", path, e))?;
+        }
+        Ok(())
+    }
+
+    async fn process_store_forward(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        let message = StoreAndForward::decode(data.payload.as_slice())?;
+        let router = self.sender_name(self.store_forward.router.unwrap_or(packet.from));
+        match (message.rr(), message.variant) {
+            (store_and_forward::RequestResponse::RouterHeartbeat, Some(store_and_forward::Variant::Heartbeat(heartbeat))) => {
+                // Prefer the primary router when there is more than one
+                if self.store_forward.router.is_none() && heartbeat.secondary == 0 {
+                    info!("Found Store & Forward router {:08x}", packet.from);
+                    self.store_forward.router = Some(packet.from);
+                    self.request_startup_history().await?;
+                }
+            }
+            (store_and_forward::RequestResponse::RouterHistory, Some(store_and_forward::Variant::History(history))) => {
+                self.store_forward.last_request = history.last_request;
+                let requested_by = self.store_forward.pending.take();
+                info!("Store & Forward router {:08x} is replaying {} messages", packet.from, history.history_messages);
+                if let Some(channel) = requested_by {
+                    to_irc.send(MeshMessage {
+                        content: format!(
+                            "Store & Forward router {} is replaying {} messages from the last {} minutes",
+                            router, history.history_messages, history.window / 60_000
+                        ),
+                        channel,
+                        chat: None,
+                    }).await?;
+                }
+            }
+            (store_and_forward::RequestResponse::RouterBusy | store_and_forward::RequestResponse::RouterError, _) => {
+                if let Some(channel) = self.store_forward.pending.take() {
+                    to_irc.send(MeshMessage {
+                        content: format!("Store & Forward router {} can't replay history right now", router),
+                        channel,
+                        chat: None,
+                    }).await?;
+                }
+            }
+            (rr @ (store_and_forward::RequestResponse::RouterTextBroadcast | store_and_forward::RequestResponse::RouterTextDirect),
+             Some(store_and_forward::Variant::Text(text))) => {
+                let Ok(text) = std::str::from_utf8(&text) else {
+                    return Ok(());
+                };
+                // Our own IRC messages come back with the rest of the history
+                if packet.from == self.my_node_num || self.templates.is_from_irc(text) || text.is_empty() {
+                    return Ok(());
+                }
+                let Some(filtered) = self.filters.mesh_to_irc.apply(text) else {
+                    debug!("Filtered out replayed message from {:08x}: {}", packet.from, text);
+                    self.metrics.record_filtered();
+                    return Ok(());
+                };
+                let filtered = self.nodes.aliases().mentions_to_irc(&filtered);
+                let text: &str = &filtered;
+                let age = match packet.rx_time {
+                    0 => format_age(packet.rx_time),
+                    rx_time => format!("{}, {}", self.templates.timestamp(rx_time), format_age(rx_time)),
+                };
+                let sender = self.sender_name(packet.from);
+                if rr == store_and_forward::RequestResponse::RouterTextDirect {
+                    let message = format!("[mesh-{}] (DM, {}): {}", sender, age, text);
+                    info!("Replayed Meshtastic message: {}", message);
+                    to_irc.send(MeshMessage::new(message)).await?;
+                } else if bridged.is_some() {
+                    self.relay_to_irc(to_irc, bridged, format!("[mesh-{}] ({}): {}", sender, age, text), None).await?;
+                }
+            }
+            (rr, _) => debug!("Ignoring Store & Forward {:?} from {:08x}", rr, packet.from),
+        }
+        Ok(())
+    }
+
+    async fn process_telemetry(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        bridged: Option<&BridgedChannel>,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        let telemetry = Telemetry::decode(data.payload.as_slice())?;
+        debug!("Telemetry from {:08x}: {:?}", packet.from, telemetry.variant);
+        
+        let events = self.telemetry.update(packet.from, &telemetry);
+        if let Some(telemetry::Variant::DeviceMetrics(metrics)) = &telemetry.variant {
+            self.node_db.lock().unwrap().update_device_metrics(packet.from, metrics);
+        }
+        let report = NodeTelemetry::from_report(&telemetry);
+        if self.relay_ports.contains(&PortNum::TelemetryApp) && !report.is_empty() {
+            let line = self.report_line(packet, bridged, &format!("telemetry: {}", report.describe()));
+            self.relay_to_irc(to_irc, bridged, line, None).await?;
+        }
+        if !self.announce_telemetry {
+            return Ok(());
+        }
+        for event in events {
+            let line = self.report_line(packet, bridged, &event.describe());
+            self.relay_to_irc(to_irc, bridged, line, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Match ACKs and NAKs against the messages we sent from IRC
+    async fn process_routing(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        let routing = Routing::decode(data.payload.as_slice())?;
+        let error = match routing.variant {
+            Some(routing::Variant::ErrorReason(reason)) => {
+                routing::Error::try_from(reason).unwrap_or(routing::Error::BadRequest)
+            }
+            // Route requests and replies are only used by the firmware itself
+            _ => return Ok(()),
+        };
+        
+        if error == routing::Error::None {
+            if self.pending_acks.remove(&data.request_id).is_some() {
+                debug!("Packet {} acknowledged by {:08x}", data.request_id, packet.from);
+            }
+            return Ok(());
+        }
+        
+        let reason = describe_routing_error(error);
+        if let Some(pending) = self.pending_acks.remove(&data.request_id) {
+            error!("Delivery of {} failed: {}", pending.description, reason);
+            if pending.packet.to != BROADCAST_ADDR {
+                self.metrics.record_node_nak(pending.packet.to);
+            }
+            if self.report_delivery_errors && pending.attempts <= self.retry.max_retries {
+                to_irc.send(MeshMessage {
+                    content: format!("Delivery of {} failed: {}, retrying", pending.description, reason),
+                    channel: pending.irc_channel.clone(),
+                    chat: None,
+                }).await?;
+            }
+            self.retry_or_fail(pending, reason, to_irc).await
+        } else if let Some(pending) = self.pending_traceroutes.remove(&data.request_id) {
+            self.metrics.record_node_nak(pending.node);
+            let line = format!("Traceroute to {} failed: {}", self.sender_name(pending.node), reason);
+            error!("{}", line);
+            to_irc.send(MeshMessage {
+                content: line,
+                channel: pending.irc_channel,
+                chat: None,
+            }).await?;
+            Ok(())
+        } else {
+            debug!("Packet {} reported error from {:08x}: {}", data.request_id, packet.from, reason);
+            Ok(())
+        }
+    }
+
+    async fn check_ack_timeouts(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        let ack_timeout = self.retry.ack_timeout;
+        let expired: Vec<u32> = self.pending_acks.iter()
+            .filter(|(_, pending)| pending.timed_out(ack_timeout))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some(pending) = self.pending_acks.remove(&id) {
+                self.retry_or_fail(pending, "no ACK received", to_irc).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send an unacknowledged message again, or tell IRC it could not be
+    /// delivered once it has used up its retries
+    async fn retry_or_fail(
+        &mut self,
+        mut pending: PendingAck,
+        reason: &str,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if pending.attempts > self.retry.max_retries {
+            error!("Giving up on {} after {} attempts: {}", pending.description, pending.attempts, reason);
+            to_irc.send(MeshMessage {
+                content: format!("Failed to deliver {} after {} attempts ({})",
+                                 pending.description, pending.attempts, reason),
+                channel: pending.irc_channel,
+                chat: None,
+            }).await?;
+            return Ok(());
+        }
+        
+        info!("Retrying {} ({}), attempt {}", pending.description, reason, pending.attempts + 1);
+        pending.attempts += 1;
+        let priority = text_priority(pending.packet.to);
+        self.queue_packet(priority, pending);
+        Ok(())
+    }
+
+    async fn process_traceroute(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        // Only responses to our own requests are of interest
+        let Some(pending) = self.pending_traceroutes.remove(&data.request_id) else {
+            debug!("Ignoring traceroute packet from {:08x}", packet.from);
+            return Ok(());
+        };
+        
+        let route = RouteDiscovery::decode(data.payload.as_slice())?;
+        let mut line = format!(
+            "Traceroute to {}: {}",
+            self.sender_name(pending.node),
+            self.format_route(self.my_node_num, &route.route, pending.node, &route.snr_towards)
+        );
+        // Older firmware doesn't record the way back
+        if !route.route_back.is_empty() || !route.snr_back.is_empty() {
+            line.push_str(" | back: ");
+            line.push_str(&self.format_route(pending.node, &route.route_back, self.my_node_num, &route.snr_back));
+        }
+        
+        info!("Traceroute response: {}", line);
+        to_irc.send(MeshMessage {
+            content: line,
+            channel: pending.irc_channel,
+            chat: None,
+        }).await?;
+        Ok(())
+    }
+
+    async fn process_admin(
+        &mut self,
+        packet: &MeshPacket,
+        data: &Data,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        // Only responses to our own requests are of interest
+        let Some(pending) = self.pending_admin.remove(&data.request_id) else {
+            debug!("Ignoring admin packet from {:08x}", packet.from);
+            return Ok(());
+        };
+        
+        let admin = AdminMessage::decode(data.payload.as_slice())?;
+        let content = match admin.payload_variant {
+            Some(admin_message::PayloadVariant::GetConfigResponse(config)) => match config.payload_variant {
+                Some(section) => format!("Config of {}: {}", self.name, serde_json::to_string(&section)?),
+                None => format!("Config of {} is empty", self.name),
+            },
+            other => {
+                debug!("Unexpected admin response: {:?}", other);
+                return Ok(());
+            }
+        };
+        
+        to_irc.send(MeshMessage {
+            content,
+            channel: pending.irc_channel,
+            chat: None,
+        }).await?;
+        Ok(())
+    }
+
+    /// e.g. "base -> rptr (6.25dB) -> abcd (-3.50dB)". Each SNR is the one the
+    /// hop heard the previous node at, in dB scaled by 4.
+    fn format_route(&self, start: u32, hops: &[u32], end: u32, snrs: &[i32]) -> String {
+        let mut nodes = vec![self.sender_name(start)];
+        for (i, node) in hops.iter().chain(std::iter::once(&end)).enumerate() {
+            let name = self.sender_name(*node);
+            nodes.push(match snrs.get(i) {
+                Some(snr) if *snr != UNKNOWN_SNR => format!("{} ({:.2}dB)", name, *snr as f32 / 4.0),
+                _ => name,
+            });
+        }
+        nodes.join(" -> ")
+    }
+
+    fn learn_node(&mut self, node: u32, user: User) {
+        self.node_db.lock().unwrap().update_user(node, &user);
+        if node == self.my_node_num {
+            self.my_user = Some(user.clone());
+        }
+        if self.nodes.insert(node, &user) {
+            info!("Discovered node: {} / {} (ID: {:08x})", user.short_name, user.long_name, node);
+        }
+    }
+
+    /// The node's name in the configured sender format
+    fn sender_name(&self, node: u32) -> String {
+        self.nodes.display(node)
+    }
+
+    /// A line about a packet that isn't chat, laid out by the mesh_to_irc
+    /// template like chat is
+    fn report_line(&self, packet: &MeshPacket, bridged: Option<&BridgedChannel>, report: &str) -> String {
+        let sender = self.sender_name(packet.from);
+        let channel = channel_name(packet, bridged);
+        let values = TemplateValues { sender: &sender, channel: &channel, snr: heard_snr(packet) };
+        self.templates.to_irc(&values, report)
+    }
+
+    /// Post a line about a mesh packet to every IRC channel its mesh channel
+    /// is routed to, tagged with the mesh channel when several are bridged.
+    /// `chat` is what was said, when the line is chat rather than a report.
+    async fn relay_to_irc(
+        &self,
+        to_irc: &mpsc::Sender<MeshMessage>,
+        bridged: Option<&BridgedChannel>,
+        line: String,
+        chat: Option<Chat>,
+    ) -> Result<()> {
+        let Some(bridged) = bridged else {
+            info!("Received Meshtastic message: {}", line);
+            to_irc.send(MeshMessage { content: line, channel: None, chat }).await?;
+            return Ok(());
+        };
+        let content = if self.router.is_multi_channel() {
+            format!("[{}] {}", bridged.name, line)
+        } else {
+            line
+        };
+        info!("Received Meshtastic message: {}", content);
+        for irc_channel in self.router.irc_channels(bridged) {
+            to_irc.send(MeshMessage {
+                content: content.clone(),
+                channel: irc_channel,
+                chat: chat.clone(),
+            }).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_to_meshtastic(
+        &mut self,
+        message: &IrcMessage,
+        to_irc: &mpsc::Sender<MeshMessage>,
+    ) -> Result<()> {
+        if let Some(command) = &message.command {
+            let reply = self.answer_command(command, message.channel.clone()).await?;
+            to_irc.send(MeshMessage {
+                content: reply,
+                channel: message.channel.clone(),
+                chat: None,
+            }).await?;
+            return Ok(());
+        }
+        
+        if let Some(destination) = &message.destination {
+            let Some(node) = self.resolve_node(destination) else {
+                to_irc.send(MeshMessage {
+                    content: format!("Unknown node: {}", destination),
+                    channel: message.channel.clone(),
+                    chat: None,
+                }).await?;
+                return Ok(());
+            };
+            if !self.is_allowed(node) {
+                debug!("Dropping direct message to unlisted node {:08x}", node);
+                self.metrics.record_dropped_unlisted();
+                return Ok(());
+            }
+            // Direct messages go out on the primary bridged channel
+            let channel = self.router.primary().index;
+            self.queue_text(message, channel, node);
+            return self.flush_outgoing(to_irc).await;
+        }
+        
+        // Send on the mesh channel asked for, or on every mesh channel routed
+        // from the IRC channel it came from
+        let indexes: Vec<u32> = match message.mesh_channel {
+            Some(index) if self.router.accepts_from_irc(index) => vec![index],
+            Some(index) => {
+                to_irc.send(MeshMessage {
+                    content: format!("Mesh channel {} is not bridged", index),
+                    channel: message.channel.clone(),
+                    chat: None,
+                }).await?;
+                return Ok(());
+            }
+            None => self.router.to_mesh(&message.channel),
+        };
+        for index in indexes {
+            self.queue_text(message, index, self.default_destination);
+        }
+        self.flush_outgoing(to_irc).await
+    }
+
+    /// Answer a bridge command. `irc_channel` is where it was asked, for
+    /// commands whose result arrives later.
+    async fn answer_command(&mut self, command: &MeshCommand, irc_channel: Option<String>) -> Result<String> {
+        match command {
+            MeshCommand::Telemetry { node } => {
+                let Some(num) = self.resolve_node(node) else {
+                    return Ok(format!("Unknown node: {}", node));
+                };
+                Ok(match self.telemetry.get(num) {
+                    Some(telemetry) => format!("[mesh-{}] {}", self.sender_name(num), telemetry.describe()),
+                    None => format!("No telemetry from {} yet", self.sender_name(num)),
+                })
+            }
+            MeshCommand::Environment { node } => {
+                let Some(num) = self.resolve_node(node) else {
+                    return Ok(format!("Unknown node: {}", node));
+                };
+                Ok(match self.telemetry.get(num).and_then(NodeTelemetry::describe_environment) {
+                    Some(environment) => format!("[mesh-{}] {}", self.sender_name(num), environment),
+                    None => format!("No environment readings from {} yet", self.sender_name(num)),
+                })
+            }
+            MeshCommand::Traceroute { node } => {
+                let Some(num) = self.resolve_node(node) else {
+                    return Ok(format!("Unknown node: {}", node));
+                };
+                self.send_traceroute(num, irc_channel);
+                Ok(format!("Traceroute to {} sent, waiting for a response", self.sender_name(num)))
+            }
+            MeshCommand::Topology { node: Some(node) } => {
+                let Some(num) = self.resolve_node(node) else {
+                    return Ok(format!("Unknown node: {}", node));
+                };
+                Ok(self.topology.describe(num, &self.nodes)
+                    .unwrap_or_else(|| format!("No neighbor info from {} yet", self.sender_name(num))))
+            }
+            MeshCommand::Topology { node: None } => {
+                let mut lines = vec![format!("Topology: {}", self.topology.summary())];
+                lines.extend(self.topology.describe_all(&self.nodes));
+                Ok(lines.join("\n"))
+            }
+            MeshCommand::Stats { node } => {
+                let Some(num) = self.resolve_node(node) else {
+                    return Ok(format!("Unknown node: {}", node));
+                };
+                Ok(match self.metrics.node_stats(num) {
+                    Some(stats) => format!("[mesh-{}] {}", self.sender_name(num), stats.describe()),
+                    None => format!("Nothing heard from {} yet", self.sender_name(num)),
+                })
+            }
+            MeshCommand::History { minutes } => {
+                let Some(router) = self.store_forward.router else {
+                    return Ok("No Store & Forward router heard yet".to_string());
+                };
+                let minutes = minutes.unwrap_or(self.store_forward.window_minutes);
+                self.request_history(minutes, irc_channel);
+                Ok(format!("Requested the last {} minutes of messages from {}", minutes, self.sender_name(router)))
+            }
+            MeshCommand::Admin(admin) => self.answer_admin(admin, irc_channel).await,
+        }
+    }
+
+    async fn answer_admin(&mut self, command: &AdminCommand, irc_channel: Option<String>) -> Result<String> {
+        if self.my_node_num == 0 {
+            return Ok(format!("Meshtastic device {} has not reported its node ID yet", self.name));
+        }
+        match command {
+            AdminCommand::Reboot { seconds } => {
+                self.send_admin(admin_message::PayloadVariant::RebootSeconds(*seconds), None).await?;
+                Ok(format!("Rebooting {} in {} seconds", self.name, seconds))
+            }
+            AdminCommand::SetOwner { short_name, long_name } => {
+                // Keep the parts of our user info that aren't being changed
+                let user = User {
+                    short_name: short_name.clone(),
+                    long_name: long_name.clone(),
+                    ..self.my_user.clone().unwrap_or_default()
+                };
+                self.send_admin(admin_message::PayloadVariant::SetOwner(user), None).await?;
+                Ok(format!("Owner of {} set to {} / {}", self.name, short_name, long_name))
+            }
+            AdminCommand::GetConfig { section } => {
+                let name = format!("{}_CONFIG", section.to_uppercase());
+                let Some(config_type) = admin_message::ConfigType::from_str_name(&name) else {
+                    return Ok(format!("Unknown config section: {}", section));
+                };
+                self.send_admin(
+                    admin_message::PayloadVariant::GetConfigRequest(config_type as i32),
+                    Some(irc_channel),
+                ).await?;
+                Ok(format!("Requested the {} config of {}", section.to_lowercase(), self.name))
+            }
+            AdminCommand::SetChannelName { index, name } => {
+                let Some(channel) = self.channel_table.channel(*index) else {
+                    return Ok(format!("{} has no channel {}", self.name, index));
+                };
+                let mut channel = channel.clone();
+                channel.settings = Some(ChannelSettings {
+                    name: name.clone(),
+                    ..channel.settings.unwrap_or_default()
+                });
+                self.send_admin(admin_message::PayloadVariant::SetChannel(channel.clone()), None).await?;
+                
+                // The device doesn't report the change, so apply it ourselves
+                let mut channel_table = std::mem::take(&mut self.channel_table);
+                channel_table.update(&FromRadio {
+                    payload_variant: Some(from_radio::PayloadVariant::Channel(channel)),
+                    ..Default::default()
+                });
+                self.refresh_channels(channel_table);
+                Ok(format!("Channel {} of {} renamed to {}", index, self.name, name))
+            }
+            AdminCommand::ExportNodes => {
+                let path = &self.node_export_path;
+                let (json, count) = {
+                    let node_db = self.node_db.lock().unwrap();
+                    (node_db.to_json()?, node_db.count())
+                };
+                tokio::fs::write(path, json).await
+                    .map_err(|e| anyhow::anyhow!("Failed to write nodes to {}: {}", path, e))?;
+                Ok(format!("Exported {} nodes to {}", count, path))
+            }
+        }
+    }
+
+    /// Connect to a radio just long enough to read its node database
+    pub async fn read_node_db(device: &DeviceConfig) -> Result<NodeDb> {
+        let connection = Self::open(device).await?;
+        let mut node_db = NodeDb::new();
+        for from_radio in &connection.startup_packets {
+            if let Some(from_radio::PayloadVariant::NodeInfo(node_info)) = &from_radio.payload_variant {
+                node_db.update_from_node_info(node_info);
+            }
+        }
+        Self::close(connection.stream_api, &device.display_name()).await;
+        Ok(node_db)
+    }
+
+    /// Whether a node may be bridged. Our own radio always is.
+    fn is_allowed(&self, node: u32) -> bool {
+        node == self.my_node_num || self.allowed_nodes.as_ref().is_none_or(|nodes| nodes.contains(&node))
+    }
+
+    /// Look up a node by `!hexid` / hex ID or by name
+    fn resolve_node(&self, node: &str) -> Option<u32> {
+        self.nodes.resolve(node)
+    }
+
+    /// Queue a message from IRC for the mesh, split into parts if it is too
+    /// long for one packet
+    fn queue_text(&mut self, message: &IrcMessage, channel: u32, destination: u32) {
+        let channel_name = self.channel_table.name(channel).unwrap_or_else(|| channel.to_string());
+        let prefix = self.templates.mesh_prefix(&TemplateValues {
+            sender: &message.sender,
+            channel: &channel_name,
+            snr: None,
+        });
+        let content = self.nodes.aliases().mentions_to_mesh(&message.content);
+        let parts = split_text(&prefix, &content, MAX_TEXT_BYTES);
+        let total = parts.len();
+        
+        let description = if destination == BROADCAST_ADDR {
+            format!("message from {}", message.sender)
+        } else {
+            format!("message from {} to {}", message.sender, self.sender_name(destination))
+        };
+        
+        for (i, text) in parts.into_iter().enumerate() {
+            // Create a text message data payload
+            let data = Data {
+                portnum: PortNum::TextMessageApp as i32,
+                payload: text.as_bytes().to_vec(),
+                want_response: false,
+                ..Default::default()
+            };
+            
+            // Create mesh packet, requesting an ACK so it can be retried if lost.
+            // The ID is assigned when it is transmitted.
+            let mesh_packet = MeshPacket {
+                to: destination,
+                from: 0, // Will be filled by the device
+                channel,
+                want_ack: true,
+                hop_limit: self.hop_limit.unwrap_or(0), // 0 leaves it to the device
+                priority: mesh_packet::Priority::Reliable as i32,
+                payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+                ..Default::default()
+            };
+            
+            self.queue_packet(text_priority(destination), PendingAck {
+                packet: mesh_packet,
+                description: if total > 1 {
+                    format!("part {}/{} of {}", i + 1, total, description)
+                } else {
+                    description.clone()
+                },
+                irc_channel: message.channel.clone(),
+                retry: true,
+                attempts: 1,
+                sent_at: Instant::now(),
+            });
+            debug!("Queued for Meshtastic: {}", text);
+        }
+    }
+
+    /// Add a packet to the outbound queue. A full queue drops its oldest
+    /// lowest-priority packet; dropped messages from IRC are reported on the
+    /// next flush.
+    fn queue_packet(&mut self, priority: Priority, pending: PendingAck) {
+        if let Some(dropped) = self.outgoing.push(priority, pending) {
+            warn!("Mesh queue full, dropped {}", dropped.description);
+            if dropped.retry {
+                self.dropped.push(dropped);
+            }
+        }
+    }
+
+    /// Send the next queued message if the rate limit allows, and warn IRC
+    /// once when the queue starts backing up
+    async fn flush_outgoing(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        for dropped in std::mem::take(&mut self.dropped) {
+            to_irc.send(MeshMessage {
+                content: format!("Dropped {}: too many messages queued for the mesh", dropped.description),
+                channel: dropped.irc_channel,
+                chat: None,
+            }).await?;
+        }
+        
+        if let Some(pending) = self.outgoing.front() {
+            let payload_len = match &pending.packet.payload_variant {
+                Some(mesh_packet::PayloadVariant::Decoded(data)) => data.payload.len(),
+                _ => 0,
+            };
+            let airtime = estimate_airtime(self.modem_preset, payload_len);
+            
+            // Space packets out so the parts of a split message arrive in order
+            if Instant::now() >= self.next_send_at && self.limiter.allows(airtime) {
+                if let Some(pending) = self.outgoing.pop() {
+                    info!("Attempting to send packet to Meshtastic radio...");
+                    self.limiter.record(airtime);
+                    self.next_send_at = Instant::now() + CHUNK_DELAY;
+                    self.transmit(pending).await?;
+                }
+            }
+        }
+        
+        if self.outgoing.is_empty() {
+            self.queue_warned = false;
+        } else if self.outgoing.len() >= self.queue_warning && !self.queue_warned {
+            self.queue_warned = true;
+            error!("{} messages queued for the mesh by the rate limit", self.outgoing.len());
+            to_irc.send(MeshMessage::new(format!(
+                "Mesh rate limit reached, {} messages queued. They will be sent as airtime allows.",
+                self.outgoing.len()
+            ))).await?;
+        }
+        Ok(())
+    }
+
+    /// Send a queued packet. Messages from IRC go out under a fresh ID and
+    /// wait for their ACK; retries get a new ID too, so the mesh doesn't drop
+    /// them as duplicates of the original.
+    async fn transmit(&mut self, mut pending: PendingAck) -> Result<()> {
+        if pending.retry {
+            pending.packet.id = utils::generate_rand_id();
+        }
+        pending.sent_at = Instant::now();
+        
+        let payload_variant = Some(to_radio::PayloadVariant::Packet(pending.packet.clone()));
+        if let Err(e) = self.send_to_radio(payload_variant).await {
+            error!("Failed to send to Meshtastic: {}", e);
+            return Err(anyhow::anyhow!("Failed to send {}: {}", pending.description, e));
+        }
+        info!("Successfully sent to Meshtastic: {}", pending.description);
+        // Remember our own packet so it isn't relayed if it comes back, e.g.
+        // uploaded by an MQTT gateway or heard by another of our radios. An ID
+        // of 0 is left for the device to fill in, so there is nothing to match.
+        if pending.packet.id != 0 {
+            self.dedup.lock().unwrap().record_sent(pending.packet.id);
+        }
+        
+        if pending.retry {
+            self.pending_acks.insert(pending.packet.id, pending);
+        }
+        Ok(())
+    }
+
+    /// Ask an unknown node for its NodeInfo so we learn its name without
+    /// waiting for its next broadcast. Like the firmware, we send our own user
+    /// info as the payload, so nothing is sent until we know it.
+    fn request_nodeinfo(&mut self, node: u32, channel: u32) {
+        let Some(my_user) = &self.my_user else {
+            return;
+        };
+        if let Some(last) = self.nodeinfo_requested.get(&node) {
+            if last.elapsed() < NODEINFO_REQUEST_INTERVAL {
+                return;
+            }
+        }
+        
+        let data = Data {
+            portnum: PortNum::NodeinfoApp as i32,
+            payload: my_user.encode_to_vec(),
+            want_response: true,
             ..Default::default()
         };
         
-        // Create mesh packet for broadcast
         let mesh_packet = MeshPacket {
-            to: 0xffffffff, // Broadcast address
+            to: node,
             from: 0, // Will be filled by the device
-            channel: self.channel,
+            channel,
             id: 0, // Will be assigned by the device
-            priority: mesh_packet::Priority::Default as i32,
+            priority: mesh_packet::Priority::Background as i32,
             payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
             ..Default::default()
         };
         
-        // Create the payload variant
-        let payload_variant = Some(meshtastic::protobufs::to_radio::PayloadVariant::Packet(mesh_packet));
-        
-        // Send using the stream API's send_to_radio_packet method
-        info!("Attempting to send packet to Meshtastic radio...");
-        match self.stream_api.send_to_radio_packet(payload_variant).await {
-            Ok(_) => {
-                info!("Successfully sent to Meshtastic: {}", text);
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to send to Meshtastic: {}", e);
-                Err(anyhow::anyhow!("Failed to send message: {}", e))
+        debug!("Requesting NodeInfo from {:08x}", node);
+        self.nodeinfo_requested.insert(node, Instant::now());
+        self.queue_packet(Priority::Low, PendingAck::once(mesh_packet, format!("NodeInfo request to {:08x}", node)));
+    }
+
+    /// Answer a direct message with the configured auto-reply, unless the
+    /// sender got one recently
+    fn send_auto_reply(&mut self, node: u32, channel: u32) {
+        let Some(reply) = &self.auto_reply else {
+            return;
+        };
+        if let Some(last) = self.auto_replied.get(&node) {
+            if last.elapsed() < AUTO_REPLY_INTERVAL {
+                return;
             }
         }
+        
+        let data = Data {
+            portnum: PortNum::TextMessageApp as i32,
+            payload: reply.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        
+        let mesh_packet = MeshPacket {
+            to: node,
+            from: 0, // Will be filled by the device
+            channel,
+            id: 0, // Will be assigned by the device
+            hop_limit: self.hop_limit.unwrap_or(0), // 0 leaves it to the device
+            priority: mesh_packet::Priority::Reliable as i32,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+            ..Default::default()
+        };
+        
+        debug!("Auto-replying to {:08x}", node);
+        self.auto_replied.insert(node, Instant::now());
+        self.queue_packet(Priority::Normal, PendingAck::once(mesh_packet, format!("auto-reply to {:08x}", node)));
     }
 
-    async fn send_ack(&mut self, packet_id: u32, to_node: u32) -> Result<()> {
-        debug!("Sending ACK for packet {} to node {:08x}", packet_id, to_node);
+    fn send_traceroute(&mut self, node: u32, irc_channel: Option<String>) {
+        // Forget requests that were never answered
+        self.pending_traceroutes.retain(|_, pending| pending.sent_at.elapsed() < TRACEROUTE_TIMEOUT);
+        
+        // The response refers back to our packet ID, so choose it ourselves
+        let id: u32 = utils::generate_rand_id();
+        
+        let data = Data {
+            portnum: PortNum::TracerouteApp as i32,
+            payload: RouteDiscovery::default().encode_to_vec(),
+            want_response: true,
+            ..Default::default()
+        };
+        
+        let mesh_packet = MeshPacket {
+            to: node,
+            from: 0, // Will be filled by the device
+            channel: self.router.primary().index,
+            id,
+            want_ack: true,
+            hop_limit: self.hop_limit.unwrap_or(0), // 0 leaves it to the device
+            priority: mesh_packet::Priority::Reliable as i32,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+            ..Default::default()
+        };
+        
+        info!("Sending traceroute to {:08x}", node);
+        self.queue_packet(Priority::Normal, PendingAck::once(mesh_packet, format!("traceroute to {:08x}", node)));
+        
+        self.pending_traceroutes.insert(id, PendingTraceroute {
+            node,
+            irc_channel,
+            sent_at: Instant::now(),
+        });
+    }
+
+    /// Send an admin message to our own radio. It never goes over the air, so
+    /// it skips the outbound queue. When `response_to` is given,
+    /// the response is posted to that IRC channel (None for the main one).
+    async fn send_admin(
+        &mut self,
+        payload_variant: admin_message::PayloadVariant,
+        response_to: Option<Option<String>>,
+    ) -> Result<()> {
+        // Forget requests that were never answered
+        self.pending_admin.retain(|_, pending| pending.sent_at.elapsed() < ADMIN_RESPONSE_TIMEOUT);
+        
+        // The response refers back to our packet ID, so choose it ourselves
+        let id: u32 = utils::generate_rand_id();
+        
+        let admin = AdminMessage {
+            payload_variant: Some(payload_variant),
+            ..Default::default()
+        };
+        
+        let data = Data {
+            portnum: PortNum::AdminApp as i32,
+            payload: admin.encode_to_vec(),
+            want_response: response_to.is_some(),
+            ..Default::default()
+        };
+        
+        let mesh_packet = MeshPacket {
+            to: self.my_node_num,
+            from: 0, // Will be filled by the device
+            channel: 0,
+            id,
+            priority: mesh_packet::Priority::Reliable as i32,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+            ..Default::default()
+        };
+        
+        let payload_variant = Some(to_radio::PayloadVariant::Packet(mesh_packet));
+        
+        info!("Sending admin message to {}", self.name);
+        self.send_to_radio(payload_variant).await
+            .map_err(|e| anyhow::anyhow!("Failed to send admin message: {}", e))?;
+        
+        if let Some(irc_channel) = response_to {
+            self.pending_admin.insert(id, PendingAdmin {
+                irc_channel,
+                sent_at: Instant::now(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Give the radio the configured owner names. Nothing is sent when it
+    /// already has them, to spare the device's flash a write on every start.
+    async fn apply_owner(&mut self) -> Result<()> {
+        let Some(owner) = self.owner.clone() else {
+            return Ok(());
+        };
+        let current = self.my_user.clone().unwrap_or_default();
+        if current.short_name == owner.short_name && current.long_name == owner.long_name {
+            debug!("{} already has owner {} / {}", self.name, owner.short_name, owner.long_name);
+            return Ok(());
+        }
+        
+        info!("Setting owner of {} to {} / {}", self.name, owner.short_name, owner.long_name);
+        let user = User {
+            short_name: owner.short_name,
+            long_name: owner.long_name,
+            ..current
+        };
+        self.send_admin(admin_message::PayloadVariant::SetOwner(user), None).await
+    }
+
+    /// Set the radio's clock to the host's, for nodes with no GPS to get it from
+    async fn sync_time(&mut self) -> Result<()> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        debug!("Setting the time on {} to {}", self.name, now.as_secs());
+        self.send_admin(admin_message::PayloadVariant::SetTimeOnly(now.as_secs() as u32), None).await
+    }
+
+    /// Ask for the history configured to be replayed on startup, once the
+    /// Store & Forward router is known
+    async fn request_startup_history(&mut self) -> Result<()> {
+        if !self.store_forward.request_on_startup || self.store_forward.router.is_none() {
+            return Ok(());
+        }
+        self.store_forward.request_on_startup = false;
+        self.request_history(self.store_forward.window_minutes, None);
+        Ok(())
+    }
+
+    /// Ask the Store & Forward router to replay the messages of the last
+    /// `minutes`. The router skips what it already sent us.
+    fn request_history(&mut self, minutes: u32, irc_channel: Option<String>) {
+        let Some(router) = self.store_forward.router else {
+            return;
+        };
+        
+        let request = StoreAndForward {
+            rr: store_and_forward::RequestResponse::ClientHistory as i32,
+            variant: Some(store_and_forward::Variant::History(store_and_forward::History {
+                window: minutes,
+                last_request: self.store_forward.last_request,
+                ..Default::default()
+            })),
+        };
+        
+        let data = Data {
+            portnum: PortNum::StoreForwardApp as i32,
+            payload: request.encode_to_vec(),
+            ..Default::default()
+        };
+        
+        let mesh_packet = MeshPacket {
+            to: router,
+            from: 0, // Will be filled by the device
+            channel: self.router.primary().index,
+            id: 0, // Will be assigned by the device
+            want_ack: true,
+            hop_limit: self.hop_limit.unwrap_or(0), // 0 leaves it to the device
+            priority: mesh_packet::Priority::Reliable as i32,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+            ..Default::default()
+        };
+        
+        info!("Requesting {} minutes of history from Store & Forward router {:08x}", minutes, router);
+        self.queue_packet(Priority::Normal, PendingAck::once(mesh_packet, format!("history request to {:08x}", router)));
+        self.store_forward.pending = Some(irc_channel);
+    }
+
+    /// Hand a packet to the radio over the current connection
+    async fn send_to_radio(&mut self, payload_variant: Option<to_radio::PayloadVariant>) -> Result<()> {
+        let stream_api = self.stream_api.as_mut()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))?;
+        stream_api.send_to_radio_packet(payload_variant).await?;
+        Ok(())
+    }
+
+    fn send_ack(&mut self, packet_id: u32, to_node: u32) {
+        debug!("Queueing ACK for packet {} to node {:08x}", packet_id, to_node);
         
         // Create an empty Data payload for the ACK
         let data = Data {
@@ -254,19 +2506,12 @@ impl MeshtasticHandler {
             ..Default::default()
         };
         
-        // Create the payload variant
-        let payload_variant = Some(meshtastic::protobufs::to_radio::PayloadVariant::Packet(mesh_packet));
-        
-        // Send the ACK
-        match self.stream_api.send_to_radio_packet(payload_variant).await {
-            Ok(_) => {
-                debug!("Successfully sent ACK for packet {}", packet_id);
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to send ACK for packet {}: {}", packet_id, e);
-                Err(anyhow::anyhow!("Failed to send ACK: {}", e))
-            }
-        }
+        // ACKs go ahead of everything else
+        self.queue_packet(Priority::High, PendingAck::once(mesh_packet, format!("ACK for packet {}", packet_id)));
     }
+}
+
+/// Direct messages go ahead of channel chatter
+fn text_priority(destination: u32) -> Priority {
+    if destination == BROADCAST_ADDR { Priority::Low } else { Priority::Normal }
 }
\ No newline at end of file
@@ -0,0 +1,208 @@
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::endpoint::Health;
+
+/// Health of the IRC leg of the bridge
+#[derive(Debug, Clone, Default)]
+pub struct IrcMetrics {
+    pub connected: bool,
+    pub connected_at: Option<Instant>,
+    pub reconnects: u32,
+    pub lag: Option<Duration>,
+    pub last_message_at: Option<Instant>,
+    pub relayed_to_irc: u64,  // Messages from the mesh posted to IRC
+    pub relayed_to_mesh: u64,  // Messages and commands from IRC passed to the mesh
+}
+
+/// Traffic the mesh handlers chose not to bridge, and what each node sent
+#[derive(Debug, Clone, Default)]
+pub struct MeshMetrics {
+    pub dropped_unlisted: u64,  // Packets and DMs involving nodes outside allowed_nodes
+    pub filtered: u64,  // Messages either way dropped by the filters
+    pub duplicates: u64,  // Copies of packets already relayed, from other radios or MQTT gateways
+    pub last_packet_at: Option<Instant>,
+    pub publish_dropped: u64,  // IRC messages over the MQTT publish rate limit
+    pub nodes: HashMap<u32, NodeStats>,
+}
+
+/// Traffic heard from one mesh node, to spot chatty or failing nodes
+#[derive(Debug, Clone, Default)]
+pub struct NodeStats {
+    pub packets: u64,
+    pub messages: u64,  // Text messages
+    pub bytes: u64,  // Payload bytes
+    pub last_snr: Option<f32>,  // None until heard directly over LoRa
+    pub naks: u64,  // Delivery errors for messages sent to the node
+    pub last_heard: Option<Instant>,
+}
+
+impl NodeStats {
+    /// e.g. "12 packets (3 messages, 456 bytes), last SNR 6.25dB, 1 NAK, heard 5m 2s ago"
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("{} packets ({} messages, {} bytes)", self.packets, self.messages, self.bytes)];
+        if let Some(snr) = self.last_snr {
+            parts.push(format!("last SNR {:.2}dB", snr));
+        }
+        parts.push(format!("{} NAK{}", self.naks, if self.naks == 1 { "" } else { "s" }));
+        if let Some(last_heard) = self.last_heard {
+            parts.push(format!("heard {} ago", format_duration(last_heard.elapsed())));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Shared metrics handle, cheap to clone and hand to each handler
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    started_at: Instant,
+    irc: Arc<Mutex<IrcMetrics>>,
+    mesh: Arc<Mutex<MeshMetrics>>,
+    endpoints: Arc<Mutex<BTreeMap<String, Health>>>,  // By endpoint name
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            irc: Arc::new(Mutex::new(IrcMetrics::default())),
+            mesh: Arc::new(Mutex::new(MeshMetrics::default())),
+            endpoints: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn irc(&self) -> IrcMetrics {
+        self.irc.lock().unwrap().clone()
+    }
+
+    pub fn mesh(&self) -> MeshMetrics {
+        self.mesh.lock().unwrap().clone()
+    }
+
+    pub fn record_dropped_unlisted(&self) {
+        self.mesh.lock().unwrap().dropped_unlisted += 1;
+    }
+
+    pub fn record_filtered(&self) {
+        self.mesh.lock().unwrap().filtered += 1;
+    }
+
+    pub fn record_publish_dropped(&self) {
+        self.mesh.lock().unwrap().publish_dropped += 1;
+    }
+
+    pub fn record_duplicate(&self) {
+        self.mesh.lock().unwrap().duplicates += 1;
+    }
+
+    pub fn node_stats(&self, node: u32) -> Option<NodeStats> {
+        self.mesh.lock().unwrap().nodes.get(&node).cloned()
+    }
+
+    pub fn record_node_packet(&self, node: u32, bytes: usize, text: bool, snr: Option<f32>) {
+        let mut mesh = self.mesh.lock().unwrap();
+        let stats = mesh.nodes.entry(node).or_default();
+        stats.packets += 1;
+        stats.bytes += bytes as u64;
+        if text {
+            stats.messages += 1;
+        }
+        if snr.is_some() {
+            stats.last_snr = snr;
+        }
+        stats.last_heard = Some(Instant::now());
+        mesh.last_packet_at = stats.last_heard;
+    }
+
+    pub fn record_node_nak(&self, node: u32) {
+        self.mesh.lock().unwrap().nodes.entry(node).or_default().naks += 1;
+    }
+
+    pub fn record_irc_connect(&self) {
+        let mut irc = self.irc.lock().unwrap();
+        if irc.connected_at.is_some() {
+            irc.reconnects += 1;
+        }
+        irc.connected = true;
+        irc.connected_at = Some(Instant::now());
+        irc.lag = None;
+    }
+
+    pub fn record_irc_disconnect(&self) {
+        self.irc.lock().unwrap().connected = false;
+    }
+
+    pub fn record_irc_lag(&self, lag: Duration) {
+        self.irc.lock().unwrap().lag = Some(lag);
+    }
+
+    pub fn record_irc_message(&self) {
+        self.irc.lock().unwrap().last_message_at = Some(Instant::now());
+    }
+
+    pub fn record_relayed_to_irc(&self) {
+        self.irc.lock().unwrap().relayed_to_irc += 1;
+    }
+
+    pub fn record_relayed_to_mesh(&self) {
+        self.irc.lock().unwrap().relayed_to_mesh += 1;
+    }
+
+    pub fn record_endpoint(&self, name: &str, health: Health) {
+        self.endpoints.lock().unwrap().insert(name.to_string(), health);
+    }
+
+    /// A snapshot of the bridge's health for monitoring, with times as
+    /// seconds ago
+    pub fn status_json(&self) -> serde_json::Value {
+        let irc = self.irc();
+        let mesh = self.mesh();
+        let endpoints: serde_json::Map<String, serde_json::Value> = self.endpoints.lock().unwrap().iter()
+            .map(|(name, health)| (name.clone(), health.describe().into()))
+            .collect();
+        let secs_ago = |at: Option<Instant>| at.map(|at| at.elapsed().as_secs());
+        json!({
+            "uptime_secs": self.uptime().as_secs(),
+            "irc": {
+                "connected": irc.connected,
+                "connected_secs": secs_ago(irc.connected_at),
+                "reconnects": irc.reconnects,
+                "lag_ms": irc.lag.map(|lag| lag.as_millis() as u64),
+            },
+            "relayed": {
+                "to_irc": irc.relayed_to_irc,
+                "to_mesh": irc.relayed_to_mesh,
+            },
+            "mesh": {
+                "last_packet_secs_ago": secs_ago(mesh.last_packet_at),
+                "nodes_heard": mesh.nodes.len(),
+                "duplicates": mesh.duplicates,
+                "dropped_unlisted": mesh.dropped_unlisted,
+                "filtered": mesh.filtered,
+                "publish_dropped": mesh.publish_dropped,
+            },
+            "endpoints": endpoints,
+        })
+    }
+}
+
+/// Format a duration as e.g. "1d 2h 3m 4s", dropping leading zero units
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, mins, secs)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, mins, secs)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
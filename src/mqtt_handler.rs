@@ -1,110 +1,873 @@
 use anyhow::Result;
-use log::{debug, error, info};
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
-use tokio::sync::mpsc;
-use std::collections::HashMap;
-use std::time::Duration;
+use log::{debug, error, info, warn};
+use rumqttc::tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rumqttc::tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use rumqttc::{matches, AsyncClient, ConnectionError, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS, Request, StateError, SubscribeReasonCode, TlsConfiguration, Transport};
+use tokio::sync::{mpsc, watch};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::config::MqttConfig;
-use crate::irc_handler::IrcMessage;
-use meshtastic::protobufs::{mesh_packet, MeshPacket, PortNum, Data, ServiceEnvelope};
+use crate::aliases::Aliases;
+use crate::backoff::Backoff;
+use crate::chunking::{split_text, CHUNK_DELAY, MAX_TEXT_BYTES};
+use crate::crypto::ChannelKeys;
+use crate::dedup::PacketDedup;
+use crate::endpoint::{ChatEndpoint, Health, MeshState, Session};
+use crate::filter::Filters;
+use crate::supervisor::Handoff;
+use crate::config::{ChannelRef, Config, DumpSink, MeshtasticConfig, MqttConfig, ProxyConfig, RangeTestMode, RetainedPolicy, TopicRoute, UnknownPortsConfig};
+use crate::irc_handler::{mask_matches, Chat, IrcMessage, MeshMessage};
+use crate::metrics::Metrics;
+use crate::nodes::{default_destination, parse_node_id, parse_node_ids, NodeDirectory};
+use crate::proxy::{self, ForwardTls};
+use crate::rate_limit::TokenBucket;
+use crate::range_test::{is_range_test, RangeTestTracker, RANGE_TEST_IDLE};
+use crate::relay::{dump_packet, format_position, format_waypoint, is_unknown_port, relay_ports, DUMP_MAX_BYTES};
+use crate::node_db::NodeDb;
+use crate::telemetry::{NodeTelemetry, TelemetryStore};
+use crate::template::{TemplateValues, Templates};
+use meshtastic::protobufs::{mesh_packet, telemetry, MeshPacket, PortNum, Data, Position, ServiceEnvelope, Telemetry, User, Waypoint};
+use serde::{Deserialize, Serialize};
+use meshtastic::utils;
+use meshtastic::Message;
+use futures_util::future::BoxFuture;
+
+const DEFAULT_CHANNEL_ID: &str = "LongFast";
+
+// QoS used when subscribe_qos or publish_qos is unset
+const DEFAULT_QOS: QoS = QoS::AtLeastOnce;
+
+// Retained on status_topic while the bridge is connected, and as its last will
+const STATUS_ONLINE: &str = "online";
+const STATUS_OFFLINE: &str = "offline";
+
+// Connection tuning defaults, overridable for high-latency links
+const DEFAULT_KEEP_ALIVE_SECS: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_MAX_INFLIGHT: u16 = 100;
+const DEFAULT_REQUEST_CAPACITY: usize = 100;
+
+// MQTT carries the keep alive as 16-bit seconds
+const MAX_KEEP_ALIVE_SECS: u64 = 65535;
+
+// Brokers speaking MQTT 3.1.1 can only reject a publish by closing the
+// connection, so a connection closed this soon after one is taken as that
+const PUBLISH_REJECT_WINDOW: Duration = Duration::from_secs(2);
+
+// Messages published at once when publish_per_minute is set but
+// publish_burst isn't
+const DEFAULT_PUBLISH_BURST: u32 = 5;
+
+// Default time between updates of stats_topic
+const DEFAULT_STATS_INTERVAL_SECS: u64 = 60;
+
+// Minimum time between relayed positions from the same node
+const DEFAULT_POSITION_INTERVAL_SECS: u64 = 900;
+
+/// An MQTT broker as an endpoint of the bridge, connected with the current
+/// config
+pub struct MqttEndpoint {
+    broker_index: usize,  // The broker's place in the config's list of brokers
+    broker_address: String,
+    state: MeshState,
+    feed: Option<Handoff<FeedEntry>>,
+    uplink: Option<Handoff<Uplink>>,
+    reload: watch::Receiver<Config>,
+    session: Session<IrcMessage, MeshMessage>,
+}
+
+impl MqttEndpoint {
+    pub fn new(
+        config: &MqttConfig,
+        broker_index: usize,
+        state: MeshState,
+        feed: Option<Handoff<FeedEntry>>,
+        uplink: Option<Handoff<Uplink>>,
+        reload: watch::Receiver<Config>,
+    ) -> Self {
+        Self {
+            broker_index,
+            broker_address: config.broker_address.clone(),
+            state,
+            feed,
+            uplink,
+            reload,
+            session: Session::default(),
+        }
+    }
+}
+
+impl ChatEndpoint for MqttEndpoint {
+    type Incoming = IrcMessage;
+    type Outgoing = MeshMessage;
+
+    fn name(&self) -> String {
+        format!("MQTT broker {}", self.broker_address)
+    }
+
+    fn connect(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // Marked as seen, so the handler only applies later reloads
+            let config = self.reload.borrow_and_update().clone();
+            let mqtt_config = config.meshtastic.all_brokers().into_iter().nth(self.broker_index).cloned()
+                .ok_or_else(|| anyhow::anyhow!("MQTT broker {} is no longer configured", self.broker_address))?;
+            info!("Initializing MQTT connection to {}...", self.broker_address);
+            let state = self.state.clone();
+            let handler = MqttHandler::new(&mqtt_config, &config.meshtastic, config.proxy.as_ref(), state.dedup, state.node_db, state.metrics).await
+                .map_err(|e| anyhow::anyhow!("Failed to initialize MQTT handler: {}", e))?;
+            info!("MQTT handler initialized successfully");
+            info!("Starting MQTT message handler loop");
+            let feed = self.feed.as_ref().map(Handoff::receiver);
+            let uplink = self.uplink.as_ref().map(Handoff::receiver);
+            let (reload, broker_index) = (self.reload.clone(), self.broker_index);
+            self.session.start(|from_irc, to_irc| handler.run(from_irc, to_irc, feed, uplink, reload, broker_index));
+            Ok(())
+        })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<MeshMessage>>> {
+        Box::pin(self.session.recv())
+    }
+
+    fn send(&mut self, message: IrcMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.session.send(message))
+    }
+
+    fn health(&self) -> Health {
+        self.session.health()
+    }
+}
 
 pub struct MqttHandler {
     client: AsyncClient,
     eventloop: EventLoop,
-    topic: String,
+    config: MqttConfig,  // As last applied, to tell what a reload changed
+    proxy: Option<ProxyConfig>,
+    broker: String,  // "host:port", for status messages
+    topics: Vec<String>,  // Filters subscribed to
+    subscribe_qos: QoS,
+    channel_filter: Option<Vec<String>>,  // Envelope channel names relayed, all if None
+    retained: RetainedPolicy,
+    routes: Vec<TopicRoute>,  // Topics relayed to their own IRC channel, first match wins
+    status_topic: Option<String>,
+    feed_topic: Option<String>,
+    uplink_root: Option<String>,  // Where radio packets are published in gateway mode
+    uplink_gateways: Arc<Mutex<HashSet<String>>>,  // Gateway IDs of our radios
+    publish_per_minute: Option<u32>,
+    publish_burst: Option<u32>,
+    stats_topic: Option<String>,
+    stats_interval: Duration,
+    downlink: Downlink,
+    nodes: NodeDirectory,
+    templates: Templates,
+    filters: Filters,
+    relay_ports: HashSet<PortNum>,  // Ports whose packets are posted to IRC
+    blocked_nodes: HashSet<u32>,  // Never relayed to IRC
+    allowed_nodes: Option<HashSet<u32>>,  // When set, the only nodes relayed to IRC
+    metrics: Metrics,
+    unknown_ports: UnknownPortsConfig,
+    range_test: RangeTestMode,
+    range_tests: RangeTestTracker,
+    position_interval: Duration,
+    last_position: HashMap<u32, Instant>,  // When each node's position was last relayed
+    telemetry: TelemetryStore,
+    announce_telemetry: bool,
+    node_db: Arc<Mutex<NodeDb>>,  // Shared with the radios
+    channel_keys: ChannelKeys,  // For packets the broker passes on encrypted
+    dedup: Arc<Mutex<PacketDedup>>,  // Shared with the radios; several gateways often upload the same packet
+    connection_lost: bool,  // Whether IRC was told the broker is unreachable
+    last_publish: Option<(Instant, u16)>,  // When the last publish went out, and its packet ID
+    pending_subscriptions: VecDeque<String>,  // Topics awaiting a SubAck, in order
+    backoff: Backoff,  // Waits between reconnection attempts, reset once connected
+}
+
+/// What messages held for a broker are filed under in a backlog file
+pub fn mqtt_leg(config: &MqttConfig) -> String {
+    format!("mqtt {}:{}", config.broker_address, config.port)
+}
+
+/// The channel name in an envelope topic, e.g. "LongFast" in
+/// "msh/US/2/e/LongFast/!a1b2c3d4"
+fn topic_channel(topic: &str) -> &str {
+    topic.rsplit('/').nth(1).unwrap_or_default()
+}
+
+/// Whether a reload changed settings only read when the handler starts,
+/// by the tasks publishing to the broker
+fn needs_restart(old: &MqttConfig, new: &MqttConfig) -> bool {
+    old.publish_qos != new.publish_qos
+        || old.channel_id != new.channel_id
+        || old.gateway_id != new.gateway_id
+        || old.root_topic != new.root_topic
+        || old.downlink != new.downlink
+        || old.routes != new.routes
+        || old.publish_per_minute != new.publish_per_minute
+        || old.publish_burst != new.publish_burst
+        || old.stats_topic != new.stats_topic
+        || old.stats_interval_secs != new.stats_interval_secs
+        || old.feed_topic != new.feed_topic
+        || old.uplink != new.uplink
+        || old.request_capacity != new.request_capacity
+}
+
+/// A message on a JSON topic (`/2/json/`), as far as the bridge reads it
+#[derive(Debug, Deserialize)]
+struct JsonMessage {
+    from: u32,
+    #[serde(rename = "type")]
+    kind: String,  // e.g. "text", "nodeinfo"
+    #[serde(default)]
+    payload: serde_json::Value,
+}
+
+/// A message bridged one way or the other, as published on feed_topic
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedEntry {
+    direction: &'static str,  // "to_irc" or "to_mesh"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,  // IRC nick, for messages to the mesh
+    irc_channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<String>,  // Node a direct message from IRC went to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mesh_channel: Option<u32>,
+    text: String,
+    time: u64,  // Unix time
+}
+
+impl FeedEntry {
+    pub fn to_irc(message: &MeshMessage, main_channel: &str) -> Self {
+        Self {
+            direction: "to_irc",
+            from: None,
+            irc_channel: message.channel.clone().unwrap_or_else(|| main_channel.to_string()),
+            destination: None,
+            mesh_channel: None,
+            text: message.content.clone(),
+            time: unix_time(),
+        }
+    }
+
+    pub fn to_mesh(message: &IrcMessage, main_channel: &str) -> Self {
+        Self {
+            direction: "to_mesh",
+            from: Some(message.sender.clone()),
+            irc_channel: message.channel.clone().unwrap_or_else(|| main_channel.to_string()),
+            destination: message.destination.clone(),
+            mesh_channel: message.mesh_channel,
+            text: message.content.clone(),
+            time: unix_time(),
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+/// A packet a radio heard, to be published as its gateway would
+#[derive(Debug, Clone)]
+pub struct Uplink {
+    pub packet: MeshPacket,
+    pub channel_id: String,  // Channel name, e.g. "LongFast"
+    pub gateway_id: String,  // The radio's node ID, e.g. "!1a2b3c4d"
+}
+
+/// Where and how IRC messages are published
+#[derive(Debug, Clone)]
+struct Downlink {
+    topic: String,  // e.g. "msh/US/2/e/LongFast/!1a2b3c4d"
+    route_topics: HashMap<String, String>,  // By IRC channel, for messages said in a route's channel
     channel: u32,
-    node_names: HashMap<u32, String>,
+    channel_id: String,
+    gateway_id: String,
+    qos: QoS,
+    hop_limit: Option<u32>,
+    destination: u32,  // Usually broadcast
+    enabled: bool,
+    aliases: Arc<Mutex<Aliases>>,  // For mentions, shared so a reload updates them
+}
+
+/// The topic gateways with downlink enabled take packets for a channel from,
+/// `<root>/2/e/<channel_id>/<gateway_id>`
+fn downlink_topic(root: &str, channel_id: &str, gateway_id: &str) -> String {
+    format!("{}/2/e/{}/{}", root.trim_end_matches('/'), channel_id, gateway_id)
+}
+
+/// The root of a topic filter: the part before "/2/" (e.g. "msh/US" for
+/// "msh/US/2/e/#"), or the filter without its trailing wildcards
+fn topic_root(filter: &str) -> &str {
+    match filter.find("/2/") {
+        Some(end) => &filter[..end],
+        None => filter.trim_end_matches(['#', '+', '/']),
+    }
+}
+
+/// Whether the broker closed the connection, rather than it timing out or
+/// failing some other way
+fn closed_by_broker(error: &ConnectionError) -> bool {
+    match error {
+        ConnectionError::MqttState(StateError::Io(e)) | ConnectionError::Io(e) => {
+            e.kind() == std::io::ErrorKind::ConnectionAborted
+        }
+        _ => false,
+    }
+}
+
+/// A QoS level from its number in the config
+fn parse_qos(level: Option<u8>, field: &str) -> Result<QoS> {
+    match level {
+        Some(level) => rumqttc::qos(level)
+            .map_err(|_| anyhow::anyhow!("MQTT {} must be 0, 1 or 2, not {}", field, level)),
+        None => Ok(DEFAULT_QOS),
+    }
+}
+
+/// TLS settings for the broker. The broker address is sent as the server name
+/// (SNI) and checked against its certificate, so it must be the name the
+/// certificate was issued for.
+fn tls_client_config(config: &MqttConfig) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    match &config.ca_file {
+        Some(ca_file) => {
+            for cert in read_certificates(ca_file)? {
+                roots.add(cert)?;
+            }
+        }
+        // The system's root certificates, which public brokers like
+        // mqtt.meshtastic.org are signed by
+        None => {
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs()?);
+        }
+    }
+    
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let tls_config = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => builder.with_client_auth_cert(
+            read_certificates(cert)?,
+            read_private_key(key, config.client_key_passphrase.as_deref())?,
+        )?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(anyhow::anyhow!("MQTT client_cert and client_key must be set together")),
+    };
+    Ok(Arc::new(tls_config))
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow::anyhow!("Failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!("No certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// A PEM private key, or with a passphrase an encrypted PKCS#8 one
+/// ("BEGIN ENCRYPTED PRIVATE KEY")
+fn read_private_key(path: &Path, passphrase: Option<&str>) -> Result<PrivateKeyDer<'static>> {
+    let Some(passphrase) = passphrase else {
+        return PrivateKeyDer::from_pem_file(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read private key from {}: {}", path.display(), e));
+    };
+    let pem = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let (_, document) = pkcs8::SecretDocument::from_pem(&pem)
+        .map_err(|e| anyhow::anyhow!("Failed to read private key from {}: {}", path.display(), e))?;
+    let key = pkcs8::EncryptedPrivateKeyInfo::try_from(document.as_bytes())
+        .and_then(|info| info.decrypt(passphrase))
+        .map_err(|e| anyhow::anyhow!("Failed to decrypt private key {}: {}", path.display(), e))?;
+    Ok(PrivatePkcs8KeyDer::from(key.as_bytes().to_vec()).into())
 }
 
 impl MqttHandler {
-    pub async fn new(config: &MqttConfig, channel: u32) -> Result<Self> {
+    pub async fn new(
+        config: &MqttConfig,
+        settings: &MeshtasticConfig,
+        proxy: Option<&ProxyConfig>,
+        dedup: Arc<Mutex<PacketDedup>>,
+        node_db: Arc<Mutex<NodeDb>>,
+        metrics: Metrics,
+    ) -> Result<Self> {
+        // Without a radio there is no channel table, so a channel name is only
+        // used as the ServiceEnvelope channel_id of outgoing messages
+        let (channel, channel_name) = match &settings.channel {
+            ChannelRef::Index(index) => (*index, DEFAULT_CHANNEL_ID.to_string()),
+            ChannelRef::Name(name) => (0, name.clone()),
+        };
+        let channel_id = config.channel_id.clone().unwrap_or(channel_name);
+        
+        // Public brokers carry mostly default-key traffic, so without keys of
+        // its own the bridge reads the default channel
+        let channel_keys = if settings.channel_keys.is_empty() {
+            ChannelKeys::default_channel()
+        } else {
+            ChannelKeys::try_from(settings.channel_keys.as_slice())?
+        };
+        
+        // Gateways ignore envelopes whose gateway ID isn't a node ID, and
+        // skip those carrying their own
+        let gateway_id = match &config.gateway_id {
+            Some(gateway_id) => {
+                let node = parse_node_id(gateway_id)
+                    .ok_or_else(|| anyhow::anyhow!("MQTT gateway_id must be a node ID like \"!a1b2c3d4\", not \"{}\"", gateway_id))?;
+                format!("!{:08x}", node)
+            }
+            None => format!("!{:08x}", utils::generate_rand_id::<u32>()),
+        };
+        let root = config.root_topic.as_deref().unwrap_or_else(|| topic_root(&config.topic));
+        let aliases = Aliases::load(settings.aliases_file.as_deref())?;
+        let downlink = Downlink {
+            topic: downlink_topic(root, &channel_id, &gateway_id),
+            route_topics: config.routes.iter()
+                .map(|route| (route.irc_channel.clone(), downlink_topic(topic_root(&route.topic), &channel_id, &gateway_id)))
+                .collect(),
+            channel,
+            channel_id,
+            gateway_id,
+            qos: parse_qos(config.publish_qos, "publish_qos")?,
+            hop_limit: settings.hop_limit,
+            destination: default_destination(settings)?,
+            enabled: config.downlink.unwrap_or(true),
+            aliases: Arc::new(Mutex::new(aliases.clone())),
+        };
+        
+        let mqtt_options = Self::mqtt_options(config, proxy).await?;
+        
+        let request_capacity = config.request_capacity.unwrap_or(DEFAULT_REQUEST_CAPACITY);
+        if request_capacity == 0 {
+            return Err(anyhow::anyhow!("MQTT request_capacity must be at least 1"));
+        }
+        let (client, mut eventloop) = AsyncClient::new(mqtt_options, request_capacity);
+        let mut network_options = eventloop.network_options();
+        network_options.set_connection_timeout(config.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS));
+        eventloop.set_network_options(network_options);
+        
+        Ok(Self {
+            client,
+            eventloop,
+            config: config.clone(),
+            proxy: proxy.cloned(),
+            broker: format!("{}:{}", config.broker_address, config.port),
+            topics: config.subscriptions(),
+            subscribe_qos: parse_qos(config.subscribe_qos, "subscribe_qos")?,
+            channel_filter: config.channel_filter.clone(),
+            retained: config.retained,
+            routes: config.routes.clone(),
+            status_topic: config.status_topic.clone(),
+            feed_topic: config.feed_topic.clone(),
+            uplink_root: config.uplink.unwrap_or(false).then(|| root.to_string()),
+            uplink_gateways: Arc::new(Mutex::new(HashSet::new())),
+            publish_per_minute: config.publish_per_minute,
+            publish_burst: config.publish_burst,
+            stats_topic: config.stats_topic.clone(),
+            stats_interval: Duration::from_secs(
+                config.stats_interval_secs.unwrap_or(DEFAULT_STATS_INTERVAL_SECS).max(1)
+            ),
+            downlink,
+            nodes: NodeDirectory::new(settings.sender_format, aliases.clone()),
+            templates: Templates::try_from(&settings.templates)?,
+            filters: Filters::try_from(&settings.filters)?,
+            relay_ports: relay_ports(settings)?,
+            blocked_nodes: parse_node_ids(&settings.blocked_nodes, "blocked_nodes")?,
+            allowed_nodes: settings.allowed_nodes.as_deref()
+                .map(|nodes| parse_node_ids(nodes, "allowed_nodes"))
+                .transpose()?,
+            metrics,
+            unknown_ports: settings.unknown_ports.clone(),
+            range_test: settings.range_test,
+            range_tests: RangeTestTracker::new(),
+            position_interval: Duration::from_secs(
+                settings.positions.min_interval_secs.unwrap_or(DEFAULT_POSITION_INTERVAL_SECS)
+            ),
+            last_position: HashMap::new(),
+            telemetry: TelemetryStore::new(&settings.telemetry),
+            announce_telemetry: settings.telemetry.announce,
+            node_db,
+            channel_keys,
+            dedup,
+            connection_lost: false,
+            last_publish: None,
+            pending_subscriptions: VecDeque::new(),
+            backoff: Backoff::new(&settings.reconnect),
+        })
+    }
+    
+    /// Options for connecting to the broker, through a local forwarder when
+    /// a proxy is configured
+    async fn mqtt_options(config: &MqttConfig, proxy: Option<&ProxyConfig>) -> Result<MqttOptions> {
+        let persistent_session = config.persistent_session.unwrap_or(false);
+        // The broker finds the session by client ID, so a generated one
+        // would start afresh each run
+        if persistent_session && config.client_id.is_none() {
+            return Err(anyhow::anyhow!("MQTT persistent_session needs a client_id"));
+        }
+        if persistent_session && config.subscribe_qos == Some(0) {
+            warn!("MQTT brokers don't queue QoS 0 messages, so persistent_session has no effect with subscribe_qos 0");
+        }
         let client_id = config.client_id.clone()
             .unwrap_or_else(|| format!("meshtastic-irc-{}", std::process::id()));
         
-        info!("Connecting to MQTT broker {}:{}", config.broker_address, config.port);
+        info!("Connecting to MQTT broker {}:{}{}", config.broker_address, config.port,
+              if config.use_tls { " (TLS)" } else { "" });
         
-        let mut mqtt_options = MqttOptions::new(
-            client_id,
-            &config.broker_address,
-            config.port,
-        );
+        // Through a proxy rumqttc connects to a local forwarder, which does
+        // the TLS so the broker's certificate is checked against its name
+        let (host, port) = match proxy {
+            Some(proxy) => {
+                let tls = if config.use_tls {
+                    Some(ForwardTls::Rustls(tls_client_config(config)?))
+                } else {
+                    None
+                };
+                let local = proxy::forward(proxy.clone(), config.broker_address.clone(), config.port, tls).await?;
+                (local.ip().to_string(), local.port())
+            }
+            None => (config.broker_address.clone(), config.port),
+        };
         
-        mqtt_options.set_keep_alive(Duration::from_secs(30));
+        let mut mqtt_options = MqttOptions::new(client_id, host, port);
+        
+        let keep_alive = config.keep_alive_secs.unwrap_or(DEFAULT_KEEP_ALIVE_SECS);
+        if keep_alive > MAX_KEEP_ALIVE_SECS {
+            return Err(anyhow::anyhow!("MQTT keep_alive_secs must be at most {}", MAX_KEEP_ALIVE_SECS));
+        }
+        mqtt_options.set_keep_alive(Duration::from_secs(keep_alive));
+        let max_inflight = config.max_inflight.unwrap_or(DEFAULT_MAX_INFLIGHT);
+        if max_inflight == 0 {
+            return Err(anyhow::anyhow!("MQTT max_inflight must be at least 1"));
+        }
+        mqtt_options.set_inflight(max_inflight);
+        mqtt_options.set_clean_session(!persistent_session);
         
         // Set credentials if provided
         if let (Some(username), Some(password)) = (&config.username, &config.password) {
             mqtt_options.set_credentials(username, password);
         }
         
-        let (client, eventloop) = AsyncClient::new(mqtt_options, 100);
+        // The broker marks the bridge offline if it drops without saying goodbye
+        if let Some(status_topic) = &config.status_topic {
+            mqtt_options.set_last_will(LastWill::new(status_topic, STATUS_OFFLINE, QoS::AtLeastOnce, true));
+        }
         
-        Ok(Self {
-            client,
-            eventloop,
-            topic: config.topic.clone(),
-            channel,
-            node_names: HashMap::new(),
-        })
+        if config.use_tls && proxy.is_none() {
+            let tls_config = TlsConfiguration::Rustls(tls_client_config(config)?);
+            mqtt_options.set_transport(Transport::tls_with_config(tls_config));
+        }
+        Ok(mqtt_options)
+    }
+    
+    /// Apply a reloaded config. Filters change in place, and changed
+    /// connection settings or topics reconnect to the broker.
+    async fn apply_config(&mut self, config: &MqttConfig, settings: &MeshtasticConfig, proxy: Option<&ProxyConfig>) -> Result<()> {
+        let relay_ports = relay_ports(settings)?;
+        let blocked_nodes = parse_node_ids(&settings.blocked_nodes, "blocked_nodes")?;
+        let allowed_nodes = settings.allowed_nodes.as_deref()
+            .map(|nodes| parse_node_ids(nodes, "allowed_nodes"))
+            .transpose()?;
+        let channel_keys = if settings.channel_keys.is_empty() {
+            ChannelKeys::default_channel()
+        } else {
+            ChannelKeys::try_from(settings.channel_keys.as_slice())?
+        };
+        let templates = Templates::try_from(&settings.templates)?;
+        let filters = Filters::try_from(&settings.filters)?;
+        let aliases = Aliases::load(settings.aliases_file.as_deref())?;
+        
+        if config != &self.config || proxy != self.proxy.as_ref() {
+            let subscribe_qos = parse_qos(config.subscribe_qos, "subscribe_qos")?;
+            let mqtt_options = Self::mqtt_options(config, proxy).await?;
+            if needs_restart(&self.config, config) {
+                warn!("Some changed settings of MQTT broker {} only apply when the bridge is restarted", self.broker);
+            }
+            self.eventloop.mqtt_options = mqtt_options;
+            self.eventloop.network_options.set_connection_timeout(
+                config.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS)
+            );
+            self.broker = format!("{}:{}", config.broker_address, config.port);
+            self.topics = config.subscriptions();
+            self.subscribe_qos = subscribe_qos;
+            self.channel_filter = config.channel_filter.clone();
+            self.retained = config.retained;
+            self.routes = config.routes.clone();
+            self.status_topic = config.status_topic.clone();
+            self.config = config.clone();
+            self.proxy = proxy.cloned();
+            // Dropping the connection makes the next poll connect with the
+            // new options and subscribe to the new topics
+            info!("MQTT settings changed, reconnecting to {}", self.broker);
+            self.eventloop.clean();
+        }
+        
+        self.relay_ports = relay_ports;
+        self.blocked_nodes = blocked_nodes;
+        self.allowed_nodes = allowed_nodes;
+        self.channel_keys = channel_keys;
+        self.nodes.set_format(settings.sender_format);
+        *self.downlink.aliases.lock().unwrap() = aliases.clone();
+        self.nodes.set_aliases(aliases);
+        if templates.irc_to_mesh() != self.templates.irc_to_mesh() {
+            // The task publishing IRC messages has its own copy
+            warn!("templates.irc_to_mesh applies to MQTT broker {} once the bridge is restarted", self.broker);
+        }
+        self.templates = templates;
+        self.filters = filters;
+        self.unknown_ports = settings.unknown_ports.clone();
+        self.range_test = settings.range_test;
+        self.position_interval = Duration::from_secs(
+            settings.positions.min_interval_secs.unwrap_or(DEFAULT_POSITION_INTERVAL_SECS)
+        );
+        self.announce_telemetry = settings.telemetry.announce;
+        self.backoff = Backoff::new(&settings.reconnect);
+        Ok(())
     }
     
     pub async fn run(
         mut self,
         from_irc: mpsc::Receiver<IrcMessage>,
-        to_irc: mpsc::Sender<String>,
+        to_irc: mpsc::Sender<MeshMessage>,
+        feed: Option<mpsc::Receiver<FeedEntry>>,
+        uplink: Option<mpsc::Receiver<Uplink>>,
+        mut reload: watch::Receiver<Config>,
+        broker_index: usize,  // This broker's place in the config's list of brokers
     ) -> Result<()> {
-        // Subscribe to the Meshtastic topic
-        self.client.subscribe(&self.topic, QoS::AtLeastOnce).await?;
-        info!("Subscribed to MQTT topic: {}", self.topic);
-        
         // Spawn task to handle messages from IRC
         let client_clone = self.client.clone();
-        let topic = self.topic.clone();
-        let channel = self.channel;
+        let downlink = self.downlink.clone();
+        let limiter = self.publish_per_minute
+            .map(|per_minute| TokenBucket::new(per_minute, self.publish_burst.unwrap_or(DEFAULT_PUBLISH_BURST)));
+        let metrics = self.metrics.clone();
+        let dedup = self.dedup.clone();
+        let templates = self.templates.clone();
+        if downlink.enabled {
+            info!("Publishing IRC messages to MQTT topic: {}", downlink.topic);
+        } else {
+            info!("MQTT downlink disabled, IRC messages are not published");
+        }
         tokio::spawn(async move {
-            Self::handle_irc_messages(from_irc, client_clone, topic, channel).await;
+            Self::handle_irc_messages(from_irc, client_clone, downlink, templates, dedup, limiter, metrics).await;
         });
         
+        if let Some(stats_topic) = self.stats_topic.clone() {
+            let client = self.client.clone();
+            let metrics = self.metrics.clone();
+            let interval = self.stats_interval;
+            tokio::spawn(async move {
+                Self::publish_stats(client, stats_topic, interval, metrics).await;
+            });
+        }
+        
+        if let (Some(feed_topic), Some(feed)) = (self.feed_topic.clone(), feed) {
+            info!("Publishing bridged messages to MQTT topic: {}", feed_topic);
+            let client = self.client.clone();
+            let qos = self.downlink.qos;
+            tokio::spawn(async move {
+                Self::publish_feed(client, feed_topic, qos, feed).await;
+            });
+        }
+        
+        if let (Some(root), Some(uplink)) = (self.uplink_root.clone(), uplink) {
+            info!("Uplinking packets heard by the radios to MQTT under {}", root);
+            let client = self.client.clone();
+            let qos = self.downlink.qos;
+            let gateways = self.uplink_gateways.clone();
+            tokio::spawn(async move {
+                Self::publish_uplink(client, root, qos, uplink, gateways).await;
+            });
+        }
+        
         // Main event loop
         loop {
+            // Polling can't be interrupted safely, so a reload is picked up
+            // after the next event, at the latest the keep alive ping
+            if reload.has_changed().unwrap_or(false) {
+                let config = reload.borrow_and_update().clone();
+                if let Some(mqtt) = config.meshtastic.all_brokers().get(broker_index) {
+                    match self.apply_config(mqtt, &config.meshtastic, config.proxy.as_ref()).await {
+                        Ok(()) => info!("Applied the reloaded config to MQTT broker {}", self.broker),
+                        Err(e) => error!("Keeping the previous config of MQTT broker {}: {}", self.broker, e),
+                    }
+                }
+            }
             match self.eventloop.poll().await {
                 Ok(event) => {
                     if let Err(e) = self.handle_mqtt_event(event, &to_irc).await {
                         error!("Error handling MQTT event: {}", e);
                     }
+                    if let Err(e) = self.summarize_range_tests(&to_irc).await {
+                        error!("Error summarizing range tests: {}", e);
+                    }
                 }
                 Err(e) => {
                     error!("MQTT connection error: {}", e);
-                    // Try to reconnect after a delay
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    let rejected = if closed_by_broker(&e) { self.take_rejected_publish() } else { None };
+                    if let Some(topic) = rejected {
+                        error!("MQTT broker {} closed the connection after a publish to {}: publish rejected, check the broker's ACLs for that topic", self.broker, topic);
+                        self.connection_lost = true;
+                        to_irc.send(MeshMessage::new(format!(
+                            "MQTT broker {} rejected a publish to {}, check its ACLs; the message was dropped", self.broker, topic
+                        ))).await?;
+                    } else if !self.connection_lost {
+                        self.connection_lost = true;
+                        to_irc.send(MeshMessage::new(format!(
+                            "Lost connection to MQTT broker {}, reconnecting", self.broker
+                        ))).await?;
+                    }
+                    // The next poll reconnects; back off until it works
+                    let Some(delay) = self.backoff.next_delay() else {
+                        return Err(anyhow::anyhow!(
+                            "Gave up reconnecting to MQTT broker {} after {} attempts", self.broker, self.backoff.attempts()
+                        ));
+                    };
+                    info!("Reconnecting to MQTT broker {} in {:?}", self.broker, delay);
+                    tokio::time::sleep(delay).await;
                 }
             }
         }
     }
     
+    /// The topic of the publish the broker rejected by closing the
+    /// connection, if it closed right after one. The publish is dropped, as
+    /// it would otherwise be resent, and rejected, on every reconnect.
+    fn take_rejected_publish(&mut self) -> Option<String> {
+        let (sent_at, pkid) = self.last_publish.take()?;
+        if sent_at.elapsed() > PUBLISH_REJECT_WINDOW {
+            return None;
+        }
+        // QoS 0 publishes aren't resent, nor can they be told apart
+        let position = self.eventloop.pending.iter()
+            .position(|request| matches!(request, Request::Publish(publish) if pkid != 0 && publish.pkid == pkid));
+        match position.and_then(|position| self.eventloop.pending.remove(position)) {
+            Some(Request::Publish(publish)) => Some(publish.topic),
+            _ => Some("a QoS 0 topic".to_string()),
+        }
+    }
+    
+    /// Keep a retained JSON snapshot of the bridge's health on the stats
+    /// topic, for monitoring systems that watch the broker
+    async fn publish_stats(client: AsyncClient, topic: String, interval: Duration, metrics: Metrics) {
+        let mut timer = tokio::time::interval(interval);
+        loop {
+            timer.tick().await;
+            let stats = metrics.status_json().to_string();
+            debug!("Publishing bridge stats to {}: {}", topic, stats);
+            if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, stats).await {
+                error!("Failed to publish bridge stats: {}", e);
+            }
+        }
+    }
+    
+    async fn publish_feed(client: AsyncClient, topic: String, qos: QoS, mut feed: mpsc::Receiver<FeedEntry>) {
+        while let Some(entry) = feed.recv().await {
+            let document = match serde_json::to_string(&entry) {
+                Ok(document) => document,
+                Err(e) => {
+                    error!("Failed to encode feed entry: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = client.publish(&topic, qos, false, document).await {
+                error!("Failed to publish to the MQTT feed: {}", e);
+            }
+        }
+    }
+    
+    /// Publish packets the radios heard, so the bridge acts as an MQTT
+    /// gateway for them
+    async fn publish_uplink(
+        client: AsyncClient,
+        root: String,
+        qos: QoS,
+        mut uplink: mpsc::Receiver<Uplink>,
+        gateways: Arc<Mutex<HashSet<String>>>,
+    ) {
+        while let Some(message) = uplink.recv().await {
+            gateways.lock().unwrap().insert(message.gateway_id.clone());
+            let topic = downlink_topic(&root, &message.channel_id, &message.gateway_id);
+            debug!("Uplinking packet {} from {:08x} to {}", message.packet.id, message.packet.from, topic);
+            let service_envelope = ServiceEnvelope {
+                packet: Some(message.packet),
+                channel_id: message.channel_id,
+                gateway_id: message.gateway_id,
+            };
+            let payload = prost::Message::encode_to_vec(&service_envelope);
+            if let Err(e) = client.publish(&topic, qos, false, payload).await {
+                error!("Failed to uplink packet to MQTT: {}", e);
+            }
+        }
+    }
+    
     async fn handle_irc_messages(
         mut from_irc: mpsc::Receiver<IrcMessage>,
         client: AsyncClient,
-        topic: String,
-        channel: u32,
+        downlink: Downlink,
+        templates: Templates,
+        dedup: Arc<Mutex<PacketDedup>>,
+        mut limiter: Option<TokenBucket>,
+        metrics: Metrics,
     ) {
         while let Some(message) = from_irc.recv().await {
             debug!("Received message from IRC: {} - {}", message.sender, message.content);
             
-            if let Err(e) = Self::send_to_mqtt(&client, &topic, &message, channel).await {
-                error!("Failed to send message to MQTT: {}", e);
+            if !downlink.enabled {
+                continue;
+            }
+            
+            // The main IRC channel is bridged to the main topic, route
+            // channels to their route's topic
+            let topic = match &message.channel {
+                None => &downlink.topic,
+                Some(irc_channel) => match downlink.route_topics.get(irc_channel) {
+                    Some(topic) => topic,
+                    None => continue,
+                },
+            };
+            if message.command.is_some() {
+                debug!("Ignoring bridge command, not supported over MQTT");
+                continue;
+            }
+            if let Some(destination) = &message.destination {
+                error!("Direct messages are not supported over MQTT, dropping message to {}", destination);
+                continue;
+            }
+            if let Some(mesh_channel) = message.mesh_channel {
+                error!("Choosing the mesh channel is not supported over MQTT, dropping message to channel {}", mesh_channel);
+                continue;
+            }
+            
+            // Long messages go out in numbered parts
+            let prefix = templates.mesh_prefix(&TemplateValues {
+                sender: &message.sender,
+                channel: &downlink.channel_id,
+                snr: None,
+            });
+            let content = downlink.aliases.lock().unwrap().mentions_to_mesh(&message.content);
+            for (i, text) in split_text(&prefix, &content, MAX_TEXT_BYTES).iter().enumerate() {
+                if i > 0 {
+                    tokio::time::sleep(CHUNK_DELAY).await;
+                }
+                // A busy IRC channel shouldn't flood the downlink topic
+                if limiter.as_mut().is_some_and(|limiter| !limiter.take()) {
+                    error!("MQTT publish rate limit reached, dropping message from {}", message.sender);
+                    metrics.record_publish_dropped();
+                    break;
+                }
+                if let Err(e) = Self::send_to_mqtt(&client, &downlink, &dedup, topic, text).await {
+                    error!("Failed to send message to MQTT: {}", e);
+                }
             }
         }
     }
     
     async fn send_to_mqtt(
         client: &AsyncClient,
+        downlink: &Downlink,
+        dedup: &Mutex<PacketDedup>,
         topic: &str,
-        message: &IrcMessage,
-        channel: u32,
+        text: &str,
     ) -> Result<()> {
-        let text = format!("[IRC-{}] {}", message.sender, message.content);
-        
         // Create a text message data payload
         let data = Data {
             portnum: PortNum::TextMessageApp as i32,
@@ -113,12 +876,17 @@ impl MqttHandler {
             ..Default::default()
         };
         
-        // Create mesh packet for broadcast
+        // Chosen here rather than by the gateway, so the packet can be
+        // recognized when it comes back
+        let id: u32 = utils::generate_rand_id();
+        
+        // Create mesh packet, broadcast unless a default destination is set
         let mesh_packet = MeshPacket {
-            to: 0xffffffff, // Broadcast address
+            to: downlink.destination,
             from: 0, // Will be filled by the device
-            channel,
-            id: 0, // Will be assigned by the device
+            channel: downlink.channel,
+            id,
+            hop_limit: downlink.hop_limit.unwrap_or(0),
             priority: mesh_packet::Priority::Default as i32,
             payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
             ..Default::default()
@@ -127,15 +895,16 @@ impl MqttHandler {
         // Create service envelope
         let service_envelope = ServiceEnvelope {
             packet: Some(mesh_packet),
-            channel_id: format!("LongFast"),
-            gateway_id: format!("irc-bridge"),
+            channel_id: downlink.channel_id.clone(),
+            gateway_id: downlink.gateway_id.clone(),
         };
         
         // Serialize to protobuf
         let payload = prost::Message::encode_to_vec(&service_envelope);
         
         info!("Sending to MQTT topic {}: {}", topic, text);
-        client.publish(topic, QoS::AtLeastOnce, false, payload).await?;
+        dedup.lock().unwrap().record_sent(id);
+        client.publish(topic, downlink.qos, false, payload).await?;
         
         Ok(())
     }
@@ -143,33 +912,89 @@ impl MqttHandler {
     async fn handle_mqtt_event(
         &mut self,
         event: Event,
-        to_irc: &mpsc::Sender<String>,
+        to_irc: &mpsc::Sender<MeshMessage>,
     ) -> Result<()> {
         match event {
             Event::Incoming(Packet::Publish(publish)) => {
                 debug!("Received MQTT message on topic: {}", publish.topic);
                 
-                // Only process messages from our subscribed topic
-                if publish.topic == self.topic {
+                // Retained messages are replayed on every subscription, and
+                // may be long out of date
+                if publish.retain && self.retained == RetainedPolicy::Skip {
+                    debug!("Skipping retained message on {}", publish.topic);
+                    return Ok(());
+                }
+                
+                // Only process messages from our subscribed topics
+                if self.topics.iter().any(|filter| matches(&publish.topic, filter)) {
                     // Try to decode as ServiceEnvelope
                     match prost::Message::decode(&publish.payload[..]) {
                         Ok(envelope) => {
                             let service_envelope: ServiceEnvelope = envelope;
+                            // Our own IRC messages come back on wildcard
+                            // subscriptions, and in gateway mode so do the
+                            // packets our radios uplinked
+                            if service_envelope.gateway_id == self.downlink.gateway_id
+                                || self.uplink_gateways.lock().unwrap().contains(&service_envelope.gateway_id) {
+                                return Ok(());
+                            }
+                            // Channel indexes differ between devices, names don't
+                            if let Some(filter) = &self.channel_filter {
+                                if !filter.iter().any(|pattern| mask_matches(pattern, &service_envelope.channel_id)) {
+                                    debug!("Ignoring packet on channel {}", service_envelope.channel_id);
+                                    return Ok(());
+                                }
+                            }
                             if let Some(packet) = service_envelope.packet {
-                                self.process_mesh_packet(packet, to_irc).await?;
+                                let old = publish.retain && self.retained == RetainedPolicy::Mark;
+                                self.process_mesh_packet(packet, &publish.topic, old, to_irc).await?;
                             }
                         }
-                        Err(e) => {
-                            debug!("Failed to decode ServiceEnvelope: {}", e);
-                        }
+                        Err(e) => match serde_json::from_slice::<JsonMessage>(&publish.payload) {
+                            Ok(message) => self.process_json_message(message),
+                            Err(_) => debug!("Failed to decode ServiceEnvelope: {}", e),
+                        },
                     }
                 }
             }
-            Event::Incoming(Packet::ConnAck(_)) => {
-                info!("Connected to MQTT broker");
+            Event::Incoming(Packet::ConnAck(connack)) => {
+                if connack.session_present {
+                    info!("Connected to MQTT broker {}, resuming the previous session", self.broker);
+                } else {
+                    info!("Connected to MQTT broker {}", self.broker);
+                }
+                // A clean session starts without subscriptions, and
+                // subscribing again to a resumed one does no harm
+                self.pending_subscriptions.clear();
+                for topic in &self.topics {
+                    self.client.try_subscribe(topic, self.subscribe_qos)?;
+                    self.pending_subscriptions.push_back(topic.clone());
+                    info!("Subscribing to MQTT topic: {}", topic);
+                }
+                if let Some(status_topic) = &self.status_topic {
+                    self.client.try_publish(status_topic, QoS::AtLeastOnce, true, STATUS_ONLINE)?;
+                }
+                self.backoff.reset();
+                if self.connection_lost {
+                    self.connection_lost = false;
+                    to_irc.send(MeshMessage::new(format!("Reconnected to MQTT broker {}", self.broker))).await?;
+                }
+            }
+            Event::Incoming(Packet::SubAck(suback)) => {
+                // One topic is subscribed to at a time, so SubAcks come back
+                // in the order of pending_subscriptions
+                let topic = self.pending_subscriptions.pop_front().unwrap_or_default();
+                if suback.return_codes.contains(&SubscribeReasonCode::Failure) {
+                    error!("MQTT broker {} refused the subscription to {}, check the broker's ACLs for that topic", self.broker, topic);
+                    to_irc.send(MeshMessage::new(format!(
+                        "MQTT broker {} refused the subscription to {}, check its ACLs", self.broker, topic
+                    ))).await?;
+                } else {
+                    debug!("Subscription to {} acknowledged by MQTT broker", topic);
+                }
             }
-            Event::Incoming(Packet::SubAck(_)) => {
-                info!("Successfully subscribed to topic");
+            Event::Outgoing(Outgoing::Publish(pkid)) => {
+                self.last_publish = Some((Instant::now(), pkid));
             }
             Event::Incoming(Packet::Disconnect) => {
                 info!("Disconnected from MQTT broker");
@@ -182,41 +1007,230 @@ impl MqttHandler {
     
     async fn process_mesh_packet(
         &mut self,
-        packet: MeshPacket,
-        to_irc: &mpsc::Sender<String>,
+        mut packet: MeshPacket,
+        topic: &str,
+        old: bool,  // Marked as a stale retained message
+        to_irc: &mpsc::Sender<MeshMessage>,
     ) -> Result<()> {
-        debug!("Processing MeshPacket from node {:08x}", packet.from);
-        
-        // Check if this is a Data packet with decoded payload
-        if let Some(payload_variant) = &packet.payload_variant {
-            match payload_variant {
-                mesh_packet::PayloadVariant::Decoded(data) => {
-                    // Only process text messages
-                    if data.portnum() == PortNum::TextMessageApp {
-                        if data.payload.len() > 0 {
-                            if let Ok(text) = std::str::from_utf8(&data.payload) {
-                                // Don't forward our own messages back to IRC
-                                if !text.starts_with("[IRC-") {
-                                    // Use short name if available, otherwise use ID
-                                    let sender = self.node_names.get(&packet.from)
-                                        .cloned()
-                                        .unwrap_or_else(|| format!("{:08x}", packet.from));
-                                    let message = format!("[mesh-{}]: {}", sender, text);
-                                    
-                                    info!("Received Meshtastic message via MQTT: {}", message);
-                                    to_irc.send(message).await?;
-                                    debug!("Forwarded Meshtastic message to IRC");
-                                }
-                            }
-                        }
+        debug!("Processing MeshPacket from node {:08x} on {}", packet.from, topic);
+        
+        // Our own messages come back through the wildcard subscription
+        if self.dedup.lock().unwrap().was_sent(packet.id) {
+            debug!("Ignoring our own packet {} uploaded by {:08x}", packet.id, packet.from);
+            return Ok(());
+        }
+        if self.dedup.lock().unwrap().is_duplicate(packet.from, packet.id) {
+            debug!("Ignoring duplicate packet {} from {:08x}", packet.id, packet.from);
+            self.metrics.record_duplicate();
+            return Ok(());
+        }
+        
+        // Gateways upload encrypted packets unless their channel has a
+        // public key; decrypt those we have a key for
+        if let Some((data, key)) = self.channel_keys.decrypt(&packet) {
+            debug!("Decrypted packet {} from {:08x} on channel {}", packet.id, packet.from, key.name);
+            packet.payload_variant = Some(mesh_packet::PayloadVariant::Decoded(data));
+        }
+        
+        // Only process decoded packets on relayed ports; ignore encrypted ones
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+            debug!("No key for packet {} from {:08x} (channel hash {})", packet.id, packet.from, packet.channel);
+            return Ok(());
+        };
+        if self.blocked_nodes.contains(&packet.from) {
+            debug!("Ignoring packet from blocked node {:08x}", packet.from);
+            return Ok(());
+        }
+        if self.allowed_nodes.as_ref().is_some_and(|nodes| !nodes.contains(&packet.from)) {
+            debug!("Dropping packet from unlisted node {:08x}", packet.from);
+            self.metrics.record_dropped_unlisted();
+            return Ok(());
+        }
+        // SNR is only known to the gateway that heard the packet
+        self.metrics.record_node_packet(packet.from, data.payload.len(), data.portnum() == PortNum::TextMessageApp, None);
+        self.node_db.lock().unwrap().heard(packet.from, None, &mqtt_leg(&self.config));
+        if data.portnum() == PortNum::PositionApp {
+            if let Ok(position) = Position::decode(data.payload.as_slice()) {
+                self.node_db.lock().unwrap().update_position(packet.from, &position);
+            }
+        }
+        
+        // Learn names so senders are shown by name rather than hex ID
+        if data.portnum() == PortNum::NodeinfoApp {
+            let user = User::decode(data.payload.as_slice())?;
+            self.node_db.lock().unwrap().update_user(packet.from, &user);
+            if self.nodes.insert(packet.from, &user) {
+                debug!("Learned name of node {:08x}: {}", packet.from, self.nodes.display(packet.from));
+            }
+            return Ok(());
+        }
+        
+        let sender = self.nodes.display(packet.from);
+        let irc_channel = self.routes.iter()
+            .find(|route| matches(topic, &route.topic))
+            .map(|route| route.irc_channel.clone());
+        if is_unknown_port(data.portnum) && self.unknown_ports.enabled {
+            // Show traffic of apps the bridge doesn't know
+            if self.unknown_ports.sink == DumpSink::Log {
+                info!("Packet from {:08x} via MQTT {}: {}", packet.from, topic, dump_packet(&packet, data, self.unknown_ports.format, None));
+            } else {
+                let dump = dump_packet(&packet, data, self.unknown_ports.format, Some(DUMP_MAX_BYTES));
+                to_irc.send(MeshMessage {
+                    content: self.report_line(&sender, topic, &packet, &dump),
+                    channel: irc_channel,
+                    chat: None,
+                }).await?;
+            }
+            return Ok(());
+        }
+        
+        // Range tests have their own port, but are sometimes run over the text port
+        let range_test = data.portnum() == PortNum::RangeTestApp
+            || (data.portnum() == PortNum::TextMessageApp
+                && std::str::from_utf8(&data.payload).is_ok_and(is_range_test));
+        if range_test && self.range_test != RangeTestMode::Relay {
+            debug!("Suppressing range test packet from {:08x}", packet.from);
+            if self.range_test == RangeTestMode::Summarize {
+                self.range_tests.record(packet.from, topic_channel(topic), None);
+            }
+            return Ok(());
+        }
+        if data.portnum() == PortNum::RangeTestApp {
+            let text = String::from_utf8_lossy(&data.payload);
+            to_irc.send(MeshMessage {
+                content: self.report_line(&sender, topic, &packet, &format!("range test: {}", text.trim())),
+                channel: irc_channel,
+                chat: None,
+            }).await?;
+            return Ok(());
+        }
+        
+        // Telemetry is recorded whether or not it is relayed, and big
+        // changes such as a low battery announced like a radio's
+        if data.portnum() == PortNum::TelemetryApp {
+            let telemetry = Telemetry::decode(data.payload.as_slice())?;
+            let events = self.telemetry.update(packet.from, &telemetry);
+            if let Some(telemetry::Variant::DeviceMetrics(metrics)) = &telemetry.variant {
+                self.node_db.lock().unwrap().update_device_metrics(packet.from, metrics);
+            }
+            if self.announce_telemetry && !old {
+                for event in events {
+                    to_irc.send(MeshMessage {
+                        content: self.report_line(&sender, topic, &packet, &event.describe()),
+                        channel: irc_channel.clone(),
+                        chat: None,
+                    }).await?;
+                }
+            }
+        }
+        
+        if !self.relay_ports.contains(&data.portnum()) || data.payload.is_empty() {
+            return Ok(());
+        }
+        
+        let mut chat = None;
+        let message = match data.portnum() {
+            PortNum::TextMessageApp | PortNum::DetectionSensorApp => {
+                let Ok(text) = std::str::from_utf8(&data.payload) else {
+                    return Ok(());
+                };
+                if data.portnum() == PortNum::DetectionSensorApp {
+                    format!("[sensor-{}] {}", sender, text.trim())
+                } else {
+                    let Some(text) = self.filters.mesh_to_irc.apply(text) else {
+                        debug!("Filtered out message from {:08x}: {}", packet.from, text);
+                        self.metrics.record_filtered();
+                        return Ok(());
+                    };
+                    if !old {
+                        chat = Some(Chat { sender: sender.clone(), text: text.to_string() });
                     }
+                    let text = self.nodes.aliases().mentions_to_irc(&text);
+                    let values = TemplateValues {
+                        sender: &sender,
+                        channel: topic_channel(topic),
+                        snr: (packet.rx_snr != 0.0).then_some(packet.rx_snr),
+                    };
+                    self.templates.to_irc(&values, &text)
                 }
-                _ => {
-                    // Ignore encrypted or other packet types
+            }
+            PortNum::PositionApp => {
+                let Some(description) = format_position(&Position::decode(data.payload.as_slice())?) else {
+                    return Ok(());
+                };
+                // Rate limit per node so GPS beacons don't flood the channel
+                let now = Instant::now();
+                if self.last_position.get(&packet.from).is_some_and(|last| now.duration_since(*last) < self.position_interval) {
+                    return Ok(());
                 }
+                self.last_position.insert(packet.from, now);
+                self.report_line(&sender, topic, &packet, &format!("position: {}", description))
             }
-        }
+            PortNum::WaypointApp => {
+                let waypoint = Waypoint::decode(data.payload.as_slice())?;
+                self.report_line(&sender, topic, &packet, &format!("waypoint: {}", format_waypoint(&waypoint)))
+            }
+            PortNum::TelemetryApp => {
+                let report = NodeTelemetry::from_report(&Telemetry::decode(data.payload.as_slice())?);
+                if report.is_empty() {
+                    return Ok(());
+                }
+                self.report_line(&sender, topic, &packet, &format!("telemetry: {}", report.describe()))
+            }
+            _ => return Ok(()),
+        };
         
+        let message = if old { format!("[old] {}", message) } else { message };
+        info!("Received Meshtastic message via MQTT {}: {}", topic, message);
+        to_irc.send(MeshMessage {
+            content: message,
+            channel: irc_channel,
+            chat,
+        }).await?;
+        debug!("Forwarded Meshtastic message to IRC");
         Ok(())
     }
-}
\ No newline at end of file
+    
+    /// JSON topics only serve to learn node names; their messages are relayed
+    /// from the protobuf topics
+    fn process_json_message(&mut self, message: JsonMessage) {
+        if message.kind != "nodeinfo" {
+            return;
+        }
+        let name = |field: &str| message.payload.get(field)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let user = User {
+            long_name: name("longname"),
+            short_name: name("shortname"),
+            ..Default::default()
+        };
+        if self.nodes.insert(message.from, &user) {
+            debug!("Learned name of node {:08x} from JSON: {}", message.from, self.nodes.display(message.from));
+        }
+    }
+    
+    /// A line about a packet that isn't chat, laid out by the mesh_to_irc
+    /// template like chat is
+    fn report_line(&self, sender: &str, topic: &str, packet: &MeshPacket, report: &str) -> String {
+        let values = TemplateValues {
+            sender,
+            channel: topic_channel(topic),
+            snr: (packet.rx_snr != 0.0).then_some(packet.rx_snr),
+        };
+        self.templates.to_irc(&values, report)
+    }
+
+    /// Post one line for each range test that has finished
+    async fn summarize_range_tests(&mut self, to_irc: &mpsc::Sender<MeshMessage>) -> Result<()> {
+        for summary in self.range_tests.finished(RANGE_TEST_IDLE) {
+            let sender = self.nodes.display(summary.node);
+            let values = TemplateValues { sender: &sender, channel: &summary.channel, snr: None };
+            let content = self.templates.to_irc(&values, &format!("sent {} range test packets", summary.packets));
+            info!("{}", content);
+            to_irc.send(MeshMessage::new(content)).await?;
+        }
+        Ok(())
+    }
+}
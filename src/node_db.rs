@@ -0,0 +1,148 @@
+use anyhow::Result;
+use meshtastic::protobufs::{DeviceMetrics, HardwareModel, NodeInfo, Position, User};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::nodes::parse_node_id;
+
+/// What the bridge knows about one node, in the shape exported for mapping
+/// tools
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NodeRecord {
+    pub id: String,  // e.g. "!a1b2c3d4"
+    pub short_name: Option<String>,
+    pub long_name: Option<String>,
+    pub hw_model: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<i32>,
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f32>,
+    pub snr: Option<f32>,
+    pub last_heard: Option<u64>,  // Unix time
+}
+
+/// Every node seen, from the radio's own node database at connect time and
+/// the packets heard since
+#[derive(Debug, Default)]
+pub struct NodeDb {
+    nodes: BTreeMap<u32, NodeRecord>,
+    heard_via: HashMap<u32, String>,  // The endpoint each node was last heard on, as links name it
+}
+
+impl NodeDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn entry(&mut self, node: u32) -> &mut NodeRecord {
+        self.nodes.entry(node).or_insert_with(|| NodeRecord {
+            id: format!("!{:08x}", node),
+            ..Default::default()
+        })
+    }
+
+    /// Take in a node from the radio's database
+    pub fn update_from_node_info(&mut self, info: &NodeInfo) {
+        if let Some(user) = &info.user {
+            self.update_user(info.num, user);
+        }
+        if let Some(position) = &info.position {
+            self.update_position(info.num, position);
+        }
+        if let Some(metrics) = &info.device_metrics {
+            self.update_device_metrics(info.num, metrics);
+        }
+        let record = self.entry(info.num);
+        if info.snr != 0.0 {
+            record.snr = Some(info.snr);
+        }
+        if info.last_heard != 0 {
+            record.last_heard = Some(info.last_heard.into());
+        }
+    }
+
+    pub fn update_user(&mut self, node: u32, user: &User) {
+        let record = self.entry(node);
+        record.short_name = Some(user.short_name.clone());
+        record.long_name = Some(user.long_name.clone());
+        record.hw_model = HardwareModel::try_from(user.hw_model).ok()
+            .map(|model| model.as_str_name().to_string());
+    }
+
+    /// Positions without a fix are ignored so the last known one is kept
+    pub fn update_position(&mut self, node: u32, position: &Position) {
+        let (Some(latitude_i), Some(longitude_i)) = (position.latitude_i, position.longitude_i) else {
+            return;
+        };
+        if latitude_i == 0 && longitude_i == 0 {
+            return;
+        }
+        let record = self.entry(node);
+        record.latitude = Some(latitude_i as f64 * 1e-7);
+        record.longitude = Some(longitude_i as f64 * 1e-7);
+        record.altitude = position.altitude;
+    }
+
+    pub fn update_device_metrics(&mut self, node: u32, metrics: &DeviceMetrics) {
+        let record = self.entry(node);
+        record.battery_level = metrics.battery_level.or(record.battery_level);
+        record.voltage = metrics.voltage.or(record.voltage);
+    }
+
+    /// Note a packet from the node heard on the endpoint `via`, with the SNR
+    /// it was received at if known
+    pub fn heard(&mut self, node: u32, snr: Option<f32>, via: &str) {
+        self.heard_via.insert(node, via.to_string());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+        let record = self.entry(node);
+        record.last_heard = Some(now);
+        if snr.is_some() {
+            record.snr = snr;
+        }
+    }
+
+    /// The endpoint a node was last heard on
+    pub fn heard_via(&self, node: u32) -> Option<&str> {
+        self.heard_via.get(&node).map(String::as_str)
+    }
+
+    /// Look up a node by `!id`, short name or long name
+    pub fn resolve(&self, name: &str) -> Option<u32> {
+        parse_node_id(name).or_else(|| {
+            self.nodes.iter()
+                .find(|(_, record)| [&record.short_name, &record.long_name].into_iter()
+                    .any(|known| known.as_deref().is_some_and(|known| known.eq_ignore_ascii_case(name))))
+                .map(|(num, _)| *num)
+        })
+    }
+
+    /// One line per node, e.g. "!a1b2c3d4 ABCD (Alice's Base) 47.6201,-122.3490 battery 85%"
+    pub fn describe_all(&self) -> Vec<String> {
+        self.nodes.values()
+            .map(|record| {
+                let mut line = record.id.clone();
+                if let (Some(short_name), Some(long_name)) = (&record.short_name, &record.long_name) {
+                    line.push_str(&format!(" {} ({})", short_name, long_name));
+                }
+                if let (Some(latitude), Some(longitude)) = (record.latitude, record.longitude) {
+                    line.push_str(&format!(" {:.4},{:.4}", latitude, longitude));
+                }
+                if let Some(level) = record.battery_level {
+                    line.push_str(&format!(" battery {}%", level));
+                }
+                line
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let nodes: Vec<&NodeRecord> = self.nodes.values().collect();
+        Ok(serde_json::to_string_pretty(&nodes)?)
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use meshtastic::protobufs::User;
+use std::collections::{HashMap, HashSet};
+
+use crate::aliases::Aliases;
+use crate::config::{MeshtasticConfig, SenderFormat};
+
+// Packets to this address go to every node
+pub const BROADCAST_ADDR: u32 = 0xffffffff;
+
+/// Parse a node ID given as `!a1b2c3d4` or `a1b2c3d4`
+pub fn parse_node_id(node: &str) -> Option<u32> {
+    let id = node.strip_prefix('!').unwrap_or(node);
+    if id.len() != 8 {
+        return None;
+    }
+    u32::from_str_radix(id, 16).ok()
+}
+
+/// Where messages said in IRC channels are sent: the configured
+/// `default_destination` node, or every node
+pub fn default_destination(settings: &MeshtasticConfig) -> Result<u32> {
+    match &settings.default_destination {
+        Some(node) => parse_node_id(node).ok_or_else(|| {
+            anyhow::anyhow!("Invalid node ID \"{}\" in default_destination, expected e.g. \"!a1b2c3d4\"", node)
+        }),
+        None => Ok(BROADCAST_ADDR),
+    }
+}
+
+/// Parse the node IDs of a config setting, naming the setting in the error
+pub fn parse_node_ids(nodes: &[String], setting: &str) -> Result<HashSet<u32>> {
+    nodes.iter()
+        .map(|node| parse_node_id(node).ok_or_else(|| {
+            anyhow::anyhow!("Invalid node ID \"{}\" in {}, expected e.g. \"!a1b2c3d4\"", node, setting)
+        }))
+        .collect()
+}
+
+/// The names a node announces in its NodeInfo
+#[derive(Debug, Clone, Default)]
+struct NodeName {
+    short_name: String,
+    long_name: String,
+}
+
+/// Known mesh nodes, and how their names are shown on IRC
+#[derive(Debug)]
+pub struct NodeDirectory {
+    nodes: HashMap<u32, NodeName>,
+    format: SenderFormat,
+    aliases: Aliases,
+}
+
+impl NodeDirectory {
+    pub fn new(format: SenderFormat, aliases: Aliases) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            format,
+            aliases,
+        }
+    }
+
+    pub fn set_format(&mut self, format: SenderFormat) {
+        self.format = format;
+    }
+
+    pub fn set_aliases(&mut self, aliases: Aliases) {
+        self.aliases = aliases;
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    /// Record a node's names. Users without any name are ignored.
+    pub fn insert(&mut self, node: u32, user: &User) -> bool {
+        if user.short_name.is_empty() && user.long_name.is_empty() {
+            return false;
+        }
+        self.nodes.insert(node, NodeName {
+            short_name: user.short_name.clone(),
+            long_name: user.long_name.clone(),
+        });
+        true
+    }
+
+    pub fn contains(&self, node: u32) -> bool {
+        self.nodes.contains_key(&node)
+    }
+
+    /// The node's alias, or its name in the configured format, falling back
+    /// to whichever name is known and finally to the hex node ID
+    pub fn display(&self, node: u32) -> String {
+        let id = format!("{:08x}", node);
+        if self.format == SenderFormat::NodeId {
+            return format!("!{}", id);
+        }
+        if let Some(alias) = self.aliases.get(node) {
+            return alias.name.clone();
+        }
+        let Some(name) = self.nodes.get(&node) else {
+            return id;
+        };
+
+        let short = (!name.short_name.is_empty()).then_some(name.short_name.as_str());
+        let long = (!name.long_name.is_empty()).then_some(name.long_name.as_str());
+        match (self.format, short, long) {
+            (SenderFormat::ShortLong, Some(short), Some(long)) => format!("{} ({})", short, long),
+            (SenderFormat::Long, _, Some(long)) => long.to_string(),
+            (_, Some(short), _) => short.to_string(),
+            (_, None, Some(long)) => long.to_string(),
+            (_, None, None) => id,
+        }
+    }
+
+    /// Look up a node by `!hexid` / hex ID, alias, linked IRC nick, short
+    /// name or long name
+    pub fn resolve(&self, node: &str) -> Option<u32> {
+        if let Some(num) = parse_node_id(node).or_else(|| self.aliases.resolve(node)) {
+            return Some(num);
+        }
+        self.nodes.iter()
+            .find(|(_, name)| name.short_name.eq_ignore_ascii_case(node))
+            .or_else(|| self.nodes.iter().find(|(_, name)| name.long_name.eq_ignore_ascii_case(node)))
+            .map(|(num, _)| *num)
+    }
+}
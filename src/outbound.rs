@@ -0,0 +1,62 @@
+use std::collections::VecDeque;
+
+/// How urgently a queued packet should go out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,    // ACKs
+    Normal,  // Direct messages and requests made from IRC
+    Low,     // Channel chatter and background requests
+}
+
+const PRIORITIES: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+/// Packets waiting for airtime, sent highest priority first and in order
+/// within a priority. When the queue is full the oldest packet of the lowest
+/// priority is dropped.
+#[derive(Debug)]
+pub struct OutboundQueue<T> {
+    queues: [VecDeque<T>; 3],
+    max_depth: usize,
+}
+
+impl<T> OutboundQueue<T> {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            max_depth: max_depth.max(1),
+        }
+    }
+
+    /// Packets beyond a lowered depth stay queued, only new ones push out
+    /// the oldest
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth.max(1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// Queue a packet, returning the one dropped to make room, if any
+    pub fn push(&mut self, priority: Priority, item: T) -> Option<T> {
+        self.queues[priority as usize].push_back(item);
+        if self.len() <= self.max_depth {
+            return None;
+        }
+        PRIORITIES.iter().rev()
+            .find_map(|priority| self.queues[*priority as usize].pop_front())
+    }
+
+    /// The packet that goes out next
+    pub fn front(&self) -> Option<&T> {
+        self.queues.iter().find_map(VecDeque::front)
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.queues.iter_mut().find_map(VecDeque::pop_front)
+    }
+}
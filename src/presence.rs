@@ -0,0 +1,40 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// When each node was last heard, and which ones have gone quiet. Shared by
+/// all radios so a node heard by any of them counts as present.
+#[derive(Debug)]
+pub struct PresenceTracker {
+    last_heard: HashMap<u32, Instant>,
+    offline: HashSet<u32>,  // Announced as offline and not heard since
+    offline_after: Duration,
+}
+
+impl PresenceTracker {
+    pub fn new(offline_after: Duration) -> Self {
+        Self {
+            last_heard: HashMap::new(),
+            offline: HashSet::new(),
+            offline_after,
+        }
+    }
+
+    /// Note that a node was heard, returning true if it was offline until now
+    pub fn heard(&mut self, node: u32) -> bool {
+        self.last_heard.insert(node, Instant::now());
+        self.offline.remove(&node)
+    }
+
+    /// Nodes that have just passed the offline timeout, with how long ago
+    /// they were last heard. Each is reported once until heard again.
+    pub fn newly_offline(&mut self) -> Vec<(u32, Duration)> {
+        let mut nodes = Vec::new();
+        for (node, heard_at) in &self.last_heard {
+            let silent = heard_at.elapsed();
+            if silent >= self.offline_after && self.offline.insert(*node) {
+                nodes.push((*node, silent));
+            }
+        }
+        nodes
+    }
+}
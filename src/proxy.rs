@@ -0,0 +1,185 @@
+use anyhow::Result;
+use log::{debug, info};
+use rumqttc::tokio_rustls::{self, rustls::pki_types::ServerName, rustls::ClientConfig};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::ProxyConfig;
+
+const SOCKS_VERSION: u8 = 5;
+
+// Authentication methods offered in the greeting
+const AUTH_NONE: u8 = 0;
+const AUTH_PASSWORD: u8 = 2;
+const AUTH_UNACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 1;
+
+// Address types in the connect request and reply
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+/// TLS the forwarder adds on the way to the server. The client only sees a
+/// local address, so it couldn't check the server's certificate itself.
+pub enum ForwardTls {
+    Rustls(Arc<ClientConfig>),
+    Native(tokio_native_tls::TlsConnector),
+}
+
+/// A human-readable explanation of a SOCKS5 reply code
+fn describe_reply(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// Open a connection to host:port through a SOCKS5 proxy. Host names are
+/// resolved by the proxy, as Tor needs.
+pub async fn connect(proxy: &ProxyConfig, host: &str, port: u16) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(&proxy.address).await
+        .map_err(|e| anyhow::anyhow!("Failed to connect to proxy {}: {}", proxy.address, e))?;
+
+    let method = if proxy.username.is_some() { AUTH_PASSWORD } else { AUTH_NONE };
+    stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(anyhow::anyhow!("Proxy {} is not a SOCKS5 proxy", proxy.address));
+    }
+    match reply[1] {
+        AUTH_NONE => {}
+        AUTH_PASSWORD => {
+            let username = proxy.username.as_deref().unwrap_or_default();
+            let password = proxy.password.as_deref().unwrap_or_default();
+            if username.len() > 255 || password.len() > 255 {
+                return Err(anyhow::anyhow!("Proxy username and password must be at most 255 bytes"));
+            }
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(anyhow::anyhow!("Proxy {} rejected the username or password", proxy.address));
+            }
+        }
+        AUTH_UNACCEPTABLE if method == AUTH_NONE => {
+            return Err(anyhow::anyhow!("Proxy {} requires a username and password", proxy.address));
+        }
+        _ => return Err(anyhow::anyhow!("Proxy {} offered no usable authentication method", proxy.address)),
+    }
+
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(anyhow::anyhow!("Host name {} is too long for a SOCKS5 proxy", host));
+            }
+            request.push(ADDRESS_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // Version, reply code, reserved, then the address the proxy bound, which
+    // isn't needed
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0 {
+        return Err(anyhow::anyhow!(
+            "Proxy {} could not connect to {}:{}: {}",
+            proxy.address, host, port, describe_reply(header[1])
+        ));
+    }
+    let address_len = match header[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => stream.read_u8().await? as usize,
+        other => return Err(anyhow::anyhow!("Proxy {} replied with unknown address type {}", proxy.address, other)),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+/// Listen on a local port and pass each connection made to it on to
+/// host:port through the proxy, with TLS if given. For clients that can only
+/// connect directly, which are pointed at the returned address instead.
+pub async fn forward(proxy: ProxyConfig, host: String, port: u16, tls: Option<ForwardTls>) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let local_address = listener.local_addr()?;
+    info!("Forwarding {} to {}:{} through proxy {}", local_address, host, port, proxy.address);
+
+    let tls = tls.map(Arc::new);
+    tokio::spawn(async move {
+        loop {
+            let (client, _) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("Failed to accept a connection to forward: {}", e);
+                    continue;
+                }
+            };
+            let proxy = proxy.clone();
+            let host = host.clone();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                if let Err(e) = forward_connection(client, &proxy, &host, port, tls.as_deref()).await {
+                    debug!("Forwarded connection to {}:{} ended: {}", host, port, e);
+                }
+            });
+        }
+    });
+
+    Ok(local_address)
+}
+
+async fn forward_connection(
+    mut client: TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+    tls: Option<&ForwardTls>,
+) -> Result<()> {
+    let server = connect(proxy, host, port).await?;
+    match tls {
+        None => {
+            let mut server = server;
+            tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+        }
+        Some(ForwardTls::Rustls(config)) => {
+            let name = ServerName::try_from(host.to_string())
+                .map_err(|e| anyhow::anyhow!("Invalid TLS server name {}: {}", host, e))?;
+            let mut server = tokio_rustls::TlsConnector::from(config.clone()).connect(name, server).await?;
+            tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+        }
+        Some(ForwardTls::Native(connector)) => {
+            let mut server = connector.connect(host, server).await?;
+            tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// A range test is summarized once nothing was heard from the node for this long
+pub const RANGE_TEST_IDLE: Duration = Duration::from_secs(300);
+
+/// Whether a text payload looks like a range test packet, e.g. "seq 42"
+pub fn is_range_test(text: &str) -> bool {
+    text.trim()
+        .strip_prefix("seq ")
+        .is_some_and(|seq| !seq.is_empty() && seq.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// A range test in progress from one node
+#[derive(Debug, Clone)]
+struct RangeTestRun {
+    packets: u32,
+    last_seen: Instant,
+    channel: String,  // The mesh channel's name, for the summary's template
+    irc_channel: Option<String>,  // Where the summary goes, None for the main channel
+}
+
+/// Counts range test packets per node so a finished test can be summed up
+/// in one line instead of flooding IRC
+#[derive(Debug, Default)]
+pub struct RangeTestTracker {
+    runs: HashMap<u32, RangeTestRun>,
+}
+
+/// A range test that has gone quiet
+#[derive(Debug, Clone)]
+pub struct RangeTestSummary {
+    pub node: u32,
+    pub packets: u32,
+    pub channel: String,
+    pub irc_channel: Option<String>,
+}
+
+impl RangeTestTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, node: u32, channel: &str, irc_channel: Option<String>) {
+        let run = self.runs.entry(node).or_insert_with(|| RangeTestRun {
+            packets: 0,
+            last_seen: Instant::now(),
+            channel: channel.to_string(),
+            irc_channel: irc_channel.clone(),
+        });
+        run.packets += 1;
+        run.last_seen = Instant::now();
+        run.channel = channel.to_string();
+        run.irc_channel = irc_channel;
+    }
+
+    /// Remove and return the tests nothing was heard from for `idle`
+    pub fn finished(&mut self, idle: Duration) -> Vec<RangeTestSummary> {
+        let done: Vec<u32> = self.runs.iter()
+            .filter(|(_, run)| run.last_seen.elapsed() >= idle)
+            .map(|(node, _)| *node)
+            .collect();
+        done.into_iter()
+            .filter_map(|node| self.runs.remove(&node).map(|run| RangeTestSummary {
+                node,
+                packets: run.packets,
+                channel: run.channel,
+                irc_channel: run.irc_channel,
+            }))
+            .collect()
+    }
+}
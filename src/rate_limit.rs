@@ -0,0 +1,136 @@
+use meshtastic::protobufs::config::lo_ra_config::{ModemPreset, RegionCode};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Meshtastic uses a 16 symbol preamble and an explicit LoRa header
+const PREAMBLE_SYMBOLS: f64 = 16.0;
+
+// Mesh packet header plus protobuf framing around the text payload
+const PACKET_OVERHEAD_BYTES: usize = 32;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// The legal duty cycle of a region, for regions that have one
+pub fn region_duty_cycle(region: RegionCode) -> Option<f32> {
+    match region {
+        RegionCode::Eu433 | RegionCode::Eu868 | RegionCode::Ua433 => Some(10.0),
+        RegionCode::Ua868 => Some(1.0),
+        _ => None,
+    }
+}
+
+/// Estimated time on air of a packet carrying `payload_len` bytes, using
+/// the LoRa airtime formula with the preset's spreading factor, bandwidth
+/// and coding rate
+pub fn estimate_airtime(preset: ModemPreset, payload_len: usize) -> Duration {
+    // (spreading factor, bandwidth in kHz, coding rate denominator)
+    let (sf, bandwidth, coding_rate) = match preset {
+        ModemPreset::ShortTurbo => (7, 500.0, 5),
+        ModemPreset::ShortFast => (7, 250.0, 5),
+        ModemPreset::ShortSlow => (8, 250.0, 5),
+        ModemPreset::MediumFast => (9, 250.0, 5),
+        ModemPreset::MediumSlow => (10, 250.0, 5),
+        ModemPreset::LongFast => (11, 250.0, 5),
+        ModemPreset::LongModerate => (11, 125.0, 8),
+        ModemPreset::LongSlow => (12, 125.0, 8),
+        ModemPreset::VeryLongSlow => (12, 62.5, 8),
+    };
+
+    let symbol_time = 2f64.powi(sf) / (bandwidth * 1000.0);
+    // Low data rate optimisation kicks in for long symbols
+    let low_data_rate = if symbol_time > 0.016 { 1.0 } else { 0.0 };
+    let sf = f64::from(sf);
+    let bits = 8.0 * (payload_len + PACKET_OVERHEAD_BYTES) as f64;
+    let payload_symbols = ((bits - 4.0 * sf + 28.0 + 16.0) / (4.0 * (sf - 2.0 * low_data_rate)))
+        .ceil()
+        .max(0.0) * f64::from(coding_rate);
+
+    Duration::from_secs_f64((PREAMBLE_SYMBOLS + 4.25 + 8.0 + payload_symbols) * symbol_time)
+}
+
+/// Budget for traffic sent to the mesh: a number of packets per minute and
+/// a share of airtime per hour, either of which may be unlimited
+#[derive(Debug)]
+pub struct RateLimiter {
+    messages_per_minute: Option<u32>,
+    airtime_per_hour: Option<Duration>,
+    sent: VecDeque<(Instant, Duration)>,  // Send time and airtime of packets in the last hour
+}
+
+impl RateLimiter {
+    pub fn new(messages_per_minute: Option<u32>, duty_cycle_percent: Option<f32>) -> Self {
+        Self {
+            messages_per_minute,
+            airtime_per_hour: duty_cycle_percent
+                .filter(|percent| *percent < 100.0)
+                .map(|percent| HOUR.mul_f32(percent.max(0.0) / 100.0)),
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Whether a packet with this much airtime fits in the budget right now
+    pub fn allows(&mut self, airtime: Duration) -> bool {
+        let now = Instant::now();
+        while self.sent.front().is_some_and(|(sent_at, _)| now.duration_since(*sent_at) >= HOUR) {
+            self.sent.pop_front();
+        }
+
+        if let Some(limit) = self.messages_per_minute {
+            let last_minute = self.sent.iter()
+                .filter(|(sent_at, _)| now.duration_since(*sent_at) < MINUTE)
+                .count();
+            if last_minute >= limit as usize {
+                return false;
+            }
+        }
+
+        if let Some(budget) = self.airtime_per_hour {
+            let used: Duration = self.sent.iter().map(|(_, airtime)| *airtime).sum();
+            if used + airtime > budget {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn record(&mut self, airtime: Duration) {
+        self.sent.push_back((Instant::now(), airtime));
+    }
+}
+
+/// A token bucket: up to `burst` messages at once, refilled at
+/// `per_minute` messages a minute
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    burst: f64,
+    per_second: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            tokens: burst,
+            burst,
+            per_second: f64::from(per_minute) / 60.0,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is left
+    pub fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use meshtastic::protobufs::{Data, MeshPacket, PortNum, Position, Waypoint};
+use serde_json::json;
+use std::collections::HashSet;
+
+use crate::config::{DumpFormat, MeshtasticConfig};
+
+/// Ports whose packets can be posted to IRC
+const RELAYABLE_PORTS: [PortNum; 5] = [
+    PortNum::TextMessageApp,
+    PortNum::PositionApp,
+    PortNum::TelemetryApp,
+    PortNum::WaypointApp,
+    PortNum::DetectionSensorApp,
+];
+
+/// Ports the bridge does something with, relayed or not
+const KNOWN_PORTS: [PortNum; 12] = [
+    PortNum::TextMessageApp,
+    PortNum::PositionApp,
+    PortNum::TelemetryApp,
+    PortNum::WaypointApp,
+    PortNum::DetectionSensorApp,
+    PortNum::NodeinfoApp,
+    PortNum::RoutingApp,
+    PortNum::TracerouteApp,
+    PortNum::AdminApp,
+    PortNum::NeighborinfoApp,
+    PortNum::StoreForwardApp,
+    PortNum::RangeTestApp,
+];
+
+// Payload bytes shown in a dump, which keeps a hex dump within one IRC line
+pub const DUMP_MAX_BYTES: usize = 160;
+
+/// Look up a port by its protobuf name ("POSITION_APP") or short name
+/// ("position", "text")
+fn parse_port(name: &str) -> Option<PortNum> {
+    let name = name.to_uppercase();
+    if name == "TEXT" {
+        return Some(PortNum::TextMessageApp);
+    }
+    PortNum::from_str_name(&name).or_else(|| PortNum::from_str_name(&format!("{}_APP", name)))
+}
+
+/// The ports relayed to IRC: `relay_ports` (text only by default), plus
+/// positions and detection alerts when their sections enable them
+pub fn relay_ports(settings: &MeshtasticConfig) -> Result<HashSet<PortNum>> {
+    let mut ports = HashSet::new();
+    match &settings.relay_ports {
+        Some(names) => {
+            for name in names {
+                let port = parse_port(name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown port \"{}\" in relay_ports", name))?;
+                if !RELAYABLE_PORTS.contains(&port) {
+                    return Err(anyhow::anyhow!(
+                        "{} can't be relayed to IRC, expected one of text, position, telemetry, waypoint, detection_sensor",
+                        port.as_str_name()
+                    ));
+                }
+                ports.insert(port);
+            }
+        }
+        None => {
+            ports.insert(PortNum::TextMessageApp);
+        }
+    }
+    if settings.positions.enabled {
+        ports.insert(PortNum::PositionApp);
+    }
+    if settings.detection.enabled {
+        ports.insert(PortNum::DetectionSensorApp);
+    }
+    Ok(ports)
+}
+
+/// e.g. "47.6201,-122.3490 alt 85m https://www.openstreetmap.org/...", or
+/// None for a position without a fix
+pub fn format_position(position: &Position) -> Option<String> {
+    let (latitude_i, longitude_i) = (position.latitude_i?, position.longitude_i?);
+    if latitude_i == 0 && longitude_i == 0 {
+        return None;
+    }
+    let altitude = position.altitude
+        .map(|altitude| format!(" alt {}m", altitude))
+        .unwrap_or_default();
+    Some(format!("{}{} {}", format_coordinates(latitude_i, longitude_i), altitude, map_link(latitude_i, longitude_i)))
+}
+
+/// e.g. "Camp: by the lake 47.6201,-122.3490 https://www.openstreetmap.org/..."
+pub fn format_waypoint(waypoint: &Waypoint) -> String {
+    let mut line = if waypoint.name.is_empty() { format!("#{}", waypoint.id) } else { waypoint.name.clone() };
+    if !waypoint.description.is_empty() {
+        line.push_str(": ");
+        line.push_str(&waypoint.description);
+    }
+    if let (Some(latitude_i), Some(longitude_i)) = (waypoint.latitude_i, waypoint.longitude_i) {
+        line.push_str(&format!(" {} {}", format_coordinates(latitude_i, longitude_i), map_link(latitude_i, longitude_i)));
+    }
+    line
+}
+
+fn format_coordinates(latitude_i: i32, longitude_i: i32) -> String {
+    format!("{:.4},{:.4}", latitude_i as f64 * 1e-7, longitude_i as f64 * 1e-7)
+}
+
+fn map_link(latitude_i: i32, longitude_i: i32) -> String {
+    let (latitude, longitude) = (latitude_i as f64 * 1e-7, longitude_i as f64 * 1e-7);
+    format!(
+        "https://www.openstreetmap.org/?mlat={:.5}&mlon={:.5}#map=15/{:.5}/{:.5}",
+        latitude, longitude, latitude, longitude
+    )
+}
+
+/// Whether the bridge has no handling for packets on this port number
+pub fn is_unknown_port(portnum: i32) -> bool {
+    PortNum::try_from(portnum).map_or(true, |port| !KNOWN_PORTS.contains(&port))
+}
+
+/// A compact dump of a packet, e.g. "port 256 (PRIVATE_APP) to ffffffff ch 0: 0a03..."
+/// in hex, or the same fields as a JSON object. Payloads longer than
+/// `max_bytes` are cut short.
+pub fn dump_packet(packet: &MeshPacket, data: &Data, format: DumpFormat, max_bytes: Option<usize>) -> String {
+    let port = match PortNum::try_from(data.portnum) {
+        Ok(port) => format!("{} ({})", data.portnum, port.as_str_name()),
+        Err(_) => data.portnum.to_string(),
+    };
+    let shown = max_bytes.map_or(data.payload.len(), |max| max.min(data.payload.len()));
+    let mut payload: String = data.payload[..shown].iter().map(|byte| format!("{:02x}", byte)).collect();
+    let truncated = shown < data.payload.len();
+    match format {
+        DumpFormat::Hex => {
+            if truncated {
+                payload.push_str(&format!("... ({} bytes)", data.payload.len()));
+            }
+            format!("port {} to {:08x} ch {}: {}", port, packet.to, packet.channel, payload)
+        }
+        DumpFormat::Json => json!({
+            "from": format!("!{:08x}", packet.from),
+            "to": format!("!{:08x}", packet.to),
+            "channel": packet.channel,
+            "id": packet.id,
+            "portnum": data.portnum,
+            "port": PortNum::try_from(data.portnum).ok().map(|port| port.as_str_name()),
+            "payload": payload,
+            "length": data.payload.len(),
+            "truncated": truncated,
+        }).to_string(),
+    }
+}
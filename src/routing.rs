@@ -0,0 +1,90 @@
+use crate::config::RouteDirection;
+
+/// A mesh channel being relayed, with the IRC channel it maps to (None for
+/// the main IRC channel)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgedChannel {
+    pub index: u32,
+    pub name: String,
+    pub irc_channel: Option<String>,
+    pub direction: RouteDirection,
+}
+
+/// Where messages go between a radio's mesh channels and the IRC channels,
+/// built from its resolved routes. The first route is the radio's primary
+/// channel, which goes both ways with the main IRC channel.
+#[derive(Debug, Clone)]
+pub struct Router {
+    routes: Vec<BridgedChannel>,
+}
+
+impl Router {
+    pub fn new(routes: Vec<BridgedChannel>) -> Self {
+        Self { routes }
+    }
+
+    /// The channel direct messages, traceroutes and other requests go out on
+    pub fn primary(&self) -> &BridgedChannel {
+        &self.routes[0]
+    }
+
+    /// The route a packet heard on a mesh channel is relayed to IRC by, if
+    /// any route carries that channel to IRC
+    pub fn relaying(&self, index: u32) -> Option<&BridgedChannel> {
+        self.routes.iter().find(|route| route.index == index && route.direction.to_irc())
+    }
+
+    /// Every IRC channel a packet relayed by `bridged` is posted to. Channels
+    /// that aren't routed here, e.g. ones with a configured key, go to their
+    /// own IRC channel only.
+    pub fn irc_channels(&self, bridged: &BridgedChannel) -> Vec<Option<String>> {
+        if !self.routes.contains(bridged) {
+            return vec![bridged.irc_channel.clone()];
+        }
+        let mut irc_channels = Vec::new();
+        for route in self.routes.iter().filter(|route| route.index == bridged.index && route.direction.to_irc()) {
+            if !irc_channels.contains(&route.irc_channel) {
+                irc_channels.push(route.irc_channel.clone());
+            }
+        }
+        irc_channels
+    }
+
+    /// The mesh channels a message said in an IRC channel is sent on
+    pub fn to_mesh(&self, irc_channel: &Option<String>) -> Vec<u32> {
+        let mut indexes = Vec::new();
+        for route in self.routes.iter().filter(|route| &route.irc_channel == irc_channel && route.direction.to_mesh()) {
+            if !indexes.contains(&route.index) {
+                indexes.push(route.index);
+            }
+        }
+        indexes
+    }
+
+    /// Whether messages from IRC may be sent on a mesh channel, when asked for
+    /// by index
+    pub fn accepts_from_irc(&self, index: u32) -> bool {
+        self.routes.iter().any(|route| route.index == index && route.direction.to_mesh())
+    }
+
+    /// Whether more than one mesh channel is bridged, so lines on IRC need
+    /// tagging with theirs
+    pub fn is_multi_channel(&self) -> bool {
+        self.routes.iter().any(|route| route.index != self.routes[0].index)
+    }
+
+    /// e.g. "0 (LongFast) <-> main, 1 (admin) -> #mesh-admin"
+    pub fn describe(&self) -> String {
+        self.routes.iter()
+            .map(|route| {
+                let arrow = match route.direction {
+                    RouteDirection::Both => "<->",
+                    RouteDirection::MeshToIrc => "->",
+                    RouteDirection::IrcToMesh => "<-",
+                };
+                format!("{} ({}) {} {}", route.index, route.name, arrow, route.irc_channel.as_deref().unwrap_or("main"))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::{Datelike, NaiveTime, Timelike, Weekday};
+
+use crate::config::{MeshtasticConfig, QuietHoursConfig};
+use crate::template::Timezone;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+
+/// One quiet period, in minutes since midnight. A period belongs to the day
+/// it starts on, so Friday 22:00-06:00 runs into Saturday morning.
+#[derive(Debug, Clone)]
+struct Period {
+    days: Vec<Weekday>,  // Every day if empty
+    from: u32,
+    length: u32,
+}
+
+impl Period {
+    fn parse(index: usize, config: &QuietHoursConfig) -> Result<Self> {
+        let setting = format!("schedule.quiet_hours[{}]", index);
+        let days = config.days.iter()
+            .map(|day| day.parse::<Weekday>()
+                .map_err(|_| anyhow::anyhow!("{}.days has \"{}\", expected a day like \"mon\" or \"sunday\"", setting, day)))
+            .collect::<Result<Vec<_>>>()?;
+        let from = minute_of_day(&setting, "from", &config.from)?;
+        let to = minute_of_day(&setting, "to", &config.to)?;
+        // Equal times make a whole day
+        let length = match (to + MINUTES_PER_DAY - from) % MINUTES_PER_DAY {
+            0 => MINUTES_PER_DAY,
+            length => length,
+        };
+        Ok(Self { days, from, length })
+    }
+
+    /// Whether `now`, in minutes since Monday midnight, falls in the period
+    fn contains(&self, now: u32) -> bool {
+        let days: Vec<u32> = if self.days.is_empty() {
+            (0..7).collect()
+        } else {
+            self.days.iter().map(|day| day.num_days_from_monday()).collect()
+        };
+        days.into_iter().any(|day| {
+            let start = day * MINUTES_PER_DAY + self.from;
+            (now + MINUTES_PER_WEEK - start) % MINUTES_PER_WEEK < self.length
+        })
+    }
+
+    /// When the period ends, as "HH:MM"
+    fn end(&self) -> String {
+        let end = (self.from + self.length) % MINUTES_PER_DAY;
+        format!("{:02}:{:02}", end / 60, end % 60)
+    }
+}
+
+fn minute_of_day(setting: &str, field: &str, time: &str) -> Result<u32> {
+    let time = NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| anyhow::anyhow!("{}.{} is \"{}\", expected a time like \"22:30\"", setting, field, time))?;
+    Ok(time.hour() * 60 + time.minute())
+}
+
+/// The configured quiet hours, during which messages from IRC are held back
+/// from the mesh. They are kept in the timezone set for templates.
+#[derive(Debug, Clone, Default)]
+pub struct QuietHours {
+    periods: Vec<Period>,
+    timezone: Timezone,
+}
+
+impl TryFrom<&MeshtasticConfig> for QuietHours {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &MeshtasticConfig) -> Result<Self> {
+        Ok(Self {
+            periods: config.schedule.quiet_hours.iter().enumerate()
+                .map(|(index, period)| Period::parse(index, period))
+                .collect::<Result<_>>()?,
+            timezone: Timezone::try_from(&config.templates)?,
+        })
+    }
+}
+
+impl QuietHours {
+    /// When the current quiet period ends, e.g. "06:00", or None outside
+    /// quiet hours
+    pub fn quiet_until(&self) -> Option<String> {
+        if self.periods.is_empty() {
+            return None;
+        }
+        let now = self.timezone.now();
+        let now = now.weekday().num_days_from_monday() * MINUTES_PER_DAY + now.hour() * 60 + now.minute();
+        self.periods.iter().find(|period| period.contains(now)).map(Period::end)
+    }
+}
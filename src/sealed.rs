@@ -0,0 +1,143 @@
+use aes::Aes256;
+use anyhow::Result;
+use base64::Engine;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{BufRead, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+// First line of an encrypted config file; the rest is base64
+const HEADER: &str = "meshtastic-irc encrypted config";
+
+// Environment variable the passphrase is read from, when not asked for
+pub const PASSPHRASE_VAR: &str = "MESHIRC_CONFIG_PASSPHRASE";
+
+// Format version, then the scrypt cost (log2 N), salt and CTR nonce sizes
+const VERSION: u8 = 1;
+const SCRYPT_LOG_N: u8 = 15;
+const MAX_SCRYPT_LOG_N: u8 = 20;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+// The passphrase that opened the config, so a reload doesn't ask again
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether a config file's text is encrypted
+pub fn is_sealed(text: &str) -> bool {
+    text.starts_with(HEADER)
+}
+
+/// Encrypt a config file's text. The passphrase is stretched with scrypt
+/// into an AES-256-CTR key and an HMAC-SHA256 key, which authenticates the
+/// ciphertext so a wrong passphrase or damaged file is caught.
+pub fn seal(plaintext: &str, passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| anyhow::anyhow!("No randomness for the salt: {}", e))?;
+    getrandom::getrandom(&mut nonce).map_err(|e| anyhow::anyhow!("No randomness for the nonce: {}", e))?;
+    let (cipher_key, mac_key) = derive_keys(passphrase, SCRYPT_LOG_N, &salt)?;
+
+    let mut sealed = vec![VERSION, SCRYPT_LOG_N];
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+    let mut ciphertext = plaintext.as_bytes().to_vec();
+    ctr::Ctr128BE::<Aes256>::new(&cipher_key.into(), &nonce.into()).apply_keystream(&mut ciphertext);
+    sealed.extend_from_slice(&ciphertext);
+    let mut mac = <Hmac<Sha256>>::new_from_slice(&mac_key)?;
+    mac.update(&sealed);
+    sealed.extend_from_slice(&mac.finalize().into_bytes());
+
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&sealed);
+    let mut text = format!("{}\n", HEADER);
+    for line in encoded.as_bytes().chunks(64) {
+        text.push_str(std::str::from_utf8(line)?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Decrypt what `seal` wrote
+pub fn open(text: &str, passphrase: &str) -> Result<String> {
+    let encoded: String = text[HEADER.len()..].split_whitespace().collect();
+    let sealed = base64::engine::general_purpose::STANDARD.decode(encoded)
+        .map_err(|e| anyhow::anyhow!("the encrypted config is damaged: {}", e))?;
+    if sealed.len() < 2 + SALT_LEN + NONCE_LEN + MAC_LEN {
+        return Err(anyhow::anyhow!("the encrypted config is truncated"));
+    }
+    if sealed[0] != VERSION || sealed[1] > MAX_SCRYPT_LOG_N {
+        return Err(anyhow::anyhow!("the config was encrypted by a newer version of the bridge"));
+    }
+    let (body, tag) = sealed.split_at(sealed.len() - MAC_LEN);
+    let salt = &body[2..2 + SALT_LEN];
+    let nonce: [u8; NONCE_LEN] = body[2 + SALT_LEN..2 + SALT_LEN + NONCE_LEN].try_into()?;
+    let (cipher_key, mac_key) = derive_keys(passphrase, body[1], salt)?;
+
+    let mut mac = <Hmac<Sha256>>::new_from_slice(&mac_key)?;
+    mac.update(body);
+    mac.verify_slice(tag).map_err(|_| anyhow::anyhow!("wrong passphrase, or the file is damaged"))?;
+    let mut plaintext = body[2 + SALT_LEN + NONCE_LEN..].to_vec();
+    ctr::Ctr128BE::<Aes256>::new(&cipher_key.into(), &nonce.into()).apply_keystream(&mut plaintext);
+    Ok(String::from_utf8(plaintext)?)
+}
+
+fn derive_keys(passphrase: &str, log_n: u8, salt: &[u8]) -> Result<([u8; 32], [u8; 32])> {
+    let params = scrypt::Params::new(log_n, 8, 1, 64)
+        .map_err(|e| anyhow::anyhow!("bad scrypt parameters: {}", e))?;
+    let mut keys = [0u8; 64];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut keys)
+        .map_err(|e| anyhow::anyhow!("scrypt failed: {}", e))?;
+    let (cipher_key, mac_key) = keys.split_at(32);
+    Ok((cipher_key.try_into()?, mac_key.try_into()?))
+}
+
+/// Decrypt an encrypted config file's text, with the passphrase from the
+/// environment, the one used before, or asked for on the terminal
+pub fn open_config(text: &str) -> Result<String> {
+    let remembered = PASSPHRASE.lock().unwrap().clone();
+    let passphrase = match remembered {
+        Some(passphrase) => passphrase,
+        None => passphrase(false)?,
+    };
+    let plaintext = open(text, &passphrase)?;
+    *PASSPHRASE.lock().unwrap() = Some(passphrase);
+    Ok(plaintext)
+}
+
+/// The passphrase from the environment, or else asked for on the terminal
+/// (twice with `confirm`, when encrypting)
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = std::env::var(PASSPHRASE_VAR).ok().filter(|passphrase| !passphrase.is_empty()) {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!("no passphrase; set {} or run from a terminal to be asked for it", PASSPHRASE_VAR));
+    }
+    let passphrase = ask("Config passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("the passphrase can't be empty"));
+    }
+    if confirm && ask("Again: ")? != passphrase {
+        return Err(anyhow::anyhow!("the passphrases don't match"));
+    }
+    Ok(passphrase)
+}
+
+/// Read a line from the terminal without echoing it. Where `stty` isn't
+/// available the line is echoed.
+fn ask(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let stty = |setting: &str| Command::new("stty").arg(setting).stdin(Stdio::inherit()).status();
+    let echo_off = stty("-echo").is_ok_and(|status| status.success());
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line);
+    if echo_off {
+        let _ = stty("echo");
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -56,15 +56,15 @@ pub async fn detect_meshtastic_port() -> Result<PathBuf> {
         }
         
         // Check if this is a known Meshtastic device
-        if is_likely_meshtastic(&port_info) {
+        if is_likely_meshtastic(port_info) {
             info!("Found likely Meshtastic device: {} - {}", 
                   port_name, 
-                  get_port_description(&port_info));
+                  get_port_description(port_info));
             meshtastic_ports.push(port_name.clone());
-        } else if is_possible_meshtastic(&port_info) {
+        } else if is_possible_meshtastic(port_info) {
             info!("Found possible Meshtastic device: {} - {}", 
                   port_name,
-                  get_port_description(&port_info));
+                  get_port_description(port_info));
             possible_ports.push(port_name.clone());
         }
     }
@@ -88,7 +88,7 @@ pub async fn detect_meshtastic_port() -> Result<PathBuf> {
     // If nothing worked, show what we found
     info!("No Meshtastic devices detected. Available ports:");
     for port_info in &ports {
-        info!("  {} - {}", port_info.port_name, get_port_description(&port_info));
+        info!("  {} - {}", port_info.port_name, get_port_description(port_info));
     }
     
     Err(anyhow::anyhow!(
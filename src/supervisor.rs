@@ -0,0 +1,177 @@
+use anyhow::Result;
+use log::{debug, error, info};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+use crate::backlog::{Backlog, Delayable};
+use crate::backoff::Backoff;
+use crate::config::{BufferConfig, ReconnectConfig};
+use crate::endpoint::{serve, ChatEndpoint};
+use crate::irc_handler::{IrcMessage, MeshMessage};
+use crate::metrics::Metrics;
+
+// Restarts allowed within the window before a leg is given up on
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const DEFAULT_RESTART_WINDOW_SECS: u64 = 600;
+
+// Room in each handler's input between restarts
+const HANDOFF_CAPACITY: usize = 100;
+
+/// Feeds a handler's input channel across restarts. Each start of the
+/// handler reads from a new receiver, and what arrives while it is down is
+/// dropped rather than holding up the other handlers.
+pub struct Handoff<T> {
+    current: watch::Sender<Option<mpsc::Sender<T>>>,
+}
+
+impl<T: Send + 'static> Handoff<T> {
+    pub fn new(mut input: mpsc::Receiver<T>) -> Self {
+        let (current, mut handler) = watch::channel(None::<mpsc::Sender<T>>);
+        tokio::spawn(async move {
+            while let Some(item) = input.recv().await {
+                let sender = handler.borrow_and_update().clone();
+                if let Some(sender) = sender {
+                    if sender.send(item).await.is_err() {
+                        debug!("Dropping a message for a handler that is down");
+                    }
+                }
+            }
+        });
+        Self { current }
+    }
+
+    /// Like `new`, but what arrives while the handler is down is held in a
+    /// backlog for `leg` and replayed to its next start
+    pub fn buffered(mut input: mpsc::Receiver<T>, config: &BufferConfig, leg: &str) -> Result<Self>
+    where
+        T: Delayable,
+    {
+        let (current, mut handler) = watch::channel(None::<mpsc::Sender<T>>);
+        // Filed apart from what the handler itself holds while reconnecting,
+        // which may be in the same file
+        let mut backlog = Backlog::new(config, &format!("{} restarting", leg))?;
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    item = input.recv() => {
+                        let Some(item) = item else {
+                            break;
+                        };
+                        let sender = handler.borrow().clone();
+                        match sender {
+                            Some(sender) => {
+                                if let Err(mpsc::error::SendError(item)) = sender.send(item).await {
+                                    backlog.push(item);
+                                }
+                            }
+                            None => backlog.push(item),
+                        }
+                    }
+                    Ok(()) = handler.changed() => {
+                        let sender = handler.borrow_and_update().clone();
+                        if let Some(sender) = sender {
+                            for item in backlog.drain() {
+                                if sender.send(item).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Self { current })
+    }
+
+    /// The receiver for the next start of the handler
+    pub fn receiver(&self) -> mpsc::Receiver<T> {
+        let (sender, receiver) = mpsc::channel(HANDOFF_CAPACITY);
+        self.current.send_replace(Some(sender));
+        receiver
+    }
+}
+
+/// A message a leg's outages are announced with. It goes where the leg's own
+/// messages go: on IRC for the radios and MQTT brokers, on the mesh for IRC.
+pub trait Notice {
+    fn notice(text: String) -> Self;
+}
+
+impl Notice for MeshMessage {
+    fn notice(text: String) -> Self {
+        MeshMessage::new(text)
+    }
+}
+
+impl Notice for IrcMessage {
+    fn notice(text: String) -> Self {
+        IrcMessage {
+            sender: "bridge".to_string(),
+            content: text,
+            channel: None,
+            destination: None,
+            mesh_channel: None,
+            command: None,
+        }
+    }
+}
+
+async fn announce<T: Notice>(output: &mpsc::Sender<T>, text: String) {
+    if output.send(T::notice(text)).await.is_err() {
+        debug!("Nowhere to announce the outage");
+    }
+}
+
+/// Run one leg of the bridge, connecting its endpoint again after the
+/// backoff whenever it fails, while the other legs keep running. Each
+/// connection reads what `input` hands it and passes what it hears to
+/// `output`, which outages are announced on too. Returns once the leg stops cleanly, or has failed more than
+/// max_restarts times within restart_window_secs.
+pub async fn supervise<E>(
+    endpoint: &mut E,
+    config: &ReconnectConfig,
+    input: &Handoff<E::Incoming>,
+    output: &mpsc::Sender<E::Outgoing>,
+    metrics: &Metrics,
+)
+where
+    E: ChatEndpoint + ?Sized,
+    E::Incoming: 'static,
+    E::Outgoing: Notice,
+{
+    let leg = endpoint.name();
+    let max_restarts = config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS) as usize;
+    let window = Duration::from_secs(config.restart_window_secs.unwrap_or(DEFAULT_RESTART_WINDOW_SECS));
+    // max_attempts limits connection attempts; restarts are limited by max_restarts
+    let mut backoff = Backoff::new(&ReconnectConfig { max_attempts: None, ..config.clone() });
+    let mut restarts: VecDeque<Instant> = VecDeque::new();
+    loop {
+        let started = Instant::now();
+        let result = serve(endpoint, input.receiver(), output, metrics).await;
+        metrics.record_endpoint(&leg, endpoint.health());
+        let e = match result {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        error!("{} failed: {}", leg, e);
+        // A leg that stayed up for a whole window starts over from the initial delay
+        if started.elapsed() >= window {
+            backoff.reset();
+        }
+        let now = Instant::now();
+        while restarts.front().is_some_and(|restart| now.duration_since(*restart) >= window) {
+            restarts.pop_front();
+        }
+        let delay = if restarts.len() < max_restarts { backoff.next_delay() } else { None };
+        let Some(delay) = delay else {
+            error!("{} failed {} times, giving up on it", leg, restarts.len() + 1);
+            announce(output, format!("{} keeps failing and has been given up on: {}", leg, e)).await;
+            return;
+        };
+        restarts.push_back(now);
+        announce(output, format!("{} is down ({}), restarting in {}s", leg, e, delay.as_secs())).await;
+        tokio::time::sleep(delay).await;
+        info!("Restarting {}", leg);
+    }
+}
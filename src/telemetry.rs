@@ -0,0 +1,211 @@
+use meshtastic::protobufs::{telemetry, DeviceMetrics, EnvironmentMetrics, Telemetry};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::config::TelemetryConfig;
+use crate::metrics::format_duration;
+
+// Defaults for the significant-event checks
+const DEFAULT_BATTERY_THRESHOLD: u32 = 20;
+const DEFAULT_BATTERY_HYSTERESIS: u32 = 5;
+const DEFAULT_VOLTAGE_DROP: f32 = 0.3;
+
+// Devices report a battery level above 100 when running on external power
+const EXTERNAL_POWER_LEVEL: u32 = 100;
+
+/// Latest telemetry reported by a node
+#[derive(Debug, Clone, Default)]
+pub struct NodeTelemetry {
+    pub device: Option<DeviceMetrics>,
+    pub environment: Option<EnvironmentMetrics>,
+    pub updated_at: Option<Instant>,
+    pub environment_at: Option<Instant>,  // When the environment readings were reported
+}
+
+impl NodeTelemetry {
+    /// The readings of a single report, without an age
+    pub fn from_report(telemetry: &Telemetry) -> Self {
+        match &telemetry.variant {
+            Some(telemetry::Variant::DeviceMetrics(metrics)) => Self { device: Some(*metrics), ..Default::default() },
+            Some(telemetry::Variant::EnvironmentMetrics(metrics)) => Self { environment: Some(*metrics), ..Default::default() },
+            _ => Self::default(),
+        }
+    }
+
+    /// Whether there are any device or environment readings
+    pub fn is_empty(&self) -> bool {
+        self.device.is_none() && self.environment.is_none()
+    }
+
+    /// One-line summary, e.g. "battery 85% 4.05V, ch util 12.3% | 21.5C 45% 1013hPa (5m ago)"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let Some(device) = &self.device {
+            let mut fields = Vec::new();
+            match device.battery_level {
+                Some(level) if level > EXTERNAL_POWER_LEVEL => fields.push("powered".to_string()),
+                Some(level) => fields.push(format!("battery {}%", level)),
+                None => {}
+            }
+            if let Some(voltage) = device.voltage {
+                fields.push(format!("{:.2}V", voltage));
+            }
+            if let Some(utilization) = device.channel_utilization {
+                fields.push(format!("ch util {:.1}%", utilization));
+            }
+            if let Some(air_util_tx) = device.air_util_tx {
+                fields.push(format!("air tx {:.1}%", air_util_tx));
+            }
+            if let Some(uptime) = device.uptime_seconds {
+                fields.push(format!("up {}", format_duration(Duration::from_secs(uptime.into()))));
+            }
+            parts.push(fields.join(", "));
+        }
+
+        if let Some(environment) = &self.environment {
+            let mut fields = Vec::new();
+            if let Some(temperature) = environment.temperature {
+                fields.push(format!("{:.1}C", temperature));
+            }
+            if let Some(humidity) = environment.relative_humidity {
+                fields.push(format!("{:.0}%", humidity));
+            }
+            if let Some(pressure) = environment.barometric_pressure {
+                fields.push(format!("{:.0}hPa", pressure));
+            }
+            parts.push(fields.join(" "));
+        }
+
+        let age = self.updated_at
+            .map(|t| format!(" ({} ago)", format_duration(t.elapsed())))
+            .unwrap_or_default();
+        format!("{}{}", parts.join(" | "), age)
+    }
+
+    /// Every environment reading, e.g. "temperature 21.5C, humidity 45%,
+    /// pressure 1013hPa, wind 3.2m/s from 270 (5m ago)", or None if the node
+    /// hasn't reported any
+    pub fn describe_environment(&self) -> Option<String> {
+        let environment = self.environment.as_ref()?;
+        let mut fields = Vec::new();
+        if let Some(temperature) = environment.temperature {
+            fields.push(format!("temperature {:.1}C", temperature));
+        }
+        if let Some(humidity) = environment.relative_humidity {
+            fields.push(format!("humidity {:.0}%", humidity));
+        }
+        if let Some(pressure) = environment.barometric_pressure {
+            fields.push(format!("pressure {:.0}hPa", pressure));
+        }
+        if let Some(gas) = environment.gas_resistance {
+            fields.push(format!("gas {:.2}MOhm", gas));
+        }
+        if let Some(iaq) = environment.iaq {
+            fields.push(format!("IAQ {}", iaq));
+        }
+        if let Some(lux) = environment.lux {
+            fields.push(format!("{:.0} lux", lux));
+        }
+        if let Some(speed) = environment.wind_speed {
+            let direction = environment.wind_direction
+                .map(|direction| format!(" from {}", direction))
+                .unwrap_or_default();
+            fields.push(format!("wind {:.1}m/s{}", speed, direction));
+        }
+        if let Some(rainfall) = environment.rainfall_1h {
+            fields.push(format!("rain {:.1}mm/h", rainfall));
+        }
+        if fields.is_empty() {
+            return None;
+        }
+
+        let age = self.environment_at
+            .map(|t| format!(" ({} ago)", format_duration(t.elapsed())))
+            .unwrap_or_default();
+        Some(format!("{}{}", fields.join(", "), age))
+    }
+}
+
+/// Something worth telling IRC about
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryEvent {
+    BatteryLow { level: u32 },
+    VoltageDrop { from: f32, to: f32 },
+}
+
+impl TelemetryEvent {
+    pub fn describe(&self) -> String {
+        match self {
+            TelemetryEvent::BatteryLow { level } => format!("battery low: {}%", level),
+            TelemetryEvent::VoltageDrop { from, to } => {
+                format!("voltage dropped from {:.2}V to {:.2}V", from, to)
+            }
+        }
+    }
+}
+
+/// Keeps the latest telemetry per node and detects significant changes
+#[derive(Debug)]
+pub struct TelemetryStore {
+    nodes: HashMap<u32, NodeTelemetry>,
+    battery_threshold: u32,
+    battery_hysteresis: u32,
+    voltage_drop: f32,
+    battery_alerted: HashSet<u32>,  // Nodes whose low battery was announced and hasn't recovered
+}
+
+impl TelemetryStore {
+    pub fn new(config: &TelemetryConfig) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            battery_threshold: config.battery_threshold.unwrap_or(DEFAULT_BATTERY_THRESHOLD),
+            battery_hysteresis: config.battery_hysteresis.unwrap_or(DEFAULT_BATTERY_HYSTERESIS),
+            voltage_drop: config.voltage_drop.unwrap_or(DEFAULT_VOLTAGE_DROP),
+            battery_alerted: HashSet::new(),
+        }
+    }
+
+    pub fn get(&self, node: u32) -> Option<&NodeTelemetry> {
+        self.nodes.get(&node)
+    }
+
+    /// Record a telemetry packet, returning any significant events it reveals
+    pub fn update(&mut self, node: u32, telemetry: &Telemetry) -> Vec<TelemetryEvent> {
+        let entry = self.nodes.entry(node).or_default();
+        entry.updated_at = Some(Instant::now());
+
+        let mut events = Vec::new();
+        match &telemetry.variant {
+            Some(telemetry::Variant::DeviceMetrics(metrics)) => {
+                let previous = entry.device.replace(*metrics);
+
+                if let Some(level) = metrics.battery_level {
+                    // Announce once per discharge. Solar nodes hover around the
+                    // threshold, so only re-arm once the battery has clearly recovered.
+                    if level < self.battery_threshold {
+                        if self.battery_alerted.insert(node) {
+                            events.push(TelemetryEvent::BatteryLow { level });
+                        }
+                    } else if level >= self.battery_threshold + self.battery_hysteresis {
+                        self.battery_alerted.remove(&node);
+                    }
+                }
+
+                let on_external_power = metrics.battery_level.is_some_and(|l| l > EXTERNAL_POWER_LEVEL);
+                if let (Some(from), Some(to)) = (previous.and_then(|p| p.voltage), metrics.voltage) {
+                    if !on_external_power && from - to >= self.voltage_drop {
+                        events.push(TelemetryEvent::VoltageDrop { from, to });
+                    }
+                }
+            }
+            Some(telemetry::Variant::EnvironmentMetrics(metrics)) => {
+                entry.environment = Some(*metrics);
+                entry.environment_at = entry.updated_at;
+            }
+            _ => {}
+        }
+
+        events
+    }
+}
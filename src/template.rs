@@ -0,0 +1,163 @@
+use anyhow::Result;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+
+use crate::config::TemplateConfig;
+
+const DEFAULT_MESH_TO_IRC: &str = "[mesh-{sender}]: {text}";
+const DEFAULT_IRC_TO_MESH: &str = "[IRC-{sender}] {text}";
+
+const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+const PLACEHOLDERS: [&str; 5] = ["sender", "channel", "snr", "time", "text"];
+
+/// The values a template's placeholders are filled with
+pub struct TemplateValues<'a> {
+    pub sender: &'a str,
+    pub channel: &'a str,  // The mesh channel's name, or its index without one
+    pub snr: Option<f32>,  // As heard by the receiving radio or gateway, None for messages from IRC
+}
+
+/// How bridged text messages are laid out, shared by the radio and MQTT
+/// handlers so both look the same on IRC and on the mesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct Templates {
+    mesh_to_irc: String,
+    irc_to_mesh: String,
+    timezone: Timezone,
+    time_format: String,
+}
+
+/// The timezone times are shown and quiet hours kept in
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Timezone {
+    #[default]
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl TryFrom<&TemplateConfig> for Timezone {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &TemplateConfig) -> Result<Self> {
+        Self::parse(config.timezone.as_deref().unwrap_or("UTC"))
+    }
+}
+
+impl Timezone {
+    /// "UTC", "local", or a fixed offset from UTC such as "+02:00"
+    fn parse(timezone: &str) -> Result<Self> {
+        if timezone.eq_ignore_ascii_case("utc") {
+            Ok(Self::Utc)
+        } else if timezone.eq_ignore_ascii_case("local") {
+            Ok(Self::Local)
+        } else {
+            timezone.parse().map(Self::Fixed).map_err(|_| anyhow::anyhow!(
+                "templates.timezone \"{}\" is not \"UTC\", \"local\" or an offset like \"+02:00\"", timezone
+            ))
+        }
+    }
+
+    /// The current time in this timezone
+    pub fn now(self) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Utc => Utc::now().fixed_offset(),
+            Timezone::Local => Local::now().fixed_offset(),
+            Timezone::Fixed(offset) => Utc::now().with_timezone(&offset),
+        }
+    }
+}
+
+impl TryFrom<&TemplateConfig> for Templates {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &TemplateConfig) -> Result<Self> {
+        let mesh_to_irc = config.mesh_to_irc.clone().unwrap_or_else(|| DEFAULT_MESH_TO_IRC.to_string());
+        let irc_to_mesh = config.irc_to_mesh.clone().unwrap_or_else(|| DEFAULT_IRC_TO_MESH.to_string());
+        check("templates.mesh_to_irc", &mesh_to_irc)?;
+        check("templates.irc_to_mesh", &irc_to_mesh)?;
+        // Long messages are split after the prefix, so nothing may follow the text
+        if !irc_to_mesh.ends_with("{text}") {
+            return Err(anyhow::anyhow!("templates.irc_to_mesh must end with {{text}}"));
+        }
+        let timezone = Timezone::try_from(config)?;
+        let time_format = config.time_format.clone().unwrap_or_else(|| DEFAULT_TIME_FORMAT.to_string());
+        if time_format.is_empty() || StrftimeItems::new(&time_format).any(|item| matches!(item, Item::Error)) {
+            return Err(anyhow::anyhow!("templates.time_format \"{}\" is not a valid strftime format", time_format));
+        }
+        Ok(Self { mesh_to_irc, irc_to_mesh, timezone, time_format })
+    }
+}
+
+impl Templates {
+    /// A text message from the mesh, as posted to IRC
+    pub fn to_irc(&self, values: &TemplateValues, text: &str) -> String {
+        self.render(&self.mesh_to_irc, values).replace("{text}", text)
+    }
+
+    /// What goes before each part of a message from IRC sent to the mesh
+    pub fn mesh_prefix(&self, values: &TemplateValues) -> String {
+        self.render(self.irc_to_mesh.trim_end_matches("{text}"), values)
+    }
+
+    /// The unrendered template for messages from IRC
+    pub fn irc_to_mesh(&self) -> &str {
+        &self.irc_to_mesh
+    }
+    
+    /// Whether mesh text looks like it was sent from IRC by a bridge using
+    /// the same template, going by the template's fixed start
+    pub fn is_from_irc(&self, text: &str) -> bool {
+        let fixed = &self.irc_to_mesh[..self.irc_to_mesh.find('{').unwrap_or(self.irc_to_mesh.len())];
+        !fixed.is_empty() && text.starts_with(fixed)
+    }
+
+    /// A time in seconds since the epoch, in the configured timezone and
+    /// format
+    pub fn timestamp(&self, secs: u32) -> String {
+        self.format_time(DateTime::from_timestamp(i64::from(secs), 0).unwrap_or_default())
+    }
+
+    fn format_time(&self, time: DateTime<Utc>) -> String {
+        match self.timezone {
+            Timezone::Utc => time.format(&self.time_format).to_string(),
+            Timezone::Local => time.with_timezone(&Local).format(&self.time_format).to_string(),
+            Timezone::Fixed(offset) => time.with_timezone(&offset).format(&self.time_format).to_string(),
+        }
+    }
+
+    /// Fill in everything but {text}, which is done last so placeholders in
+    /// the message itself are left alone
+    fn render(&self, template: &str, values: &TemplateValues) -> String {
+        let snr = values.snr.map(|snr| format!("{:.1}", snr)).unwrap_or_else(|| "?".to_string());
+        template
+            .replace("{sender}", values.sender)
+            .replace("{channel}", values.channel)
+            .replace("{snr}", &snr)
+            .replace("{time}", &self.format_time(Utc::now()))
+    }
+}
+
+/// Reject placeholders the bridge doesn't know, which would otherwise show
+/// up literally
+fn check(setting: &str, template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(anyhow::anyhow!(
+                "{} has unknown placeholder {{{}}}, expected one of {{{}}}",
+                setting, name, PLACEHOLDERS.join("}, {")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if !template.contains("{text}") {
+        return Err(anyhow::anyhow!("{} must contain {{text}}", setting));
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use meshtastic::protobufs::NeighborInfo;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use crate::metrics::format_duration;
+use crate::nodes::NodeDirectory;
+
+/// The neighbors a node last reported hearing, with the SNR it heard each at
+#[derive(Debug, Clone)]
+struct NodeNeighbors {
+    neighbors: BTreeMap<u32, f32>,
+    reported_at: Instant,
+}
+
+/// A node's entry in the JSON export
+#[derive(Debug, Serialize)]
+struct NodeExport {
+    id: String,
+    name: String,
+    reported_secs_ago: u64,
+    neighbors: Vec<NeighborExport>,
+}
+
+#[derive(Debug, Serialize)]
+struct NeighborExport {
+    id: String,
+    name: String,
+    snr: f32,
+}
+
+/// Which nodes hear which, built from NeighborInfo packets
+#[derive(Debug, Default)]
+pub struct Topology {
+    nodes: BTreeMap<u32, NodeNeighbors>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace a node's neighbors with those in its latest report
+    pub fn update(&mut self, node: u32, info: &NeighborInfo) {
+        let neighbors = info.neighbors.iter()
+            .map(|neighbor| (neighbor.node_id, neighbor.snr))
+            .collect();
+        self.nodes.insert(node, NodeNeighbors {
+            neighbors,
+            reported_at: Instant::now(),
+        });
+    }
+
+    /// One-line summary, e.g. "3 nodes reporting, 4 links"
+    pub fn summary(&self) -> String {
+        let links: usize = self.nodes.values().map(|node| node.neighbors.len()).sum();
+        format!("{} nodes reporting, {} links", self.nodes.len(), links)
+    }
+
+    /// What one node hears, e.g. "ABCD hears EFGH (6.25dB), IJKL (-3.50dB) (5m 2s ago)"
+    pub fn describe(&self, node: u32, names: &NodeDirectory) -> Option<String> {
+        let entry = self.nodes.get(&node)?;
+        let heard: Vec<String> = entry.neighbors.iter()
+            .map(|(neighbor, snr)| format!("{} ({:.2}dB)", names.display(*neighbor), snr))
+            .collect();
+        let heard = if heard.is_empty() { "no one".to_string() } else { heard.join(", ") };
+        Some(format!(
+            "{} hears {} ({} ago)",
+            names.display(node), heard, format_duration(entry.reported_at.elapsed())
+        ))
+    }
+
+    /// Every reporting node, one line each
+    pub fn describe_all(&self, names: &NodeDirectory) -> Vec<String> {
+        self.nodes.keys()
+            .filter_map(|node| self.describe(*node, names))
+            .collect()
+    }
+
+    /// The adjacency map as JSON, for mesh planning tools
+    pub fn to_json(&self, names: &NodeDirectory) -> Result<String> {
+        let nodes: Vec<NodeExport> = self.nodes.iter()
+            .map(|(node, entry)| NodeExport {
+                id: format!("!{:08x}", node),
+                name: names.display(*node),
+                reported_secs_ago: entry.reported_at.elapsed().as_secs(),
+                neighbors: entry.neighbors.iter()
+                    .map(|(neighbor, snr)| NeighborExport {
+                        id: format!("!{:08x}", neighbor),
+                        name: names.display(*neighbor),
+                        snr: *snr,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&nodes)?)
+    }
+}